@@ -0,0 +1,503 @@
+use crate::parser::{Block, Expression, Program, Statement};
+use crate::pretty_printer::PrettyPrinter;
+use std::mem;
+
+// Report where two programs differ, one line per changed node, as the path to
+// it followed by the node before and after: `statements[1].value.left: 2 + 3 -> 5`.
+// Nodes of the same kind are compared field by field, so only the innermost
+// change is reported. Statement lists are aligned on their equal statements,
+// removed and changed ones are numbered in the first program, added ones in the
+// second. Two equal programs give an empty string
+pub fn diff(before: &Program, after: &Program) -> String {
+    let mut differ = Differ::default();
+    match (before, after) {
+        (Program::Statements(before), Program::Statements(after)) => {
+            differ.statements("statements", before, after);
+        }
+    }
+    differ.changes.join("\n")
+}
+
+#[derive(Default)]
+struct Differ {
+    changes: Vec<String>,
+}
+
+impl Differ {
+    fn statements(&mut self, path: &str, before: &[Statement], after: &[Statement]) {
+        // longest common subsequence, the statements left out of it around each
+        // equal pair were changed, removed or added
+        let mut common = vec![vec![0; after.len() + 1]; before.len() + 1];
+        for i in (0..before.len()).rev() {
+            for j in (0..after.len()).rev() {
+                common[i][j] = if before[i] == after[j] {
+                    common[i + 1][j + 1] + 1
+                } else {
+                    common[i + 1][j].max(common[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        let (mut removed, mut added) = (vec![], vec![]);
+        while i < before.len() || j < after.len() {
+            if i < before.len() && j < after.len() && before[i] == after[j] {
+                self.unmatched(path, before, after, &removed, &added);
+                removed.clear();
+                added.clear();
+                i += 1;
+                j += 1;
+            } else if j == after.len() || (i < before.len() && common[i + 1][j] >= common[i][j + 1])
+            {
+                removed.push(i);
+                i += 1;
+            } else {
+                added.push(j);
+                j += 1;
+            }
+        }
+        self.unmatched(path, before, after, &removed, &added);
+    }
+
+    // a removed and an added statement of the same kind, in the same order, are
+    // taken as one changed statement
+    fn unmatched(
+        &mut self,
+        path: &str,
+        before: &[Statement],
+        after: &[Statement],
+        removed: &[usize],
+        added: &[usize],
+    ) {
+        let mut added = added.to_vec();
+        for &i in removed {
+            let same_kind = added
+                .iter()
+                .position(|&j| mem::discriminant(&before[i]) == mem::discriminant(&after[j]));
+            match same_kind {
+                Some(index) => {
+                    let j = added[index];
+                    // the ones skipped over can't be paired with a later statement
+                    self.added(path, after, &added[..index]);
+                    added.drain(..=index);
+                    self.statement(&format!("{}[{}]", path, i), &before[i], &after[j]);
+                }
+                None => self.changes.push(format!(
+                    "{}[{}]: removed {}",
+                    path,
+                    i,
+                    statement_source(&before[i])
+                )),
+            }
+        }
+        self.added(path, after, &added);
+    }
+
+    fn added(&mut self, path: &str, after: &[Statement], added: &[usize]) {
+        for &j in added {
+            self.changes.push(format!(
+                "{}[{}]: added {}",
+                path,
+                j,
+                statement_source(&after[j])
+            ));
+        }
+    }
+
+    fn statement(&mut self, path: &str, before: &Statement, after: &Statement) {
+        if before == after {
+            return;
+        }
+        match (before, after) {
+            (
+                Statement::VariableDeclaration {
+                    identifier: name,
+                    value,
+                    ..
+                },
+                Statement::VariableDeclaration {
+                    identifier,
+                    value: new_value,
+                    ..
+                },
+            )
+            | (
+                Statement::Assignment {
+                    identifier: name,
+                    value,
+                    ..
+                },
+                Statement::Assignment {
+                    identifier,
+                    value: new_value,
+                    ..
+                },
+            ) if name == identifier => {
+                self.expression(&format!("{}.value", path), value, new_value)
+            }
+            (
+                Statement::TupleDeclaration {
+                    identifiers: names,
+                    value,
+                    ..
+                },
+                Statement::TupleDeclaration {
+                    identifiers,
+                    value: new_value,
+                    ..
+                },
+            ) if names == identifiers => {
+                self.expression(&format!("{}.value", path), value, new_value)
+            }
+            (
+                Statement::IndexAssignment {
+                    array,
+                    index,
+                    value,
+                    ..
+                },
+                Statement::IndexAssignment {
+                    array: new_array,
+                    index: new_index,
+                    value: new_value,
+                    ..
+                },
+            ) if array == new_array => {
+                self.expression(&format!("{}.index", path), index, new_index);
+                self.expression(&format!("{}.value", path), value, new_value);
+            }
+            (Statement::FunctionCall(call), Statement::FunctionCall(new_call)) => {
+                self.expression(&format!("{}.call", path), call, new_call)
+            }
+            (Statement::Print(value), Statement::Print(new_value))
+            | (Statement::Eprint(value), Statement::Eprint(new_value))
+            | (Statement::Exit(value), Statement::Exit(new_value))
+            | (
+                Statement::Panic { message: value, .. },
+                Statement::Panic {
+                    message: new_value, ..
+                },
+            ) => self.expression(&format!("{}.value", path), value, new_value),
+            (
+                Statement::Assert { condition, .. },
+                Statement::Assert {
+                    condition: new_condition,
+                    ..
+                },
+            ) => self.expression(&format!("{}.condition", path), condition, new_condition),
+            (
+                Statement::FunctionDeclaration {
+                    name,
+                    parameters,
+                    return_type,
+                    body,
+                },
+                Statement::FunctionDeclaration {
+                    name: new_name,
+                    parameters: new_parameters,
+                    return_type: new_return_type,
+                    body: new_body,
+                },
+            ) if name == new_name
+                && parameters == new_parameters
+                && return_type == new_return_type =>
+            {
+                self.block(&format!("{}.body", path), body, new_body)
+            }
+            (
+                Statement::IfStatement {
+                    condition,
+                    then_block,
+                    else_block,
+                },
+                Statement::IfStatement {
+                    condition: new_condition,
+                    then_block: new_then_block,
+                    else_block: new_else_block,
+                },
+            ) if else_block.is_some() == new_else_block.is_some() => {
+                self.expression(&format!("{}.condition", path), condition, new_condition);
+                self.block(&format!("{}.then_block", path), then_block, new_then_block);
+                if let (Some(else_block), Some(new_else_block)) = (else_block, new_else_block) {
+                    self.block(&format!("{}.else_block", path), else_block, new_else_block);
+                }
+            }
+            (
+                Statement::WhileLoop {
+                    label,
+                    condition,
+                    body,
+                },
+                Statement::WhileLoop {
+                    label: new_label,
+                    condition: new_condition,
+                    body: new_body,
+                },
+            )
+            | (
+                Statement::DoWhileLoop {
+                    label,
+                    condition,
+                    body,
+                },
+                Statement::DoWhileLoop {
+                    label: new_label,
+                    condition: new_condition,
+                    body: new_body,
+                },
+            ) if label == new_label => {
+                self.expression(&format!("{}.condition", path), condition, new_condition);
+                self.block(&format!("{}.body", path), body, new_body);
+            }
+            (Statement::Block(block), Statement::Block(new_block)) => {
+                self.block(&format!("{}.block", path), block, new_block)
+            }
+            (
+                Statement::Match {
+                    value,
+                    arms,
+                    default,
+                },
+                Statement::Match {
+                    value: new_value,
+                    arms: new_arms,
+                    default: new_default,
+                },
+            ) if arms.len() == new_arms.len()
+                && arms
+                    .iter()
+                    .zip(new_arms)
+                    .all(|(arm, new_arm)| arm.pattern == new_arm.pattern)
+                && default.is_some() == new_default.is_some() =>
+            {
+                self.expression(&format!("{}.value", path), value, new_value);
+                for (index, (arm, new_arm)) in arms.iter().zip(new_arms).enumerate() {
+                    self.block(
+                        &format!("{}.arms[{}].body", path, index),
+                        &arm.body,
+                        &new_arm.body,
+                    );
+                }
+                if let (Some(default), Some(new_default)) = (default, new_default) {
+                    self.block(&format!("{}.default", path), default, new_default);
+                }
+            }
+            _ => self.changes.push(format!(
+                "{}: {} -> {}",
+                path,
+                statement_source(before),
+                statement_source(after)
+            )),
+        }
+    }
+
+    fn block(&mut self, path: &str, before: &Block, after: &Block) {
+        self.statements(
+            &format!("{}.statements", path),
+            &before.statements,
+            &after.statements,
+        );
+        let path = format!("{}.return_expression", path);
+        match (&before.return_expression, &after.return_expression) {
+            (Some(value), Some(new_value)) => self.expression(&path, value, new_value),
+            (Some(value), None) => self.changes.push(format!(
+                "{}: removed {}",
+                path,
+                PrettyPrinter::expression_to_source(value)
+            )),
+            (None, Some(new_value)) => self.changes.push(format!(
+                "{}: added {}",
+                path,
+                PrettyPrinter::expression_to_source(new_value)
+            )),
+            (None, None) => {}
+        }
+    }
+
+    fn expression(&mut self, path: &str, before: &Expression, after: &Expression) {
+        if before == after {
+            return;
+        }
+        match (before, after) {
+            (
+                Expression::ArithmeticExpression {
+                    left,
+                    operator,
+                    right,
+                },
+                Expression::ArithmeticExpression {
+                    left: new_left,
+                    operator: new_operator,
+                    right: new_right,
+                },
+            ) if operator == new_operator => self.operands(path, left, right, new_left, new_right),
+            (
+                Expression::Comparison {
+                    left,
+                    operator,
+                    right,
+                    ..
+                },
+                Expression::Comparison {
+                    left: new_left,
+                    operator: new_operator,
+                    right: new_right,
+                    ..
+                },
+            ) if operator == new_operator => self.operands(path, left, right, new_left, new_right),
+            (
+                Expression::LogicalExpression {
+                    left,
+                    operator,
+                    right,
+                },
+                Expression::LogicalExpression {
+                    left: new_left,
+                    operator: new_operator,
+                    right: new_right,
+                },
+            ) if operator == new_operator => self.operands(path, left, right, new_left, new_right),
+            (
+                Expression::FunctionCall {
+                    name, arguments, ..
+                },
+                Expression::FunctionCall {
+                    name: new_name,
+                    arguments: new_arguments,
+                    ..
+                },
+            ) if name == new_name && arguments.len() == new_arguments.len() => {
+                self.expressions(&format!("{}.arguments", path), arguments, new_arguments)
+            }
+            (Expression::Tuple(elements), Expression::Tuple(new_elements))
+            | (Expression::Array(elements), Expression::Array(new_elements))
+                if elements.len() == new_elements.len() =>
+            {
+                self.expressions(&format!("{}.elements", path), elements, new_elements)
+            }
+            (
+                Expression::IfExpression {
+                    condition,
+                    then_block,
+                    else_block,
+                },
+                Expression::IfExpression {
+                    condition: new_condition,
+                    then_block: new_then_block,
+                    else_block: new_else_block,
+                },
+            ) => {
+                self.expression(&format!("{}.condition", path), condition, new_condition);
+                self.block(&format!("{}.then_block", path), then_block, new_then_block);
+                self.block(&format!("{}.else_block", path), else_block, new_else_block);
+            }
+            (
+                Expression::Index { array, index },
+                Expression::Index {
+                    array: new_array,
+                    index: new_index,
+                },
+            ) => {
+                self.expression(&format!("{}.array", path), array, new_array);
+                self.expression(&format!("{}.index", path), index, new_index);
+            }
+            (
+                Expression::ArrayRepeat { value, count },
+                Expression::ArrayRepeat {
+                    value: new_value,
+                    count: new_count,
+                },
+            ) => {
+                self.expression(&format!("{}.value", path), value, new_value);
+                self.expression(&format!("{}.count", path), count, new_count);
+            }
+            (Expression::Not(operand), Expression::Not(new_operand)) => {
+                self.expression(&format!("{}.operand", path), operand, new_operand)
+            }
+            (
+                Expression::Sequence { steps, value },
+                Expression::Sequence {
+                    steps: new_steps,
+                    value: new_value,
+                },
+            ) if steps.len() == new_steps.len() => {
+                for (index, (step, new_step)) in steps.iter().zip(new_steps).enumerate() {
+                    self.statement(&format!("{}.steps[{}]", path, index), step, new_step);
+                }
+                self.expression(&format!("{}.value", path), value, new_value);
+            }
+            _ => self.changes.push(format!(
+                "{}: {} -> {}",
+                path,
+                PrettyPrinter::expression_to_source(before),
+                PrettyPrinter::expression_to_source(after)
+            )),
+        }
+    }
+
+    fn operands(
+        &mut self,
+        path: &str,
+        left: &Expression,
+        right: &Expression,
+        new_left: &Expression,
+        new_right: &Expression,
+    ) {
+        self.expression(&format!("{}.left", path), left, new_left);
+        self.expression(&format!("{}.right", path), right, new_right);
+    }
+
+    fn expressions(&mut self, path: &str, before: &[Expression], after: &[Expression]) {
+        for (index, (expression, new_expression)) in before.iter().zip(after).enumerate() {
+            self.expression(&format!("{}[{}]", path, index), expression, new_expression);
+        }
+    }
+}
+
+// a statement on one line, the lines of a statement with a body joined by spaces
+fn statement_source(statement: &Statement) -> String {
+    PrettyPrinter::to_source(&Program::Statements(vec![statement.clone()]))
+        .lines()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::optimizer::Optimizer;
+    use crate::parser::Parser;
+
+    fn parse(source: &str) -> Program {
+        Parser::new(Lexer::tokenize(source).unwrap())
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn constant_folding_changes_only_the_folded_operand() {
+        let parsed = parse("this n = read(); print(n + 2 * 3); print(n);");
+        let optimized = Optimizer::optimize_ast(parsed.clone());
+        assert_eq!(
+            diff(&parsed, &optimized),
+            "statements[1].value.right: 2 * 3 -> 6"
+        );
+    }
+
+    #[test]
+    fn equal_programs_have_no_differences() {
+        let program = parse("this n = 1; print(n);");
+        assert_eq!(diff(&program, &program.clone()), "");
+    }
+
+    #[test]
+    fn removed_and_added_statements_are_numbered_in_their_own_program() {
+        let before = parse("print(1); print(2); print(3);");
+        let after = parse("print(1); print(3); print(4);");
+        assert_eq!(
+            diff(&before, &after),
+            "statements[1]: removed print(2);\nstatements[2]: added print(4);"
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use crate::parser::{Block, Expression, Parameter, Program, Statement, TypeAnnotation};
+
+// Serialize the AST for external tools: every node is an object tagged with
+// its variant name under `kind`, followed by its fields in declaration order.
+// Absent optional fields are `null`, operators are their variant names
+pub fn ast_to_json(program: &Program) -> String {
+    match program {
+        Program::Statements(statements) => node(
+            "Program",
+            &[("statements", array(statements.iter().map(statement)))],
+        ),
+    }
+}
+
+fn statement(statement: &Statement) -> String {
+    match statement {
+        Statement::VariableDeclaration {
+            identifier,
+            value,
+            position,
+        } => node(
+            "VariableDeclaration",
+            &[
+                ("identifier", string(identifier)),
+                ("value", expression(value)),
+                ("position", position.to_string()),
+            ],
+        ),
+        Statement::FunctionDeclaration {
+            name,
+            parameters,
+            return_type,
+            body,
+        } => node(
+            "FunctionDeclaration",
+            &[
+                ("name", string(name)),
+                ("parameters", array(parameters.iter().map(parameter))),
+                (
+                    "return_type",
+                    optional(return_type.as_ref().map(type_annotation)),
+                ),
+                ("body", block(body)),
+            ],
+        ),
+        Statement::FunctionCall(call) => node("FunctionCall", &[("call", expression(call))]),
+        Statement::Assignment {
+            identifier,
+            value,
+            position,
+        } => node(
+            "Assignment",
+            &[
+                ("identifier", string(identifier)),
+                ("value", expression(value)),
+                ("position", position.to_string()),
+            ],
+        ),
+        Statement::IndexAssignment {
+            array,
+            index,
+            value,
+            position,
+        } => node(
+            "IndexAssignment",
+            &[
+                ("array", string(array)),
+                ("index", expression(index)),
+                ("value", expression(value)),
+                ("position", position.to_string()),
+            ],
+        ),
+        Statement::Print(value) => node("Print", &[("value", expression(value))]),
+        Statement::Eprint(value) => node("Eprint", &[("value", expression(value))]),
+        Statement::IfStatement {
+            condition,
+            then_block,
+            else_block,
+        } => node(
+            "IfStatement",
+            &[
+                ("condition", expression(condition)),
+                ("then_block", block(then_block)),
+                ("else_block", optional(else_block.as_ref().map(block))),
+            ],
+        ),
+        Statement::Assert {
+            condition,
+            position,
+        } => node(
+            "Assert",
+            &[
+                ("condition", expression(condition)),
+                ("position", position.to_string()),
+            ],
+        ),
+        Statement::TupleDeclaration {
+            identifiers,
+            value,
+            position,
+        } => node(
+            "TupleDeclaration",
+            &[
+                (
+                    "identifiers",
+                    array(identifiers.iter().map(|name| string(name))),
+                ),
+                ("value", expression(value)),
+                ("position", position.to_string()),
+            ],
+        ),
+        Statement::WhileLoop {
+            label,
+            condition,
+            body,
+        } => node(
+            "WhileLoop",
+            &[
+                ("label", optional(label.as_deref().map(string))),
+                ("condition", expression(condition)),
+                ("body", block(body)),
+            ],
+        ),
+        Statement::DoWhileLoop {
+            label,
+            body,
+            condition,
+        } => node(
+            "DoWhileLoop",
+            &[
+                ("label", optional(label.as_deref().map(string))),
+                ("body", block(body)),
+                ("condition", expression(condition)),
+            ],
+        ),
+        Statement::Break { label } => node(
+            "Break",
+            &[("label", optional(label.as_deref().map(string)))],
+        ),
+        Statement::Exit(code) => node("Exit", &[("code", expression(code))]),
+        Statement::Panic { message, position } => node(
+            "Panic",
+            &[
+                ("message", expression(message)),
+                ("position", position.to_string()),
+            ],
+        ),
+        Statement::Block(body) => node("BlockStatement", &[("body", block(body))]),
+        Statement::Match {
+            value,
+            arms,
+            default,
+        } => node(
+            "Match",
+            &[
+                ("value", expression(value)),
+                (
+                    "arms",
+                    array(arms.iter().map(|arm| {
+                        node(
+                            "MatchArm",
+                            &[
+                                ("pattern", arm.pattern.to_string()),
+                                ("body", block(&arm.body)),
+                            ],
+                        )
+                    })),
+                ),
+                ("default", optional(default.as_ref().map(block))),
+            ],
+        ),
+    }
+}
+
+fn expression(expression: &Expression) -> String {
+    match expression {
+        Expression::Integer(value) => node("Integer", &[("value", value.to_string())]),
+        Expression::String(value) => node("String", &[("value", string(value))]),
+        Expression::Nil => node("Nil", &[]),
+        Expression::Identifier { name, position } => node(
+            "Identifier",
+            &[("name", string(name)), ("position", position.to_string())],
+        ),
+        Expression::FunctionCall {
+            name,
+            arguments,
+            position,
+        } => node(
+            "FunctionCall",
+            &[
+                ("name", string(name)),
+                ("arguments", array(arguments.iter().map(self::expression))),
+                ("position", position.to_string()),
+            ],
+        ),
+        Expression::ArithmeticExpression {
+            left,
+            operator,
+            right,
+        } => binary(
+            "ArithmeticExpression",
+            left,
+            format!("{:?}", operator),
+            right,
+        ),
+        Expression::Comparison {
+            left,
+            operator,
+            right,
+            position,
+        } => node(
+            "Comparison",
+            &[
+                ("left", self::expression(left)),
+                ("operator", string(&format!("{:?}", operator))),
+                ("right", self::expression(right)),
+                ("position", position.to_string()),
+            ],
+        ),
+        Expression::LogicalExpression {
+            left,
+            operator,
+            right,
+        } => binary("LogicalExpression", left, format!("{:?}", operator), right),
+        Expression::IfExpression {
+            condition,
+            then_block,
+            else_block,
+        } => node(
+            "IfExpression",
+            &[
+                ("condition", self::expression(condition)),
+                ("then_block", block(then_block)),
+                ("else_block", block(else_block)),
+            ],
+        ),
+        Expression::Not(operand) => node("Not", &[("operand", self::expression(operand))]),
+        Expression::Tuple(elements) => node(
+            "Tuple",
+            &[("elements", array(elements.iter().map(self::expression)))],
+        ),
+        Expression::Sequence { steps, value } => node(
+            "Sequence",
+            &[
+                ("steps", array(steps.iter().map(statement))),
+                ("value", self::expression(value)),
+            ],
+        ),
+        Expression::Array(elements) => node(
+            "Array",
+            &[("elements", array(elements.iter().map(self::expression)))],
+        ),
+        Expression::ArrayRepeat { value, count } => node(
+            "ArrayRepeat",
+            &[
+                ("value", self::expression(value)),
+                ("count", self::expression(count)),
+            ],
+        ),
+        Expression::Index {
+            array: indexed,
+            index,
+        } => node(
+            "Index",
+            &[
+                ("array", self::expression(indexed)),
+                ("index", self::expression(index)),
+            ],
+        ),
+    }
+}
+
+fn binary(kind: &str, left: &Expression, operator: String, right: &Expression) -> String {
+    node(
+        kind,
+        &[
+            ("left", expression(left)),
+            ("operator", string(&operator)),
+            ("right", expression(right)),
+        ],
+    )
+}
+
+fn block(block: &Block) -> String {
+    node(
+        "Block",
+        &[
+            ("statements", array(block.statements.iter().map(statement))),
+            (
+                "return_expression",
+                optional(block.return_expression.as_ref().map(expression)),
+            ),
+        ],
+    )
+}
+
+fn parameter(parameter: &Parameter) -> String {
+    node(
+        "Parameter",
+        &[
+            ("name", string(&parameter.name)),
+            (
+                "type_annotation",
+                type_annotation(&parameter.type_annotation),
+            ),
+        ],
+    )
+}
+
+fn type_annotation(type_annotation: &TypeAnnotation) -> String {
+    match type_annotation {
+        TypeAnnotation::Int => node("Int", &[]),
+        TypeAnnotation::I32 => node("I32", &[]),
+        TypeAnnotation::Array => node("Array", &[]),
+        TypeAnnotation::Tuple(elements) => node(
+            "Tuple",
+            &[(
+                "elements",
+                array(elements.iter().map(self::type_annotation)),
+            )],
+        ),
+    }
+}
+
+// fields are already serialized
+fn node(kind: &str, fields: &[(&str, String)]) -> String {
+    let mut members = vec![format!("\"kind\":{}", string(kind))];
+    members.extend(
+        fields
+            .iter()
+            .map(|(name, value)| format!("{}:{}", string(name), value)),
+    );
+    format!("{{{}}}", members.join(","))
+}
+
+fn array(items: impl Iterator<Item = String>) -> String {
+    format!("[{}]", items.collect::<Vec<_>>().join(","))
+}
+
+fn optional(value: Option<String>) -> String {
+    value.unwrap_or_else(|| "null".to_string())
+}
+
+pub(crate) fn string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn json(source: &str) -> String {
+        ast_to_json(
+            &Parser::new(Lexer::tokenize(source).unwrap())
+                .parse()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn each_node_is_tagged_with_its_kind() {
+        assert_eq!(
+            json("this n = x + 1;"),
+            concat!(
+                r#"{"kind":"Program","statements":[{"kind":"VariableDeclaration","identifier":"n","#,
+                r#""value":{"kind":"ArithmeticExpression","#,
+                r#""left":{"kind":"Identifier","name":"x","position":3},"#,
+                r#""operator":"Add","right":{"kind":"Integer","value":1}},"position":1}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn functions_carry_their_signature_and_body() {
+        let json = json("fn half(n: int) -> int { return n / 2; }; print(half(8));");
+        for field in [
+            r#""kind":"FunctionDeclaration","name":"half""#,
+            r#""parameters":[{"kind":"Parameter","name":"n","type_annotation":{"kind":"Int"}}]"#,
+            r#""return_type":{"kind":"Int"}"#,
+            r#""return_expression":{"kind":"ArithmeticExpression""#,
+            r#""kind":"Print""#,
+            r#""kind":"FunctionCall","name":"half","arguments":[{"kind":"Integer","value":8}]"#,
+        ] {
+            assert!(json.contains(field), "{} is missing from {}", field, json);
+        }
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert!(json(r#"print("say \"hi\"\n");"#)
+            .contains(r#"{"kind":"String","value":"say \"hi\"\u000a"}"#));
+    }
+}
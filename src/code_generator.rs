@@ -1,118 +1,435 @@
+use crate::interner::{Interner, Symbol};
 use crate::parser::{
-    Block, ComparativeOperator, Condition, Expression, Operator, Program, Statement,
+    Block, ComparativeOperator, Expression, LogicalOperator, Operator, Program, Statement,
+    TypeAnnotation,
 };
-use std::collections::HashMap;
+use crate::semantic;
+use crate::visitor::{self, Visitor};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 #[derive(Debug, Clone)]
 pub enum OpCode {
-    PUSH(i64), // Push constant onto stack
-    // POP,       // Pop value from stack
-    PRINT, // Print
+    PUSHC(usize),  // Push constant from the constant pool onto stack
+    POP,           // Pop value from stack
+    NIL,           // Push the nil value void functions return
+    PUSHB(bool),   // Push a boolean, like the result of a comparison
+    PUSHS(Symbol), // Push a string literal, kept in the symbol table
+    DUP,           // Push a copy of the top of stack
+    PRINT,         // Print
+    PRINTERR,      // Print to the error output
+    READ,          // Read an integer from input onto stack
+    ARGS,          // Push the number of arguments the program was started with
+    ARG,           // Replace the index on top of stack with that program argument
+    ASSERT(usize), // Fail if top of stack is false, carries the assert's source position
 
     // Arithmetic
-    ADD, // Add top two values on stack
-    SUB, // Subtract
-    MUL, // Multiply
-    DIV, // Divide
+    ADD,    // Add top two values on stack
+    SUB,    // Subtract
+    MUL,    // Multiply
+    DIV,    // Divide
+    MOD,    // Remainder, truncated like Rust's `%` (sign follows the dividend)
+    POW,    // Raise the second value to the power on top of stack
+    ABS,    // Absolute value of top of stack
+    MIN,    // Smaller of top two values
+    MAX,    // Larger of top two values
+    WADD,   // Add, always wrapping around whatever the VM's arithmetic mode
+    WMUL,   // Multiply, always wrapping around
+    WRAP32, // Wrap top of stack to a 32-bit integer, the result of i32 arithmetic
 
     // Variable operations
-    STORE(String), // Store top of stack in variable
-    LOAD(String),  // Load variable onto stack
+    STORE(Symbol),         // Store top of stack in global variable
+    STOREI(Symbol, i64),   // Store an immediate in global variable
+    LOAD(Symbol),          // Load global variable onto stack
+    StoreLocal(usize),     // Store top of stack in local slot of the current frame
+    LoadLocal(usize),      // Load local slot of the current frame onto stack
+    INCR(Symbol, i64),     // Add an immediate to a global variable in place
+    IncrLocal(usize, i64), // Add an immediate to a local slot of the current frame in place
+
+    // Arrays
+    MakeArray(usize), // Replace that many values on top of stack with an array of them, first pushed first
+    RepeatArray, // Replace a value and a count on top of stack with an array of count copies of the value
+    LoadIndex,   // Replace an array and an index on top of stack with the element
+    StoreIndex(Symbol), // Pop a value and an index into the array of a global variable in place
+    StoreIndexLocal(usize), // Pop a value and an index into the array of a local slot in place
+    LEN,         // Replace an array or a string on top of stack with its length
 
     // Function operations
-    DECLARE(String),  // Declare a function
-    TailCall(String), // Tail call function
-    CALL(String),     // Call function with name
-    RET,              // Return from function
-    ENTER,            // Function prologue
-    EXIT,             // Function epilogue
+    DECLARE(Symbol, Option<TypeAnnotation>), // Declare a function with its return type
+    TailCall(Symbol),                        // Tail call function
+    CALL(Symbol),                            // Call function with name
+    TailCallAddr(usize),                     // Tail call function resolved to its ENTER index
+    CallAddr(usize),                         // Call function resolved to its ENTER index
+    RET,                                     // Return from function
+    ENTER,                                   // Function prologue
+    EXIT,                                    // Function epilogue
 
     // Control Flow operations
     JUMP(usize),       // Unconditional jump to instruction index
     JmpIfFalse(usize), // Conditional jump if top of stack is false
-    // JmpIfTrue(usize),  // Conditional jump if top of stack is true
+    JmpIfTrue(usize),  // Conditional jump if top of stack is true
+    JZ(usize),         // Conditional jump if top of stack is equal to the integer 0
+    JNZ(usize),        // Conditional jump if top of stack is not equal to the integer 0
 
     // Comparison operations
     EQUAL,    // Compare top two values for equality
     NotEqual, // Compare top two values for inequality
+    NOT,      // Replace top of stack with true if it is zero or false, otherwise false
+
+    HALT,  // Stop the program, the top of stack is its exit code
+    PANIC, // Stop the program with an error, the top of stack is its message
+}
+
+impl OpCode {
+    // the mnemonic without operands, as the disassembler spells it
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpCode::PUSHC(_) => "PUSHC",
+            OpCode::POP => "POP",
+            OpCode::NIL => "NIL",
+            OpCode::PUSHB(_) => "PUSHB",
+            OpCode::PUSHS(_) => "PUSHS",
+            OpCode::DUP => "DUP",
+            OpCode::PRINT => "PRINT",
+            OpCode::PRINTERR => "PRINTERR",
+            OpCode::READ => "READ",
+            OpCode::ARGS => "ARGS",
+            OpCode::ARG => "ARG",
+            OpCode::ASSERT(_) => "ASSERT",
+            OpCode::ADD => "ADD",
+            OpCode::SUB => "SUB",
+            OpCode::MUL => "MUL",
+            OpCode::DIV => "DIV",
+            OpCode::MOD => "MOD",
+            OpCode::POW => "POW",
+            OpCode::ABS => "ABS",
+            OpCode::MIN => "MIN",
+            OpCode::MAX => "MAX",
+            OpCode::WADD => "WADD",
+            OpCode::WMUL => "WMUL",
+            OpCode::WRAP32 => "WRAP32",
+            OpCode::STORE(_) => "STORE",
+            OpCode::STOREI(_, _) => "STOREI",
+            OpCode::LOAD(_) => "LOAD",
+            OpCode::StoreLocal(_) => "StoreLocal",
+            OpCode::LoadLocal(_) => "LoadLocal",
+            OpCode::MakeArray(_) => "MakeArray",
+            OpCode::RepeatArray => "RepeatArray",
+            OpCode::LoadIndex => "LoadIndex",
+            OpCode::StoreIndex(_) => "StoreIndex",
+            OpCode::StoreIndexLocal(_) => "StoreIndexLocal",
+            OpCode::LEN => "LEN",
+            OpCode::INCR(_, _) => "INCR",
+            OpCode::IncrLocal(_, _) => "IncrLocal",
+            OpCode::DECLARE(_, _) => "DECLARE",
+            OpCode::TailCall(_) => "TailCall",
+            OpCode::CALL(_) => "CALL",
+            OpCode::TailCallAddr(_) => "TailCallAddr",
+            OpCode::CallAddr(_) => "CallAddr",
+            OpCode::RET => "RET",
+            OpCode::ENTER => "ENTER",
+            OpCode::EXIT => "EXIT",
+            OpCode::JUMP(_) => "JUMP",
+            OpCode::JmpIfFalse(_) => "JmpIfFalse",
+            OpCode::JmpIfTrue(_) => "JmpIfTrue",
+            OpCode::JZ(_) => "JZ",
+            OpCode::JNZ(_) => "JNZ",
+            OpCode::EQUAL => "EQUAL",
+            OpCode::NotEqual => "NotEqual",
+            OpCode::NOT => "NOT",
+            OpCode::HALT => "HALT",
+            OpCode::PANIC => "PANIC",
+        }
+    }
+
+    // the instruction index a jump goes to, None for every other opcode;
+    // a new jump opcode only has to be added here to be back-patched and verified
+    pub fn jump_target(&self) -> Option<usize> {
+        match self {
+            OpCode::JUMP(address)
+            | OpCode::JmpIfFalse(address)
+            | OpCode::JmpIfTrue(address)
+            | OpCode::JZ(address)
+            | OpCode::JNZ(address) => Some(*address),
+            _ => None,
+        }
+    }
+
+    pub fn jump_target_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            OpCode::JUMP(address)
+            | OpCode::JmpIfFalse(address)
+            | OpCode::JmpIfTrue(address)
+            | OpCode::JZ(address)
+            | OpCode::JNZ(address) => Some(address),
+            _ => None,
+        }
+    }
 }
+
 pub struct CodeGenerator {
     opcode_list: Vec<OpCode>,
+    constants: Vec<i64>,                   // Constant pool referenced by PUSHC
+    constant_indexes: HashMap<i64, usize>, // Maps a constant to its pool index
     label_counter: usize,
     label_positions: HashMap<usize, usize>, // Maps label IDs to bytecode_list index
     unresolved_jumps: Vec<(usize, usize)>, // List of (instruction index, label ID) for back-patching
+    locals: Option<HashMap<String, usize>>, // Maps locals of the current function to slots, None at top level
+    return_counts: HashMap<String, usize>, // Maps functions to the number of values a call leaves on the stack
+    return_types: HashMap<String, TypeAnnotation>, // Maps functions to their declared return type
+    return_type: Option<TypeAnnotation>,   // Return type of the function being generated
+    i32_variables: HashSet<String>,        // Variables in scope holding i32 values
+    source_positions: BTreeMap<usize, Option<usize>>, // Maps instruction indexes to the token position they came from
+    loop_exits: Vec<(Option<String>, usize)>, // Label and exit label ID of each enclosing loop, innermost last
+    block_scopes: Vec<HashMap<String, String>>, // Renames of the variables declared in each enclosing bare block, innermost last
+    block_variables: usize, // Variables declared in bare blocks so far, numbers their renames
+    symbols: Interner,      // Names of the globals and functions the opcodes refer to
+}
+
+impl Default for CodeGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CodeGenerator {
     pub fn new() -> Self {
         Self {
             opcode_list: vec![],
+            constants: vec![],
+            constant_indexes: Default::default(),
             label_counter: 0,
             label_positions: Default::default(),
             unresolved_jumps: vec![],
+            locals: None,
+            return_counts: HashMap::new(),
+            return_types: HashMap::new(),
+            return_type: None,
+            i32_variables: HashSet::new(),
+            source_positions: BTreeMap::new(),
+            loop_exits: vec![],
+            block_scopes: vec![],
+            block_variables: 0,
+            symbols: Interner::new(),
         }
     }
 
     pub fn generate(&mut self, program: Program) -> Vec<OpCode> {
+        let mut signatures = Signatures::default();
+        signatures.visit_program(&program);
+        self.return_counts = signatures.counts;
+        self.return_types = signatures.types;
+
         match program {
             Program::Statements(statements) => {
+                // with a `main` function only declarations run at the top level,
+                // the program starts by calling `main` once they are all in place
+                let has_main = statements.iter().any(|statement| {
+                    matches!(statement, Statement::FunctionDeclaration { name, .. } if name == "main")
+                });
                 for statement in statements {
+                    let is_declaration = matches!(
+                        statement,
+                        Statement::FunctionDeclaration { .. }
+                            | Statement::VariableDeclaration { .. }
+                    );
+                    if has_main && !is_declaration {
+                        continue;
+                    }
                     self.generate_statement(statement);
                 }
+                // the call has no source of its own, its values are discarded
+                if has_main {
+                    self.mark_position(None);
+                    self.opcode_list
+                        .push(OpCode::CALL(self.symbols.intern("main")));
+                    let return_count = self.return_counts.get("main").copied().unwrap_or(1);
+                    for _ in 0..return_count {
+                        self.opcode_list.push(OpCode::POP);
+                    }
+                }
             }
         }
         self.resolve_labels();
+        self.resolve_calls();
         self.opcode_list.clone()
     }
 
+    // every call leaves a value, void functions return nil,
+    // and a tuple leaves one value per element
+    pub fn return_count(return_type: &Option<TypeAnnotation>) -> usize {
+        match return_type {
+            None
+            | Some(TypeAnnotation::Int)
+            | Some(TypeAnnotation::I32)
+            | Some(TypeAnnotation::Array) => 1,
+            Some(TypeAnnotation::Tuple(elements)) => elements.len(),
+        }
+    }
+
+    // only some instructions are marked, the others belong to the
+    // closest marked instruction before them, like in a line table
+    pub fn source_position(&self, instruction: usize) -> Option<usize> {
+        self.source_positions
+            .range(..=instruction)
+            .next_back()
+            .and_then(|(_, &position)| position)
+    }
+
+    pub fn constants(&self) -> Vec<i64> {
+        self.constants.clone()
+    }
+
+    pub fn symbols(&self) -> Interner {
+        self.symbols.clone()
+    }
+
     fn generate_statement(&mut self, statement: Statement) {
+        // positions marked in the previous statement don't carry over
+        self.mark_position(None);
         match statement {
-            Statement::VariableDeclaration { identifier, value } => {
-                self.generate_expression(value);
-                self.opcode_list.push(OpCode::STORE(identifier));
+            Statement::VariableDeclaration {
+                identifier,
+                value,
+                position,
+            } => {
+                self.mark_position(Some(position));
+                let target = self.declared_name(&identifier);
+                // a declaration takes the width of its value
+                if self.is_i32(&value) {
+                    self.i32_variables.insert(target.clone());
+                } else {
+                    self.i32_variables.remove(&target);
+                }
+                self.generate_store(target.clone(), value, false, position);
+                self.bind(identifier, target);
+            }
+            Statement::Assignment {
+                identifier,
+                value,
+                position,
+            } => {
+                self.mark_position(Some(position));
+                let identifier = self.scoped_name(&identifier);
+                let wrap = self.i32_variables.contains(&identifier) && !self.is_i32(&value);
+                // an i32 counter is wrapped after every step, so it isn't fused
+                match self.increment(&identifier, &value) {
+                    Some((position, step)) if !self.i32_variables.contains(&identifier) => {
+                        self.mark_position(Some(position));
+                        let opcode = match &self.locals {
+                            Some(locals) => OpCode::IncrLocal(locals[&identifier], step),
+                            None => OpCode::INCR(self.symbols.intern(&identifier), step),
+                        };
+                        self.opcode_list.push(opcode);
+                    }
+                    _ => self.generate_store(identifier, value, wrap, position),
+                }
             }
-            Statement::Assignment { identifier, value } => {
+            // the element is written in place, a function writing into a global
+            // array first copies it into a local, as an assignment would
+            Statement::IndexAssignment {
+                array,
+                index,
+                value,
+                position,
+            } => {
+                self.mark_position(Some(position));
+                let array = self.scoped_name(&array);
+                let local = match &self.locals {
+                    Some(locals) if !locals.contains_key(&array) => {
+                        self.emit_load(array.clone());
+                        self.emit_store(array.clone());
+                        true
+                    }
+                    locals => locals.is_some(),
+                };
+                self.generate_expression(index);
                 self.generate_expression(value);
-                self.opcode_list.push(OpCode::STORE(identifier));
+                self.mark_position(Some(position));
+                let opcode = if local {
+                    OpCode::StoreIndexLocal(self.local_slot(&array))
+                } else {
+                    OpCode::StoreIndex(self.symbols.intern(&array))
+                };
+                self.opcode_list.push(opcode);
             }
             Statement::FunctionDeclaration {
                 name,
                 parameters,
+                return_type,
                 body,
-                ..
             } => {
-                self.opcode_list.push(OpCode::DECLARE(name));
+                let return_type =
+                    return_type.or_else(|| semantic::inferred_return_type(&parameters, &body));
+                self.opcode_list.push(OpCode::DECLARE(
+                    self.symbols.intern(&name),
+                    return_type.clone(),
+                ));
                 self.opcode_list.push(OpCode::ENTER);
-                for param in parameters.iter().rev() {
-                    self.opcode_list.push(OpCode::STORE(param.name.clone()));
+
+                // parameters take the first slots in declaration order,
+                // arguments are on the stack so they are stored in reverse
+                let enclosing_locals = self.locals.replace(HashMap::new());
+                let enclosing_return_type = std::mem::replace(&mut self.return_type, return_type);
+                let enclosing_i32_variables = std::mem::take(&mut self.i32_variables);
+                // the function can't see the variables of the blocks around it
+                let enclosing_block_scopes = std::mem::take(&mut self.block_scopes);
+                let slots = parameters
+                    .iter()
+                    .map(|param| self.local_slot(&param.name))
+                    .collect::<Vec<usize>>();
+                for (slot, param) in slots.into_iter().zip(&parameters).rev() {
+                    // i32 arguments are wrapped on the way in, tail calls included
+                    if param.type_annotation == TypeAnnotation::I32 {
+                        self.opcode_list.push(OpCode::WRAP32);
+                        self.i32_variables.insert(param.name.clone());
+                    }
+                    self.opcode_list.push(OpCode::StoreLocal(slot));
                 }
 
                 let is_has_return_statement = body.return_expression.is_some();
                 self.generate_block(body);
 
                 if !is_has_return_statement {
+                    self.opcode_list.push(OpCode::NIL);
                     self.opcode_list.push(OpCode::RET);
                 }
                 self.opcode_list.push(OpCode::EXIT);
+                self.locals = enclosing_locals;
+                self.return_type = enclosing_return_type;
+                self.i32_variables = enclosing_i32_variables;
+                self.block_scopes = enclosing_block_scopes;
             }
             Statement::FunctionCall(expr) => {
+                // the returned values are not used
+                let return_count = match &expr {
+                    Expression::FunctionCall { name, .. } => {
+                        self.return_counts.get(name).copied().unwrap_or(1)
+                    }
+                    _ => 1,
+                };
                 self.generate_expression(expr);
+                for _ in 0..return_count {
+                    self.opcode_list.push(OpCode::POP);
+                }
             }
             Statement::Print(expr) => {
                 self.generate_expression(expr);
                 self.opcode_list.push(OpCode::PRINT);
             }
+            Statement::Eprint(expr) => {
+                self.generate_expression(expr);
+                self.opcode_list.push(OpCode::PRINTERR);
+            }
             Statement::IfStatement {
                 condition,
                 then_block,
                 else_block,
             } => {
-                self.generate_condition(condition);
                 let else_label = self.get_new_label();
                 let end_label = self.get_new_label();
 
-                // 0 is a placeholder
-                self.emit_jump(OpCode::JmpIfFalse(0), else_label);
+                // jumps to the else block when the condition is false
+                self.generate_branch(condition, else_label);
                 // Generate the then block
                 self.generate_block(then_block);
 
@@ -130,54 +447,403 @@ impl CodeGenerator {
                 // Mark the end of the if-else statement
                 self.set_label_position(end_label);
             }
+            Statement::Assert {
+                condition,
+                position,
+            } => {
+                self.generate_condition(condition);
+                self.mark_position(Some(position));
+                self.opcode_list.push(OpCode::ASSERT(position));
+            }
+            Statement::WhileLoop {
+                label,
+                condition,
+                body,
+            } => {
+                let start_label = self.get_new_label();
+                let end_label = self.get_new_label();
+
+                self.set_label_position(start_label);
+                self.generate_branch(condition, end_label);
+                self.loop_exits.push((label, end_label));
+                self.generate_block(body);
+                self.loop_exits.pop();
+                self.emit_jump(OpCode::JUMP(0), start_label);
+
+                self.set_label_position(end_label);
+            }
+            Statement::DoWhileLoop {
+                label,
+                body,
+                condition,
+            } => {
+                let start_label = self.get_new_label();
+                let end_label = self.get_new_label();
+
+                // the body comes first, the condition jumps back to it while true
+                self.set_label_position(start_label);
+                self.loop_exits.push((label, end_label));
+                self.generate_block(body);
+                self.loop_exits.pop();
+                self.generate_jump(condition, true, start_label);
+
+                self.set_label_position(end_label);
+            }
+            Statement::Break { label } => {
+                // the semantic check makes sure the loop exists
+                let &(_, exit_label) = self
+                    .loop_exits
+                    .iter()
+                    .rev()
+                    .find(|(loop_label, _)| label.is_none() || *loop_label == label)
+                    .expect("No loop for break");
+                self.emit_jump(OpCode::JUMP(0), exit_label);
+            }
+            Statement::Block(block) => {
+                self.block_scopes.push(HashMap::new());
+                self.generate_block(block);
+                self.block_scopes.pop();
+            }
+            Statement::Exit(code) => {
+                self.generate_expression(code);
+                self.opcode_list.push(OpCode::HALT);
+            }
+            Statement::Panic { message, position } => {
+                self.generate_expression(message);
+                self.mark_position(Some(position));
+                self.opcode_list.push(OpCode::PANIC);
+            }
+            Statement::Match {
+                value,
+                arms,
+                default,
+            } => {
+                // the value stays on the stack while it is compared with each
+                // pattern in turn, and is popped before running the chosen arm
+                self.generate_expression(value);
+                let end_label = self.get_new_label();
+                for arm in arms {
+                    let next_arm_label = self.get_new_label();
+                    self.opcode_list.push(OpCode::DUP);
+                    let index = self.add_constant(arm.pattern);
+                    self.opcode_list.push(OpCode::PUSHC(index));
+                    self.opcode_list.push(OpCode::EQUAL);
+                    self.emit_jump(OpCode::JmpIfFalse(0), next_arm_label);
+                    self.opcode_list.push(OpCode::POP);
+                    self.generate_block(arm.body);
+                    self.emit_jump(OpCode::JUMP(0), end_label);
+                    self.set_label_position(next_arm_label);
+                }
+
+                // no pattern matched
+                self.opcode_list.push(OpCode::POP);
+                if let Some(default) = default {
+                    self.generate_block(default);
+                }
+                self.set_label_position(end_label);
+            }
+            Statement::TupleDeclaration {
+                identifiers,
+                value,
+                position,
+            } => {
+                self.mark_position(Some(position));
+                let widths = match &value {
+                    Expression::Tuple(elements) => elements
+                        .iter()
+                        .map(|element| self.is_i32(element))
+                        .collect(),
+                    Expression::FunctionCall { name, .. } => match self.return_types.get(name) {
+                        Some(TypeAnnotation::Tuple(elements)) => elements
+                            .iter()
+                            .map(|element| *element == TypeAnnotation::I32)
+                            .collect(),
+                        _ => vec![],
+                    },
+                    _ => vec![],
+                };
+                let targets = identifiers
+                    .iter()
+                    .map(|identifier| self.declared_name(identifier))
+                    .collect::<Vec<String>>();
+                for (index, target) in targets.iter().enumerate() {
+                    if widths.get(index) == Some(&true) {
+                        self.i32_variables.insert(target.clone());
+                    } else {
+                        self.i32_variables.remove(target);
+                    }
+                }
+                // the values are pushed in order, so the last one is on top
+                self.generate_expression(value);
+                self.mark_position(Some(position));
+                for target in targets.iter().rev() {
+                    self.emit_store(target.clone());
+                }
+                for (identifier, target) in identifiers.into_iter().zip(targets) {
+                    self.bind(identifier, target);
+                }
+            }
         }
     }
 
-    // generate code from block and return a boolean
-    // which indicates this block has return or not
+    // generate code from a statement block, its return expression is
+    // returned from the enclosing function with RET
     fn generate_block(&mut self, block: Block) {
         for statement in block.statements {
             self.generate_statement(statement);
         }
 
         if let Some(return_expr) = block.return_expression {
+            // an i32 function wraps what it returns unless it already is an i32
+            let wrap = self.return_type == Some(TypeAnnotation::I32) && !self.is_i32(&return_expr);
             // if return statement only return function call
             match return_expr {
-                Expression::FunctionCall { name, arguments } => {
+                Expression::FunctionCall {
+                    name,
+                    arguments,
+                    position,
+                } if !Self::is_builtin(&name) && !wrap => {
                     for arg in arguments {
                         self.generate_expression(arg);
                     }
-                    self.opcode_list.push(OpCode::TailCall(name));
+                    self.mark_position(Some(position));
+                    self.opcode_list
+                        .push(OpCode::TailCall(self.symbols.intern(&name)));
+                }
+                Expression::Tuple(elements) => {
+                    let types = match &self.return_type {
+                        Some(TypeAnnotation::Tuple(types)) => types.clone(),
+                        _ => vec![],
+                    };
+                    for (index, element) in elements.into_iter().enumerate() {
+                        let wrap = types.get(index) == Some(&TypeAnnotation::I32)
+                            && !self.is_i32(&element);
+                        self.generate_expression(element);
+                        if wrap {
+                            self.opcode_list.push(OpCode::WRAP32);
+                        }
+                    }
                 }
                 _ => {
                     self.generate_expression(return_expr);
+                    if wrap {
+                        self.opcode_list.push(OpCode::WRAP32);
+                    }
                 }
             }
             self.opcode_list.push(OpCode::RET);
         }
     }
 
-    fn generate_condition(&mut self, condition: Condition) {
+    // jump to `false_label` when the condition is false, otherwise fall through;
+    // unlike `generate_condition` no 1 or 0 is left on the stack, so every
+    // short-circuit of `&&` and `||` jumps straight to where it is decided
+    fn generate_branch(&mut self, condition: Expression, false_label: usize) {
+        match condition {
+            Expression::LogicalExpression {
+                left,
+                operator: LogicalOperator::And,
+                right,
+            } => {
+                self.generate_branch(*left, false_label);
+                self.generate_branch(*right, false_label);
+            }
+            Expression::LogicalExpression {
+                left,
+                operator: LogicalOperator::Or,
+                right,
+            } if !matches!(*left, Expression::LogicalExpression { .. }) => {
+                let true_label = self.get_new_label();
+
+                // a true left side skips the right side
+                self.generate_jump(*left, true, true_label);
+                self.generate_branch(*right, false_label);
+                self.set_label_position(true_label);
+            }
+            Expression::LogicalExpression {
+                left,
+                operator: LogicalOperator::Or,
+                right,
+            } => {
+                let right_label = self.get_new_label();
+                let true_label = self.get_new_label();
+
+                // the left side branches on its own clauses first
+                self.generate_branch(*left, right_label);
+                self.emit_jump(OpCode::JUMP(0), true_label);
+
+                self.set_label_position(right_label);
+                self.generate_branch(*right, false_label);
+                self.set_label_position(true_label);
+            }
+            other => self.generate_jump(other, false, false_label),
+        }
+    }
+
+    // jump to `label` when the condition evaluates to `jump_when`; comparing
+    // with a literal 0 tests the other side directly instead of pushing the 0
+    fn generate_jump(&mut self, condition: Expression, jump_when: bool, label: usize) {
+        match condition {
+            Expression::Comparison {
+                left,
+                operator,
+                right,
+                position,
+            } if *left == Expression::Integer(0) || *right == Expression::Integer(0) => {
+                let operand = if *right == Expression::Integer(0) {
+                    *left
+                } else {
+                    *right
+                };
+                self.generate_expression(operand);
+                self.mark_position(Some(position));
+                let jumps_on_zero = (operator == ComparativeOperator::Equal) == jump_when;
+                let opcode = if jumps_on_zero {
+                    OpCode::JZ(0)
+                } else {
+                    OpCode::JNZ(0)
+                };
+                self.emit_jump(opcode, label);
+            }
+            other => {
+                self.generate_condition(other);
+                let opcode = if jump_when {
+                    OpCode::JmpIfTrue(0)
+                } else {
+                    OpCode::JmpIfFalse(0)
+                };
+                self.emit_jump(opcode, label);
+            }
+        }
+    }
+
+    // generate code from an expression block, unlike a statement block its
+    // trailing expression is left on the stack as the value of the block
+    fn generate_expression_block(&mut self, block: Block) {
+        for statement in block.statements {
+            self.generate_statement(statement);
+        }
+        let value = block
+            .return_expression
+            .expect("Expression block without a value");
+        self.generate_expression(value);
+    }
+
+    // leaves a boolean on the stack for comparisons and logical expressions,
+    // any other expression is left as is and counts as true when not zero
+    fn generate_condition(&mut self, condition: Expression) {
         match condition {
-            Condition::Comparison {
+            Expression::Comparison {
                 left,
                 operator,
                 right,
+                position,
             } => {
-                self.generate_expression(left);
-                self.generate_expression(right);
+                self.generate_expression(*left);
+                self.generate_expression(*right);
+                self.mark_position(Some(position));
                 self.generate_comparative_operator(operator);
             }
+            Expression::LogicalExpression {
+                left,
+                operator: LogicalOperator::And,
+                right,
+            } => {
+                let false_label = self.get_new_label();
+                let end_label = self.get_new_label();
+
+                // skip the right side once the left side is false
+                self.generate_condition(*left);
+                self.emit_jump(OpCode::JmpIfFalse(0), false_label);
+                self.generate_condition(*right);
+                self.emit_jump(OpCode::JUMP(0), end_label);
+
+                self.set_label_position(false_label);
+                self.opcode_list.push(OpCode::PUSHB(false));
+                self.set_label_position(end_label);
+            }
+            Expression::LogicalExpression {
+                left,
+                operator: LogicalOperator::Or,
+                right,
+            } => {
+                let right_label = self.get_new_label();
+                let end_label = self.get_new_label();
+
+                // skip the right side once the left side is true
+                self.generate_condition(*left);
+                self.emit_jump(OpCode::JmpIfFalse(0), right_label);
+                self.opcode_list.push(OpCode::PUSHB(true));
+                self.emit_jump(OpCode::JUMP(0), end_label);
+
+                self.set_label_position(right_label);
+                self.generate_condition(*right);
+                self.set_label_position(end_label);
+            }
+            other => self.generate_expression(other),
+        }
+    }
+
+    // an expression is an i32 when any of its operands is, variables and
+    // calls hold values already wrapped
+    fn is_i32(&self, expression: &Expression) -> bool {
+        match expression {
+            Expression::Identifier { name, .. } => {
+                self.i32_variables.contains(&self.scoped_name(name))
+            }
+            Expression::ArithmeticExpression { left, right, .. } => {
+                self.is_i32(left) || self.is_i32(right)
+            }
+            Expression::IfExpression {
+                then_block,
+                else_block,
+                ..
+            } => [then_block, else_block].iter().any(|block| {
+                block
+                    .return_expression
+                    .as_ref()
+                    .is_some_and(|value| self.is_i32(value))
+            }),
+            Expression::FunctionCall {
+                name, arguments, ..
+            } if Self::is_builtin(name) => arguments.iter().any(|argument| self.is_i32(argument)),
+            Expression::FunctionCall { name, .. } => {
+                self.return_types.get(name) == Some(&TypeAnnotation::I32)
+            }
+            Expression::Sequence { value, .. } => self.is_i32(value),
+            _ => false,
         }
     }
 
     fn generate_expression(&mut self, expression: Expression) {
+        // the i32 results computed here are wrapped after each operation
+        let wrap = matches!(
+            expression,
+            Expression::ArithmeticExpression { .. }
+                | Expression::IfExpression { .. }
+                | Expression::FunctionCall { .. }
+        ) && self.is_i32(&expression)
+            && !matches!(&expression, Expression::FunctionCall { name, .. } if !Self::is_builtin(name));
+        self.generate_value(expression);
+        if wrap {
+            self.opcode_list.push(OpCode::WRAP32);
+        }
+    }
+
+    fn generate_value(&mut self, expression: Expression) {
         match expression {
             Expression::Integer(value) => {
-                self.opcode_list.push(OpCode::PUSH(value));
+                let index = self.add_constant(value);
+                self.opcode_list.push(OpCode::PUSHC(index));
             }
-            Expression::Identifier(name) => {
-                self.opcode_list.push(OpCode::LOAD(name));
+            Expression::String(value) => {
+                let symbol = self.symbols.intern(&value);
+                self.opcode_list.push(OpCode::PUSHS(symbol));
+            }
+            Expression::Nil => self.opcode_list.push(OpCode::NIL),
+            Expression::Identifier { name, position } => {
+                self.mark_position(Some(position));
+                self.emit_load(self.scoped_name(&name));
             }
             Expression::ArithmeticExpression {
                 left,
@@ -188,21 +854,128 @@ impl CodeGenerator {
                 self.generate_expression(*right);
                 self.generate_operator(operator);
             }
-            Expression::FunctionCall { name, arguments } => {
+            Expression::Array(elements) => {
+                let count = elements.len();
+                for element in elements {
+                    self.generate_expression(element);
+                }
+                self.opcode_list.push(OpCode::MakeArray(count));
+            }
+            Expression::ArrayRepeat { value, count } => {
+                self.generate_expression(*value);
+                self.generate_expression(*count);
+                self.opcode_list.push(OpCode::RepeatArray);
+            }
+            Expression::Index { array, index } => {
+                self.generate_expression(*array);
+                self.generate_expression(*index);
+                self.opcode_list.push(OpCode::LoadIndex);
+            }
+            Expression::Comparison { .. } | Expression::LogicalExpression { .. } => {
+                self.generate_condition(expression);
+            }
+            Expression::IfExpression {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                self.generate_condition(*condition);
+                let else_label = self.get_new_label();
+                let end_label = self.get_new_label();
+
+                // each branch leaves exactly one value on the stack
+                self.emit_jump(OpCode::JmpIfFalse(0), else_label);
+                self.generate_expression_block(*then_block);
+                self.emit_jump(OpCode::JUMP(0), end_label);
+
+                self.set_label_position(else_label);
+                self.generate_expression_block(*else_block);
+
+                // both branches merge here with their value on the stack
+                self.set_label_position(end_label);
+            }
+            Expression::Not(operand) => {
+                self.generate_condition(*operand);
+                self.opcode_list.push(OpCode::NOT);
+            }
+            Expression::Tuple(elements) => {
+                for element in elements {
+                    self.generate_expression(element);
+                }
+            }
+            Expression::Sequence { steps, value } => {
+                for step in steps {
+                    self.generate_statement(step);
+                }
+                self.generate_expression(*value);
+            }
+            Expression::FunctionCall {
+                name,
+                arguments,
+                position,
+            } if Self::is_builtin(&name) => {
+                self.generate_builtin_call(name, arguments, position);
+            }
+            Expression::FunctionCall {
+                name,
+                arguments,
+                position,
+            } => {
                 for arg in arguments {
                     self.generate_expression(arg);
                 }
-                self.opcode_list.push(OpCode::CALL(name));
+                self.mark_position(Some(position));
+                self.opcode_list
+                    .push(OpCode::CALL(self.symbols.intern(&name)));
             }
         }
     }
 
+    // builtins are called like regular functions but compile to dedicated opcodes,
+    // their names are reserved so programs can't declare functions with them
+    pub fn builtin_arity(name: &str) -> Option<usize> {
+        match name {
+            "read" | "args" => Some(0),
+            "abs" | "arg" | "len" => Some(1),
+            "min" | "max" | "wadd" | "wmul" => Some(2),
+            _ => None,
+        }
+    }
+
+    fn is_builtin(name: &str) -> bool {
+        Self::builtin_arity(name).is_some()
+    }
+
+    fn generate_builtin_call(&mut self, name: String, arguments: Vec<Expression>, position: usize) {
+        if Self::builtin_arity(&name) != Some(arguments.len()) {
+            panic!("Wrong number of arguments for builtin {}", name);
+        }
+        for argument in arguments {
+            self.generate_expression(argument);
+        }
+        self.mark_position(Some(position));
+        match name.as_str() {
+            "read" => self.opcode_list.push(OpCode::READ),
+            "args" => self.opcode_list.push(OpCode::ARGS),
+            "arg" => self.opcode_list.push(OpCode::ARG),
+            "abs" => self.opcode_list.push(OpCode::ABS),
+            "len" => self.opcode_list.push(OpCode::LEN),
+            "min" => self.opcode_list.push(OpCode::MIN),
+            "max" => self.opcode_list.push(OpCode::MAX),
+            "wadd" => self.opcode_list.push(OpCode::WADD),
+            "wmul" => self.opcode_list.push(OpCode::WMUL),
+            _ => panic!("Unknown builtin: {}", name),
+        }
+    }
+
     fn generate_operator(&mut self, operator: Operator) {
         let opcode = match operator {
             Operator::Add => OpCode::ADD,
             Operator::Subtract => OpCode::SUB,
             Operator::Multiply => OpCode::MUL,
             Operator::Divide => OpCode::DIV,
+            Operator::Modulo => OpCode::MOD,
+            Operator::Power => OpCode::POW,
         };
         self.opcode_list.push(opcode);
     }
@@ -215,6 +988,145 @@ impl CodeGenerator {
         self.opcode_list.push(opcode);
     }
 
+    // `x = x + 5` or `x = x - 5` changes x in place, this is where x is read
+    // and the step added to it; inside a function x has to be a local already,
+    // a global read there is stored into a new local instead
+    fn increment(&self, target: &str, value: &Expression) -> Option<(usize, i64)> {
+        let Expression::ArithmeticExpression {
+            left,
+            operator,
+            right,
+        } = value
+        else {
+            return None;
+        };
+        let (name, position, step) = match (left.as_ref(), operator, right.as_ref()) {
+            (
+                Expression::Identifier { name, position },
+                Operator::Add,
+                Expression::Integer(step),
+            )
+            | (
+                Expression::Integer(step),
+                Operator::Add,
+                Expression::Identifier { name, position },
+            ) => (name, *position, *step),
+            (
+                Expression::Identifier { name, position },
+                Operator::Subtract,
+                Expression::Integer(step),
+            ) => (name, *position, step.checked_neg()?),
+            _ => return None,
+        };
+        let is_local = self
+            .locals
+            .as_ref()
+            .is_none_or(|locals| locals.contains_key(target));
+        (self.scoped_name(name) == target && is_local).then_some((position, step))
+    }
+
+    // a global set to a folded constant skips the operand stack;
+    // `wrap` stores the value in an i32 variable
+    // the store is marked with the target's `position` again, the value's
+    // own marks have taken over while it was generated
+    fn generate_store(&mut self, name: String, value: Expression, wrap: bool, position: usize) {
+        match value {
+            Expression::Integer(value) => {
+                let value = if wrap { value as i32 as i64 } else { value };
+                if self.locals.is_none() {
+                    self.opcode_list
+                        .push(OpCode::STOREI(self.symbols.intern(&name), value));
+                } else {
+                    self.generate_expression(Expression::Integer(value));
+                    self.emit_store(name);
+                }
+            }
+            value => {
+                self.generate_expression(value);
+                if wrap {
+                    self.opcode_list.push(OpCode::WRAP32);
+                }
+                self.mark_position(Some(position));
+                self.emit_store(name);
+            }
+        }
+    }
+
+    // inside a function every stored variable is local to the frame
+    fn emit_store(&mut self, name: String) {
+        if self.locals.is_some() {
+            let slot = self.local_slot(&name);
+            self.opcode_list.push(OpCode::StoreLocal(slot));
+        } else {
+            self.opcode_list
+                .push(OpCode::STORE(self.symbols.intern(&name)));
+        }
+    }
+
+    // names without a local slot are read from the globals
+    fn emit_load(&mut self, name: String) {
+        match self.locals.as_ref().and_then(|locals| locals.get(&name)) {
+            Some(&slot) => self.opcode_list.push(OpCode::LoadLocal(slot)),
+            None => self
+                .opcode_list
+                .push(OpCode::LOAD(self.symbols.intern(&name))),
+        }
+    }
+
+    // a variable declared in a bare block gets a name of its own, which
+    // can't clash with the identifiers of the program
+    fn declared_name(&mut self, name: &str) -> String {
+        if self.block_scopes.is_empty() {
+            return name.to_string();
+        }
+        self.block_variables += 1;
+        format!("{}#{}", name, self.block_variables)
+    }
+
+    // takes effect after the declaration's value, which still sees the enclosing variable
+    fn bind(&mut self, name: String, target: String) {
+        if let Some(scope) = self.block_scopes.last_mut() {
+            scope.insert(name, target);
+        }
+    }
+
+    // what a name refers to from here, the innermost block declaring it wins
+    fn scoped_name(&self, name: &str) -> String {
+        self.block_scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    fn local_slot(&mut self, name: &str) -> usize {
+        let locals = self
+            .locals
+            .as_mut()
+            .expect("No function for local variable");
+        let next_slot = locals.len();
+        *locals.entry(name.to_string()).or_insert(next_slot)
+    }
+
+    // the next emitted instruction comes from the token at `position`, or from
+    // somewhere unknown until the next mark
+    fn mark_position(&mut self, position: Option<usize>) {
+        self.source_positions
+            .insert(self.opcode_list.len(), position);
+    }
+
+    // identical constants share a single pool slot
+    fn add_constant(&mut self, value: i64) -> usize {
+        if let Some(&index) = self.constant_indexes.get(&value) {
+            return index;
+        }
+        let index = self.constants.len();
+        self.constants.push(value);
+        self.constant_indexes.insert(value, index);
+        index
+    }
+
     fn get_new_label(&mut self) -> usize {
         let label = self.label_counter;
         self.label_counter += 1;
@@ -232,17 +1144,42 @@ impl CodeGenerator {
         self.unresolved_jumps.push((label, position));
     }
 
+    // calls to a function declared exactly once jump straight to its ENTER,
+    // so the VM doesn't look the name up on every call
+    fn resolve_calls(&mut self) {
+        let mut entries: HashMap<Symbol, Option<usize>> = HashMap::new();
+        for (position, opcode) in self.opcode_list.iter().enumerate() {
+            if let OpCode::DECLARE(name, _) = opcode {
+                entries
+                    .entry(*name)
+                    .and_modify(|entry| *entry = None)
+                    .or_insert(Some(position + 1));
+            }
+        }
+
+        for opcode in &mut self.opcode_list {
+            let resolved = match opcode {
+                OpCode::CALL(name) => entries.get(name).copied().flatten().map(OpCode::CallAddr),
+                OpCode::TailCall(name) => entries
+                    .get(name)
+                    .copied()
+                    .flatten()
+                    .map(OpCode::TailCallAddr),
+                _ => None,
+            };
+            if let Some(resolved) = resolved {
+                *opcode = resolved;
+            }
+        }
+    }
+
     fn resolve_labels(&mut self) {
         for (label, index) in &self.unresolved_jumps {
             if let Some(&position) = self.label_positions.get(label) {
-                if let Some(opcode) = self.opcode_list.get_mut(*index) {
-                    match opcode {
-                        OpCode::JUMP(ref mut addr_placeholder)
-                        | OpCode::JmpIfFalse(ref mut addr_placeholder) => {
-                            *addr_placeholder = position;
-                        }
-                        _ => panic!("Unexpected opcode for label resolution"),
-                    }
+                let opcode = &mut self.opcode_list[*index];
+                match opcode.jump_target_mut() {
+                    Some(address) => *address = position,
+                    None => panic!("Unexpected opcode for label resolution: {:?}", opcode),
                 }
             } else {
                 panic!("Unresolved label: {}", label);
@@ -251,3 +1188,295 @@ impl CodeGenerator {
         self.unresolved_jumps.clear();
     }
 }
+
+// the return count and type of every declared function, inferred for the ones
+// declared without a type, so calls made before the declaration is generated
+// know how many values to discard and their width
+#[derive(Default)]
+struct Signatures {
+    counts: HashMap<String, usize>,
+    types: HashMap<String, TypeAnnotation>,
+}
+
+impl Visitor<'_> for Signatures {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let Statement::FunctionDeclaration {
+            name,
+            parameters,
+            return_type,
+            body,
+        } = statement
+        {
+            let return_type = &return_type
+                .clone()
+                .or_else(|| semantic::inferred_return_type(parameters, body));
+            self.counts
+                .insert(name.clone(), CodeGenerator::return_count(return_type));
+            if let Some(return_type) = return_type {
+                self.types.insert(name.clone(), return_type.clone());
+            }
+        }
+        visitor::walk_statement(self, statement);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{Lexer, Span};
+    use crate::parser::Parser;
+    use crate::virtual_machine::VirtualMachine;
+
+    fn generate(source: &str) -> (Vec<OpCode>, Vec<i64>) {
+        let program = Parser::new(Lexer::tokenize(source).unwrap())
+            .parse()
+            .unwrap();
+        let mut code_generator = CodeGenerator::new();
+        let opcodes = code_generator.generate(program);
+        (opcodes, code_generator.constants())
+    }
+
+    #[test]
+    fn calls_are_resolved_to_the_functions_entry() {
+        let mut code_generator = CodeGenerator::new();
+        let program = Parser::new(
+            Lexer::tokenize(
+                "fn even(n: int) -> int { if n == 0 { return 1; }; return odd(n - 1); }; \
+                 fn odd(n: int) -> int { if n == 0 { return 0; }; return even(n - 1); }; \
+                 print(even(4));",
+            )
+            .unwrap(),
+        )
+        .parse()
+        .unwrap();
+        let opcodes = code_generator.generate(program);
+        let symbols = code_generator.symbols();
+        // the function declared right before the instruction at an address
+        let function_at = |address: usize| match (&opcodes[address - 1], &opcodes[address]) {
+            (OpCode::DECLARE(name, _), OpCode::ENTER) => symbols.resolve(*name).to_string(),
+            other => panic!(
+                "call target {} is not a function entry: {:?}",
+                address, other
+            ),
+        };
+
+        let mut targets = vec![];
+        for opcode in &opcodes {
+            match opcode {
+                OpCode::CALL(_) | OpCode::TailCall(_) => panic!("unresolved {:?}", opcode),
+                OpCode::CallAddr(address) | OpCode::TailCallAddr(address) => {
+                    targets.push(function_at(*address))
+                }
+                _ => {}
+            }
+        }
+        targets.sort();
+        assert_eq!(targets, vec!["even", "even", "odd"]);
+    }
+
+    #[test]
+    fn every_jump_kind_is_back_patched() {
+        let jumps = || {
+            [
+                OpCode::JUMP(0),
+                OpCode::JmpIfFalse(0),
+                OpCode::JmpIfTrue(0),
+                OpCode::JZ(0),
+                OpCode::JNZ(0),
+            ]
+        };
+        let mut code_generator = CodeGenerator::new();
+        let backward = code_generator.get_new_label();
+        let forward = code_generator.get_new_label();
+        code_generator.set_label_position(backward);
+        for opcode in jumps() {
+            code_generator.emit_jump(opcode, forward);
+        }
+        code_generator.set_label_position(forward);
+        for opcode in jumps() {
+            code_generator.emit_jump(opcode, backward);
+        }
+        code_generator.resolve_labels();
+        let targets = code_generator
+            .opcode_list
+            .iter()
+            .map(|opcode| opcode.jump_target())
+            .collect::<Vec<_>>();
+        assert_eq!(targets[..5], [Some(5); 5]);
+        assert_eq!(targets[5..], [Some(0); 5]);
+    }
+
+    #[test]
+    fn identical_literals_share_a_pool_slot() {
+        let (opcodes, constants) = generate("print(7); print(8); print(7);");
+        assert_eq!(constants, vec![7, 8]);
+        let pushed: Vec<usize> = opcodes
+            .iter()
+            .filter_map(|opcode| match opcode {
+                OpCode::PUSHC(index) => Some(*index),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(pushed, vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn a_global_assigned_a_literal_takes_an_immediate() {
+        let (opcodes, constants) = generate("this x = 1; x = 5;");
+        assert!(matches!(
+            opcodes[..],
+            [OpCode::STOREI(declared, 1), OpCode::STOREI(assigned, 5)] if declared == assigned
+        ));
+        assert!(constants.is_empty());
+    }
+
+    #[test]
+    fn without_the_optimizer_literal_arithmetic_is_left_to_run() {
+        // what `--no-opt` hands to the code generator
+        let (opcodes, constants) = generate("print(2 + 3);");
+        assert!(matches!(
+            opcodes[..],
+            [
+                OpCode::PUSHC(0),
+                OpCode::PUSHC(1),
+                OpCode::ADD,
+                OpCode::PRINT
+            ]
+        ));
+        assert_eq!(constants, vec![2, 3]);
+
+        let program = Parser::new(Lexer::tokenize("print(2 + 3);").unwrap())
+            .parse()
+            .unwrap();
+        let mut code_generator = CodeGenerator::new();
+        let opcodes = code_generator.generate(crate::optimizer::Optimizer::optimize_ast(program));
+        assert!(matches!(opcodes[..], [OpCode::PUSHC(0), OpCode::PRINT]));
+        assert_eq!(code_generator.constants(), vec![5]);
+    }
+
+    #[test]
+    fn comparing_with_zero_jumps_on_the_value_itself() {
+        let (opcodes, constants) = generate("this x = 1; if x == 0 { print(1); };");
+        assert!(matches!(
+            opcodes[..4],
+            [
+                OpCode::STOREI(_, 1),
+                OpCode::LOAD(_),
+                OpCode::JNZ(6),
+                OpCode::PUSHC(0)
+            ]
+        ));
+        assert_eq!(constants, vec![1]);
+        let (opcodes, _) = generate("this x = 1; if 0 != x { print(1); };");
+        assert!(matches!(opcodes[1..3], [OpCode::LOAD(_), OpCode::JZ(6)]));
+        let (opcodes, constants) = generate("this n = 3; while n != 0 { n = n - 1; };");
+        assert!(matches!(
+            opcodes[..],
+            [
+                OpCode::STOREI(_, 3),
+                OpCode::LOAD(_),
+                OpCode::JZ(5),
+                OpCode::INCR(_, -1),
+                OpCode::JUMP(1)
+            ]
+        ));
+        assert!(constants.is_empty());
+    }
+
+    #[test]
+    fn adding_a_literal_to_a_variable_increments_it_in_place() {
+        let (opcodes, constants) = generate("this i = 0; i = i + 1;");
+        assert!(matches!(
+            opcodes[..],
+            [OpCode::STOREI(declared, 0), OpCode::INCR(incremented, 1)] if declared == incremented
+        ));
+        assert!(constants.is_empty());
+        let (opcodes, _) = generate("fn f(n: int) -> int { n = n + 2; return n; }; print(f(1));");
+        assert!(opcodes
+            .iter()
+            .any(|opcode| matches!(opcode, OpCode::IncrLocal(0, 2))));
+        assert!(!opcodes.iter().any(|opcode| matches!(opcode, OpCode::ADD)));
+    }
+
+    #[test]
+    fn element_writes_go_straight_into_the_variable() {
+        let (opcodes, _) = generate(
+            "this xs = [1, 2]; xs[0] = 3; fn f(ys: array) -> int { ys[1] = 4; return ys[1]; }; print(f(xs));",
+        );
+        // the index and the value, then the write, the array isn't stored back
+        assert!(matches!(
+            opcodes[3..7],
+            [
+                OpCode::STORE(_),
+                OpCode::PUSHC(_),
+                OpCode::PUSHC(_),
+                OpCode::StoreIndex(_)
+            ]
+        ));
+        assert!(matches!(
+            opcodes[9..13],
+            [
+                OpCode::StoreLocal(0),
+                OpCode::PUSHC(_),
+                OpCode::PUSHC(_),
+                OpCode::StoreIndexLocal(0)
+            ]
+        ));
+    }
+
+    // where the error of a program run without the semantic check points, the
+    // given constants are seeded read-only like `VirtualMachine::with_constants`
+    fn failing_span(source: &str, constants: &[(&str, i64)]) -> Span {
+        let (tokens, spans) = Lexer::tokenize_with_spans(source, 4).unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let mut code_generator = CodeGenerator::new();
+        let opcodes = code_generator.generate(program);
+        let mut vm = VirtualMachine::with_constants(
+            opcodes,
+            code_generator.constants(),
+            code_generator.symbols(),
+            constants
+                .iter()
+                .map(|(name, value)| (name.to_string(), *value))
+                .collect(),
+        );
+        let error = vm.run().expect_err("the program fails");
+        let position = error
+            .instruction()
+            .and_then(|ip| code_generator.source_position(ip))
+            .expect("the failing instruction has a position");
+        spans[position]
+    }
+
+    #[test]
+    fn an_undefined_variable_points_at_its_line() {
+        assert_eq!(
+            failing_span("this x = 1;\nprint(x);\nprint(y);", &[]),
+            Span { line: 3, column: 7 }
+        );
+    }
+
+    #[test]
+    fn runtime_errors_of_each_statement_point_at_it() {
+        // comparing strings
+        assert_eq!(
+            failing_span("this s = \"a\";\nif s == \"a\" {\n    print(1);\n};", &[]),
+            Span { line: 2, column: 6 }
+        );
+        // writing past the end of an array
+        assert_eq!(
+            failing_span("this xs = [1, 2];\nxs[2] = 5;", &[]),
+            Span { line: 2, column: 1 }
+        );
+        // assigning a constant
+        assert_eq!(
+            failing_span("print(1);\nlimit = 5;", &[("limit", 3)]),
+            Span { line: 2, column: 1 }
+        );
+        // a panic
+        assert_eq!(
+            failing_span("print(1);\n  panic(\"boom\");", &[]),
+            Span { line: 2, column: 3 }
+        );
+    }
+}
@@ -0,0 +1,71 @@
+use crate::code_generator::OpCode;
+use crate::interner::Interner;
+
+// One line per instruction with its index, symbols shown by name
+// and constants by their value next to the pool index
+pub fn disassemble(opcodes: &[OpCode], constants: &[i64], symbols: &Interner) -> String {
+    listing(opcodes, constants, symbols, None)
+}
+
+// `disassemble` with an arrow in front of the instruction at `current`
+pub fn listing(
+    opcodes: &[OpCode],
+    constants: &[i64],
+    symbols: &Interner,
+    current: Option<usize>,
+) -> String {
+    let mut output = String::new();
+    for (index, opcode) in opcodes.iter().enumerate() {
+        let marker = match current {
+            Some(current) if current == index => "-> ",
+            Some(_) => "   ",
+            None => "",
+        };
+        output.push_str(&format!(
+            "{}{:>4} {}\n",
+            marker,
+            index,
+            instruction(opcode, constants, symbols)
+        ));
+    }
+    output
+}
+
+pub fn instruction(opcode: &OpCode, constants: &[i64], symbols: &Interner) -> String {
+    match opcode {
+        OpCode::PUSHC(index) => match constants.get(*index) {
+            Some(value) => format!("PUSHC {} ; {}", index, value),
+            None => format!("PUSHC {} ; undefined", index),
+        },
+        OpCode::STORE(symbol) => format!("STORE {}", symbols.resolve(*symbol)),
+        OpCode::STOREI(symbol, value) => format!("STOREI {} {}", symbols.resolve(*symbol), value),
+        OpCode::LOAD(symbol) => format!("LOAD {}", symbols.resolve(*symbol)),
+        OpCode::DECLARE(symbol, return_type) => match return_type {
+            Some(return_type) => {
+                format!("DECLARE {} -> {:?}", symbols.resolve(*symbol), return_type)
+            }
+            None => format!("DECLARE {}", symbols.resolve(*symbol)),
+        },
+        OpCode::TailCall(symbol) => format!("TailCall {}", symbols.resolve(*symbol)),
+        OpCode::CALL(symbol) => format!("CALL {}", symbols.resolve(*symbol)),
+        OpCode::PUSHB(value) => format!("PUSHB {}", value),
+        OpCode::PUSHS(symbol) => format!("PUSHS {:?}", symbols.resolve(*symbol)),
+        OpCode::ASSERT(position) => format!("ASSERT {}", position),
+        OpCode::StoreLocal(slot) => format!("StoreLocal {}", slot),
+        OpCode::LoadLocal(slot) => format!("LoadLocal {}", slot),
+        OpCode::MakeArray(count) => format!("MakeArray {}", count),
+        OpCode::INCR(symbol, step) => format!("INCR {} {}", symbols.resolve(*symbol), step),
+        OpCode::IncrLocal(slot, step) => format!("IncrLocal {} {}", slot, step),
+        OpCode::StoreIndex(symbol) => format!("StoreIndex {}", symbols.resolve(*symbol)),
+        OpCode::StoreIndexLocal(slot) => format!("StoreIndexLocal {}", slot),
+        OpCode::TailCallAddr(address) => format!("TailCallAddr {}", address),
+        OpCode::CallAddr(address) => format!("CallAddr {}", address),
+        OpCode::JUMP(address) => format!("JUMP {}", address),
+        OpCode::JmpIfFalse(address) => format!("JmpIfFalse {}", address),
+        OpCode::JmpIfTrue(address) => format!("JmpIfTrue {}", address),
+        OpCode::JZ(address) => format!("JZ {}", address),
+        OpCode::JNZ(address) => format!("JNZ {}", address),
+        // no operands
+        other => other.name().to_string(),
+    }
+}
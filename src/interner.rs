@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+// An interned identifier, opcodes and the VM compare and hash these
+// instead of the names they stand for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+// The single table of identifiers and string literals used by a program,
+// each name is stored once
+#[derive(Debug, Clone, Default)]
+pub struct Interner {
+    names: Vec<String>,
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the same name always gives back the same symbol
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.to_string());
+        self.symbols.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.symbols.get(name).copied()
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+
+    // indexed by symbol
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code_generator::OpCode;
+    use crate::virtual_machine::{Value, VirtualMachine};
+
+    #[test]
+    fn the_same_name_gives_the_same_symbol() {
+        let mut interner = Interner::new();
+        let first = interner.intern("total");
+        let other = interner.intern("count");
+        assert_eq!(interner.intern("total"), first);
+        assert_ne!(first, other);
+        assert_eq!(interner.get("count"), Some(other));
+        assert_eq!(interner.get("missing"), None);
+        assert_eq!(interner.resolve(first), "total");
+        assert_eq!(interner.names(), ["total", "count"]);
+    }
+
+    #[test]
+    fn every_occurrence_of_a_global_uses_one_symbol() {
+        let bytecode = crate::compile(
+            "this total = 0; while total != 2 { total = total + 1; }; print(total);",
+        )
+        .unwrap();
+        let total = bytecode.symbols.get("total").unwrap();
+        let mut uses = 0;
+        for opcode in &bytecode.opcodes {
+            if let OpCode::LOAD(symbol)
+            | OpCode::STORE(symbol)
+            | OpCode::STOREI(symbol, _)
+            | OpCode::INCR(symbol, _) = opcode
+            {
+                assert_eq!(*symbol, total);
+                uses += 1;
+            }
+        }
+        assert!(uses >= 3);
+
+        let mut vm = VirtualMachine::new(bytecode.opcodes, bytecode.constants, bytecode.symbols);
+        vm.run().unwrap();
+        assert_eq!(vm.global("total"), Some(Value::Int(2)));
+    }
+}
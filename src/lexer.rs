@@ -1,44 +1,243 @@
+use std::fmt;
+
 #[derive(Debug, Eq, PartialEq)]
 pub enum Token<'value> {
     Identifier(&'value str),
     Minus,
     Plus,
+    PlusPlus,
+    MinusMinus,
     Divide,
     Multiply,
+    StarStar, // `**`, exponentiation
+    Modulo,
     CompareEqual,
     CompareNotEqual,
+    And,
+    Or,
+    Not,
     Equal,
     Return,
     If,
     Else,
+    While,
+    Do,
+    Break,
+    Label(&'value str), // `'outer`, without the quote
     Func,
     Print,
+    Eprint,
+    Assert,
+    Exit,
+    Panic,
     This,
+    Let, // the same as `this`
+    Match,
+    FatArrow,
+    Underscore,
     LeftParen,
     RightParen,
     LeftBracket,
     RightBracket,
+    LeftSquare,  // `[`, arrays and indexing
+    RightSquare, // `]`
     Colon,
     Comma,
     SemiColon,
     Arrow,
     Integer(i64),
+    String(String), // escapes already replaced
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError {
+    UnexpectedCharacter { character: char, position: usize },
+    IntegerOutOfRange { literal: String, position: usize },
+    UnterminatedString { position: usize },
+    UnterminatedMultilineString { position: usize },
+    InvalidEscape { escape: char, position: usize },
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnexpectedCharacter {
+                character,
+                position,
+            } => write!(
+                f,
+                "Unexpected character {} at position {}",
+                character, position
+            ),
+            LexError::IntegerOutOfRange { literal, position } => write!(
+                f,
+                "Integer literal {} at position {} does not fit in an int",
+                literal, position
+            ),
+            LexError::UnterminatedString { position } => write!(
+                f,
+                "String literal at position {} is not closed on its line",
+                position
+            ),
+            LexError::UnterminatedMultilineString { position } => write!(
+                f,
+                "Multi-line string literal at position {} is not closed with \"\"\" before the end of the file",
+                position
+            ),
+            LexError::InvalidEscape { escape, position } => {
+                write!(f, "Unknown escape \\{} at position {}", escape, position)
+            }
+        }
+    }
 }
 
+// text between tokens which doesn't change the meaning of the program
+#[derive(Debug, Eq, PartialEq)]
+pub enum Trivia<'value> {
+    Whitespace(&'value str),
+    Comment(&'value str), // `// ...` up to the end of the line, without the newline
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct TokenWithTrivia<'value> {
+    pub leading_trivia: Vec<Trivia<'value>>,
+    pub token: Token<'value>,
+}
+
+// line and column of the first character of a token, both starting at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+struct Lexed<'value> {
+    tokens: Vec<Token<'value>>,
+    starts: Vec<usize>,                   // char index where each token starts
+    trivia: Vec<(usize, Trivia<'value>)>, // paired with the index of the token it comes before
+}
+
+// columns count a tab as a single character unless told otherwise
+pub const DEFAULT_TAB_WIDTH: usize = 1;
+
+// the digits of i64::MIN, one more than i64::MAX
+const MIN_MAGNITUDE: &str = "9223372036854775808";
+
 pub struct Lexer;
 impl Lexer {
-    pub fn tokenize(input: &str) -> Vec<Token> {
+    pub fn tokenize(input: &str) -> Result<Vec<Token<'_>>, LexError> {
+        Ok(Self::lex(input, false)?.tokens)
+    }
+
+    // `tokenize` along with where each token is in the source, so a token
+    // position can be reported as a line and column; a tab moves the column
+    // to the next multiple of `tab_width`, like an editor would
+    pub fn tokenize_with_spans(
+        input: &str,
+        tab_width: usize,
+    ) -> Result<(Vec<Token<'_>>, Vec<Span>), LexError> {
+        let tab_width = tab_width.max(1);
+        let lexed = Self::lex(input, false)?;
+        let mut spans = Vec::with_capacity(lexed.starts.len());
+        let mut span = Span { line: 1, column: 1 };
+        let mut chars = input.chars();
+        let mut index = 0;
+        for start in lexed.starts {
+            while index < start {
+                match chars.next() {
+                    Some('\n') => {
+                        span.line += 1;
+                        span.column = 1;
+                    }
+                    Some('\t') => span.column += tab_width - (span.column - 1) % tab_width,
+                    _ => span.column += 1,
+                }
+                index += 1;
+            }
+            spans.push(span);
+        }
+        Ok((lexed.tokens, spans))
+    }
+
+    // lossless variant of `tokenize` for tools like a formatter: every token keeps
+    // the trivia in front of it, and the trivia after the last token is returned aside
+    pub fn tokenize_with_trivia(
+        input: &str,
+    ) -> Result<(Vec<TokenWithTrivia<'_>>, Vec<Trivia<'_>>), LexError> {
+        let lexed = Self::lex(input, true)?;
+        let mut trivia = lexed.trivia.into_iter().peekable();
+        let mut tokens_with_trivia = Vec::with_capacity(lexed.tokens.len());
+        for (index, token) in lexed.tokens.into_iter().enumerate() {
+            let mut leading_trivia = Vec::new();
+            while let Some((_, piece)) = trivia.next_if(|(before, _)| *before == index) {
+                leading_trivia.push(piece);
+            }
+            tokens_with_trivia.push(TokenWithTrivia {
+                leading_trivia,
+                token,
+            });
+        }
+        let trailing_trivia = trivia.map(|(_, piece)| piece).collect();
+        Ok((tokens_with_trivia, trailing_trivia))
+    }
+
+    // trivia is only collected when `keep_trivia` is set
+    fn lex(input: &str, keep_trivia: bool) -> Result<Lexed<'_>, LexError> {
         // Roughly estimate capacity
         let mut tokens = Vec::with_capacity(input.len() / 2);
+        let mut starts = Vec::with_capacity(input.len() / 2);
+        let mut trivia = Vec::new();
         let chars = input.chars().collect::<Vec<char>>();
+        // `i` indexes chars, but `input` has to be sliced by byte offsets
+        let offsets = input
+            .char_indices()
+            .map(|(offset, _)| offset)
+            .chain([input.len()])
+            .collect::<Vec<usize>>();
         let mut i = 0;
+        let mut token_start = 0;
         while i < chars.len() {
+            // an iteration pushes at most one token, which starts where it began
+            if starts.len() < tokens.len() {
+                starts.push(token_start);
+            }
+            token_start = i;
             match chars[i] {
-                _ if chars[i] == ' ' || chars[i] == '\n' || chars[i] == '\t' => {
-                    i += 1;
+                // includes '\r', so files with Windows line endings lex too
+                _ if chars[i].is_whitespace() => {
+                    let start = i;
+                    while i < chars.len() && chars[i].is_whitespace() {
+                        i += 1;
+                    }
+                    if keep_trivia {
+                        let text = &input[offsets[start]..offsets[i]];
+                        trivia.push((tokens.len(), Trivia::Whitespace(text)));
+                    }
+                    continue;
+                }
+                '/' if chars.get(i + 1) == Some(&'/') => {
+                    let start = i;
+                    // a CRLF line ending is left out of the comment as a whole
+                    while i < chars.len()
+                        && chars[i] != '\n'
+                        && !(chars[i] == '\r' && chars.get(i + 1) == Some(&'\n'))
+                    {
+                        i += 1;
+                    }
+                    if keep_trivia {
+                        let text = &input[offsets[start]..offsets[i]];
+                        trivia.push((tokens.len(), Trivia::Comment(text)));
+                    }
                     continue;
                 }
-                '+' => tokens.push(Token::Plus),
+                '+' => match chars.get(i + 1) {
+                    Some('+') => {
+                        tokens.push(Token::PlusPlus);
+                        i += 2;
+                        continue;
+                    }
+                    _ => tokens.push(Token::Plus),
+                },
                 '-' => match chars.get(i + 1) {
                     Some(c) if c.eq(&'>') => {
                         tokens.push(Token::Arrow);
@@ -46,10 +245,23 @@ impl Lexer {
                         i += 2;
                         continue;
                     }
+                    Some('-') => {
+                        tokens.push(Token::MinusMinus);
+                        i += 2;
+                        continue;
+                    }
                     _ => tokens.push(Token::Minus),
                 },
-                '*' => tokens.push(Token::Multiply),
+                '*' => match chars.get(i + 1) {
+                    Some('*') => {
+                        tokens.push(Token::StarStar);
+                        i += 2;
+                        continue;
+                    }
+                    _ => tokens.push(Token::Multiply),
+                },
                 '/' => tokens.push(Token::Divide),
+                '%' => tokens.push(Token::Modulo),
                 '=' => match chars.get(i + 1) {
                     Some('=') => {
                         tokens.push(Token::CompareEqual);
@@ -61,22 +273,74 @@ impl Lexer {
                         i += 2;
                         continue;
                     }
+                    Some('>') => {
+                        tokens.push(Token::FatArrow);
+                        i += 2;
+                        continue;
+                    }
                     _ => tokens.push(Token::Equal),
                 },
                 '(' => tokens.push(Token::LeftParen),
                 ')' => tokens.push(Token::RightParen),
                 '{' => tokens.push(Token::LeftBracket),
                 '}' => tokens.push(Token::RightBracket),
+                '[' => tokens.push(Token::LeftSquare),
+                ']' => tokens.push(Token::RightSquare),
                 ',' => tokens.push(Token::Comma),
+                '!' if chars.get(i + 1) == Some(&'=') => {
+                    tokens.push(Token::CompareNotEqual);
+                    i += 2;
+                    continue;
+                }
+                '!' => tokens.push(Token::Not),
+                '&' if chars.get(i + 1) == Some(&'&') => {
+                    tokens.push(Token::And);
+                    i += 2;
+                    continue;
+                }
+                '|' if chars.get(i + 1) == Some(&'|') => {
+                    tokens.push(Token::Or);
+                    i += 2;
+                    continue;
+                }
                 ':' => tokens.push(Token::Colon),
+                '_' => tokens.push(Token::Underscore),
+                '\'' if chars.get(i + 1).is_some_and(|c| c.is_alphabetic()) => {
+                    let start = i + 1;
+                    i = start;
+                    while i < chars.len() && chars[i].is_alphabetic() {
+                        i += 1;
+                    }
+                    tokens.push(Token::Label(&input[offsets[start]..offsets[i]]));
+                    continue;
+                }
                 ';' => tokens.push(Token::SemiColon),
-                _ if chars[i].is_numeric() => {
+                '"' => {
+                    let (value, end) = Self::lex_string(&chars, i)?;
+                    tokens.push(Token::String(value));
+                    i = end;
+                    continue;
+                }
+                // only ASCII digits, other numeric chars can't be parsed into an i64
+                _ if chars[i].is_ascii_digit() => {
                     let start = i;
-                    while i < chars.len() && chars[i].is_numeric() {
+                    while i < chars.len() && chars[i].is_ascii_digit() {
                         i += 1;
                     }
-                    let number = input[start..i].parse::<i64>().unwrap();
-                    tokens.push(Token::Integer(number));
+                    let literal = &input[offsets[start]..offsets[i]];
+                    // i64::MIN has no positive counterpart, so its literal only
+                    // fits together with the minus negating it. A second negation
+                    // in front, `- -9223372036854775808`, takes it out of range again
+                    if literal == MIN_MAGNITUDE
+                        && Self::is_negation(&tokens)
+                        && !Self::is_negation(&tokens[..tokens.len() - 1])
+                    {
+                        tokens.pop();
+                        token_start = starts.pop().expect("every token has a start");
+                        tokens.push(Token::Integer(i64::MIN));
+                        continue;
+                    }
+                    tokens.push(Token::Integer(Self::parse_integer(literal, start)?));
                     continue;
                 }
                 _ if chars[i].is_alphabetic() => {
@@ -84,25 +348,279 @@ impl Lexer {
                     while i < chars.len() && chars[i].is_alphabetic() {
                         i += 1;
                     }
-                    let new_string = &input[start..i];
+                    // the width types are the only words with digits in them
+                    let width = chars.get(i..i + 2).filter(|digits| {
+                        matches!(digits, ['3', '2'] | ['6', '4'])
+                            && !chars.get(i + 2).is_some_and(|c| c.is_alphanumeric())
+                    });
+                    if i - start == 1 && chars[start] == 'i' && width.is_some() {
+                        i += 2;
+                    }
+                    let new_string = &input[offsets[start]..offsets[i]];
                     match new_string {
                         "if" => tokens.push(Token::If),
                         "else" => tokens.push(Token::Else),
+                        "while" => tokens.push(Token::While),
+                        "do" => tokens.push(Token::Do),
+                        "break" => tokens.push(Token::Break),
                         "fn" => tokens.push(Token::Func),
                         "print" => tokens.push(Token::Print),
+                        "eprint" => tokens.push(Token::Eprint),
+                        "assert" => tokens.push(Token::Assert),
                         "return" => tokens.push(Token::Return),
+                        "exit" => tokens.push(Token::Exit),
+                        "panic" => tokens.push(Token::Panic),
                         "this" => tokens.push(Token::This),
+                        "let" => tokens.push(Token::Let),
+                        "match" => tokens.push(Token::Match),
                         _ => tokens.push(Token::Identifier(new_string)),
                     }
                     continue;
                 }
                 _ => {
-                    panic!("Unexpected character {} at position: {} ", chars[i], i);
+                    return Err(LexError::UnexpectedCharacter {
+                        character: chars[i],
+                        position: i,
+                    });
                 }
             }
             i += 1;
         }
+        if starts.len() < tokens.len() {
+            starts.push(token_start);
+        }
+
+        Ok(Lexed {
+            tokens,
+            starts,
+            trivia,
+        })
+    }
+
+    // `start` is the opening quote, returns the value and the index after the closing one;
+    // \" \\ \n and \t are the escapes, a string ends on the line it starts unless it
+    // is opened with three quotes: then it runs to the next three quotes, newlines included
+    fn lex_string(chars: &[char], start: usize) -> Result<(String, usize), LexError> {
+        let is_multiline = chars.get(start + 1..start + 3) == Some(&['"', '"']);
+        let mut value = String::new();
+        let mut i = if is_multiline { start + 3 } else { start + 1 };
+        loop {
+            match chars.get(i) {
+                Some('"') if !is_multiline => return Ok((value, i + 1)),
+                Some('"') if chars.get(i..i + 3) == Some(&['"', '"', '"']) => {
+                    return Ok((value, i + 3))
+                }
+                // a CRLF line ending is kept as a single newline
+                Some('\r') if is_multiline && chars.get(i + 1) == Some(&'\n') => i += 1,
+                Some('\n') if is_multiline => {
+                    value.push('\n');
+                    i += 1;
+                }
+                Some('\\') => {
+                    let escaped = match chars.get(i + 1) {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some(&escape) if escape != '\n' => {
+                            return Err(LexError::InvalidEscape {
+                                escape,
+                                position: i,
+                            })
+                        }
+                        _ => return Err(LexError::UnterminatedString { position: start }),
+                    };
+                    value.push(escaped);
+                    i += 2;
+                }
+                None if is_multiline => {
+                    return Err(LexError::UnterminatedMultilineString { position: start })
+                }
+                Some('\n') | None => return Err(LexError::UnterminatedString { position: start }),
+                Some(&c) => {
+                    value.push(c);
+                    i += 1;
+                }
+            }
+        }
+    }
+
+    // the last token is a minus in front of an operand rather than between two
+    fn is_negation(tokens: &[Token]) -> bool {
+        match tokens {
+            [before @ .., Token::Minus] => !matches!(
+                before.last(),
+                Some(
+                    Token::Integer(_)
+                        | Token::Identifier(_)
+                        | Token::String(_)
+                        | Token::RightParen
+                        | Token::RightSquare
+                )
+            ),
+            _ => false,
+        }
+    }
+
+    // every integer is an i64 for now, narrower types would check their range here
+    fn parse_integer(literal: &str, position: usize) -> Result<i64, LexError> {
+        literal
+            .parse::<i64>()
+            .map_err(|_| LexError::IntegerOutOfRange {
+                literal: literal.to_string(),
+                position,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_literal_just_over_the_largest_integer_is_out_of_range() {
+        assert_eq!(
+            Lexer::tokenize("print(9223372036854775808);"),
+            Err(LexError::IntegerOutOfRange {
+                literal: "9223372036854775808".to_string(),
+                position: 6,
+            })
+        );
+        assert!(Lexer::tokenize("print(9223372036854775807);").is_ok());
+    }
+
+    #[test]
+    fn the_smallest_integer_is_one_literal_with_its_minus() {
+        let (tokens, spans) =
+            Lexer::tokenize_with_spans("print(-9223372036854775808);", 1).unwrap();
+        assert_eq!(tokens[2], Token::Integer(i64::MIN));
+        assert_eq!(tokens.len(), spans.len());
+        assert_eq!(spans[2], Span { line: 1, column: 7 });
+        assert_eq!(
+            Lexer::tokenize("3 - -9223372036854775808").unwrap(),
+            vec![Token::Integer(3), Token::Minus, Token::Integer(i64::MIN)]
+        );
+    }
+
+    #[test]
+    fn subtracting_the_digits_of_the_smallest_integer_is_out_of_range() {
+        assert!(Lexer::tokenize("1 -9223372036854775808").is_err());
+        assert!(Lexer::tokenize("- -9223372036854775808").is_err());
+    }
+
+    #[test]
+    fn unicode_identifiers_and_comments_lex() {
+        assert_eq!(
+            Lexer::tokenize("this café = 12; // 🎉 done\nthis ñ=5;").unwrap(),
+            vec![
+                Token::This,
+                Token::Identifier("café"),
+                Token::Equal,
+                Token::Integer(12),
+                Token::SemiColon,
+                Token::This,
+                Token::Identifier("ñ"),
+                Token::Equal,
+                Token::Integer(5),
+                Token::SemiColon,
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_after_multi_byte_text_are_sliced_whole() {
+        let (tokens, spans) = Lexer::tokenize_with_spans("print(\"日本\", 42);", 1).unwrap();
+        assert_eq!(tokens[4], Token::Integer(42));
+        assert_eq!(
+            spans[4],
+            Span {
+                line: 1,
+                column: 13
+            }
+        );
+        // other numeric characters aren't digits
+        assert!(Lexer::tokenize("print(٣);").is_err());
+    }
+
+    #[test]
+    fn trivia_is_kept_with_the_token_it_comes_before() {
+        let (tokens, trailing) =
+            Lexer::tokenize_with_trivia("print(1);  // one\n\tprint(2); ").unwrap();
+        assert!(tokens[..5]
+            .iter()
+            .all(|token| token.leading_trivia.is_empty()));
+        assert_eq!(
+            tokens[5],
+            TokenWithTrivia {
+                leading_trivia: vec![
+                    Trivia::Whitespace("  "),
+                    Trivia::Comment("// one"),
+                    Trivia::Whitespace("\n\t"),
+                ],
+                token: Token::Print,
+            }
+        );
+        assert_eq!(trailing, vec![Trivia::Whitespace(" ")]);
+        // the tokens are the same as without trivia
+        assert_eq!(
+            tokens
+                .into_iter()
+                .map(|token| token.token)
+                .collect::<Vec<_>>(),
+            Lexer::tokenize("print(1);  // one\n\tprint(2); ").unwrap()
+        );
+    }
+
+    #[test]
+    fn tabs_move_the_column_to_the_next_tab_stop() {
+        let column = |source: &str, tab_width: usize| {
+            let (_, spans) = Lexer::tokenize_with_spans(source, tab_width).unwrap();
+            spans.last().unwrap().column
+        };
+        assert_eq!(column("\tx", 4), 5);
+        assert_eq!(column("ab\tx", 4), 5);
+        assert_eq!(column("abcd\tx", 4), 9);
+        assert_eq!(column("\t\tx", 8), 17);
+        assert_eq!(column("\tx", 1), 2);
+    }
+
+    #[test]
+    fn a_triple_quoted_string_spans_lines() {
+        let source = "this s = \"\"\"one\ntwo\"\"\"; print(s);";
+        let (tokens, spans) = Lexer::tokenize_with_spans(source, 4).unwrap();
+        assert_eq!(tokens[3], Token::String("one\ntwo".to_string()));
+        assert_eq!(
+            spans[3],
+            Span {
+                line: 1,
+                column: 10
+            }
+        );
+        // the tokens after it are on the line it ends on
+        assert_eq!(spans[5], Span { line: 2, column: 9 });
+        assert_eq!(
+            Lexer::tokenize(&source.replace('\n', "\r\n")).unwrap(),
+            Lexer::tokenize(source).unwrap()
+        );
+        assert!(matches!(
+            Lexer::tokenize("this s = \"\"\"one\ntwo;"),
+            Err(LexError::UnterminatedMultilineString { .. })
+        ));
+    }
 
-        tokens
+    #[test]
+    fn windows_line_endings_lex_like_unix_ones() {
+        let source = "this s = \"ab\"; // note\nprint(s);\n\n  print(1);\n";
+        let unix = Lexer::tokenize_with_spans(source, 4).unwrap();
+        let crlf = source.replace('\n', "\r\n");
+        let windows = Lexer::tokenize_with_spans(&crlf, 4).unwrap();
+        assert_eq!(windows, unix);
+        assert_eq!(
+            windows.1.last(),
+            Some(&Span {
+                line: 4,
+                column: 11
+            })
+        );
     }
 }
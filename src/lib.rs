@@ -0,0 +1,232 @@
+use crate::code_generator::{CodeGenerator, OpCode};
+use crate::interner::Interner;
+use crate::lexer::{LexError, Lexer};
+use crate::optimizer::Optimizer;
+use crate::parser::{ParseError, Parser, Program};
+use crate::semantic::SemanticError;
+use crate::verifier::VerifyError;
+use crate::virtual_machine::{RuntimeError, VirtualMachine};
+use crate::warning::Warning;
+use std::collections::HashSet;
+use std::fmt;
+
+pub mod ast_diff;
+pub mod ast_json;
+pub mod code_generator;
+pub mod disassembler;
+pub mod interner;
+pub mod lexer;
+pub mod optimizer;
+pub mod parser;
+pub mod pretty_printer;
+pub mod rust_emitter;
+pub mod semantic;
+pub mod verifier;
+pub mod virtual_machine;
+pub mod visitor;
+pub mod warning;
+
+#[derive(Debug)]
+pub enum CompileError {
+    Lex(LexError),
+    Parse(ParseError),
+    Semantic(SemanticError),
+    Verify(VerifyError),
+    Runtime(RuntimeError),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Lex(e) => write!(f, "{}", e),
+            CompileError::Parse(e) => write!(f, "{}", e),
+            CompileError::Semantic(e) => write!(f, "{}", e),
+            CompileError::Verify(e) => write!(f, "Verification error: {}", e),
+            CompileError::Runtime(e) => write!(f, "Runtime error: {}", e),
+        }
+    }
+}
+
+impl From<LexError> for CompileError {
+    fn from(e: LexError) -> Self {
+        CompileError::Lex(e)
+    }
+}
+
+impl From<ParseError> for CompileError {
+    fn from(e: ParseError) -> Self {
+        CompileError::Parse(e)
+    }
+}
+
+impl From<SemanticError> for CompileError {
+    fn from(e: SemanticError) -> Self {
+        CompileError::Semantic(e)
+    }
+}
+
+impl From<VerifyError> for CompileError {
+    fn from(e: VerifyError) -> Self {
+        CompileError::Verify(e)
+    }
+}
+
+impl From<RuntimeError> for CompileError {
+    fn from(e: RuntimeError) -> Self {
+        CompileError::Runtime(e)
+    }
+}
+
+// the opcodes refer to the constant pool and the symbol table, so they travel together
+#[derive(Debug)]
+pub struct Bytecode {
+    pub opcodes: Vec<OpCode>,
+    pub constants: Vec<i64>,
+    pub symbols: Interner,
+    pub warnings: Vec<Warning>, // from every phase, in the order they were found
+}
+
+// the whole pipeline the CLI runs, without printing the intermediate phases
+pub fn compile(source: &str) -> Result<Bytecode, CompileError> {
+    compile_with_globals(source, &HashSet::new())
+}
+
+// `compile` for a program run with `VirtualMachine::with_globals`, the names
+// of the seeded globals can be assigned without declaring them first
+pub fn compile_with_globals(
+    source: &str,
+    globals: &HashSet<String>,
+) -> Result<Bytecode, CompileError> {
+    let tokens = Lexer::tokenize(source)?;
+    let mut parser = Parser::new(tokens);
+    let ast = parser.parse()?;
+    let mut warnings = parser.warnings().to_vec();
+    warnings.extend(check(&ast, globals)?);
+    let ast = Optimizer::optimize_ast(ast);
+
+    let mut code_generator = CodeGenerator::new();
+    let opcodes = generate(&mut code_generator, ast)?;
+    Ok(Bytecode {
+        opcodes,
+        constants: code_generator.constants(),
+        symbols: code_generator.symbols(),
+        warnings,
+    })
+}
+
+// the checks between parsing and optimizing: a semantic error stops the
+// pipeline, the warnings only point at code that is likely a mistake
+pub fn check(ast: &Program, globals: &HashSet<String>) -> Result<Vec<Warning>, SemanticError> {
+    semantic::check_with_globals(ast, globals)?;
+    let mut warnings = semantic::unused_functions(ast);
+    warnings.extend(optimizer::infinite_loops(ast));
+    Ok(warnings)
+}
+
+// the opcodes for a checked AST, verified before anything can run them.
+// The generator keeps the constants, symbols and source positions they refer to
+pub fn generate(
+    code_generator: &mut CodeGenerator,
+    ast: Program,
+) -> Result<Vec<OpCode>, VerifyError> {
+    let opcodes = code_generator.generate(ast);
+    verifier::verify(&opcodes, &code_generator.symbols())?;
+    Ok(opcodes)
+}
+
+pub fn run(source: &str) -> Result<(), CompileError> {
+    let bytecode = compile(source)?;
+    VirtualMachine::new(bytecode.opcodes, bytecode.constants, bytecode.symbols).run()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::semantic::SemanticError;
+
+    #[test]
+    fn compile_returns_the_opcodes_with_their_constants() {
+        let bytecode = compile("fn f(n: int) -> int { return n * 40; }; print(f(2));").unwrap();
+        assert!(bytecode
+            .opcodes
+            .iter()
+            .any(|opcode| matches!(opcode, OpCode::PRINT)));
+        assert!(bytecode.constants.contains(&40));
+        assert!(bytecode.symbols.get("f").is_some());
+        assert!(bytecode.warnings.is_empty());
+    }
+
+    #[test]
+    fn compile_collects_the_warnings() {
+        let bytecode = compile("fn unused() -> int { return 1; }; print(1);").unwrap();
+        assert_eq!(bytecode.warnings.len(), 1);
+    }
+
+    #[test]
+    fn compile_reports_the_failing_phase() {
+        assert!(matches!(
+            compile("this n = 1 @ 2;"),
+            Err(CompileError::Lex(_))
+        ));
+        assert!(matches!(compile("this n = ;"), Err(CompileError::Parse(_))));
+        assert!(matches!(
+            compile("n = 1;"),
+            Err(CompileError::Semantic(
+                SemanticError::UndeclaredAssignment { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn a_bare_return_needs_a_function_without_a_return_type() {
+        assert!(compile("fn f(n: int) { if n == 0 { return; }; print(n); }; f(1);").is_ok());
+        assert!(matches!(
+            compile("fn f() -> int { return; }; print(f());"),
+            Err(CompileError::Semantic(
+                SemanticError::MissingReturnValue { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn only_array_parameters_can_be_indexed() {
+        assert!(
+            compile("fn f(xs: array) -> int { xs[0] = 1; return xs[0]; }; print(f([0]));").is_ok()
+        );
+        assert!(matches!(
+            compile("fn f(xs: int) -> int { return xs[0]; }; print(f([0]));"),
+            Err(CompileError::Semantic(SemanticError::IndexedInteger { .. }))
+        ));
+        assert!(matches!(
+            compile("fn f(xs: i32) { xs[0] = 1; }; f([0]);"),
+            Err(CompileError::Semantic(SemanticError::IndexedInteger { .. }))
+        ));
+        // declared again, the name is a local of any kind
+        assert!(
+            compile("fn f(xs: int) -> int { this xs = [xs]; return xs[0]; }; print(f(1));").is_ok()
+        );
+    }
+
+    #[test]
+    fn run_executes_the_program() {
+        assert!(run("this n = 6 * 7; print(n);").is_ok());
+    }
+
+    #[test]
+    fn run_reports_runtime_errors() {
+        assert!(matches!(
+            run("this zero = 0; print(1 / zero);"),
+            Err(CompileError::Runtime(RuntimeError::DivisionByZero { .. }))
+        ));
+        assert!(matches!(run("this n = ;"), Err(CompileError::Parse(_))));
+    }
+
+    #[test]
+    fn run_reports_a_panic_with_its_message() {
+        assert!(matches!(
+            run("panic(\"boom\");"),
+            Err(CompileError::Runtime(RuntimeError::UserPanic { message, .. })) if message == "boom"
+        ));
+    }
+}
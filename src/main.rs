@@ -1,25 +1,136 @@
-use crate::code_generator::CodeGenerator;
-use crate::lexer::Lexer;
-use crate::optimizer::Optimizer;
-use crate::parser::Parser;
-use crate::virtual_machine::VirtualMachine;
+use simple_compiler::code_generator::CodeGenerator;
+use simple_compiler::disassembler::disassemble;
+use simple_compiler::lexer::{Lexer, DEFAULT_TAB_WIDTH};
+use simple_compiler::optimizer::Optimizer;
+use simple_compiler::parser::{Parser, DEFAULT_MAX_ERRORS};
+use simple_compiler::pretty_printer::PrettyPrinter;
+use simple_compiler::virtual_machine::{ArithmeticMode, RuntimeError, VirtualMachine};
+use simple_compiler::{ast_diff, rust_emitter};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 use std::{env, fs};
 
-mod code_generator;
-mod lexer;
-mod optimizer;
-mod parser;
-mod virtual_machine;
+struct Options {
+    file_path: String,
+    arithmetic_mode: ArithmeticMode,
+    time_phases: bool,
+    profile: bool,
+    optimize: bool,
+    tab_width: usize,
+    max_errors: usize,
+    emit_rust: Option<String>, // file to write the program translated to Rust to
+    trace: Option<String>,     // file to write the execution trace to
+    host_constants: HashMap<String, i64>, // read-only globals given with --const=NAME=VALUE
+    arguments: Vec<i64>,       // passed on to the program, after the source file
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut file_path = None;
+    let mut arithmetic_mode = ArithmeticMode::Wrapping;
+    let mut time_phases = false;
+    let mut profile = false;
+    let mut optimize = true;
+    let mut tab_width = DEFAULT_TAB_WIDTH;
+    let mut max_errors = DEFAULT_MAX_ERRORS;
+    let mut emit_rust = None;
+    let mut trace = None;
+    let mut host_constants = HashMap::new();
+    let mut arguments = vec![];
+    for arg in args {
+        match arg.as_str() {
+            "--checked" => arithmetic_mode = ArithmeticMode::Checked,
+            "--saturating" => arithmetic_mode = ArithmeticMode::Saturating,
+            "--time" => time_phases = true,
+            "--profile" => profile = true,
+            "--no-opt" => optimize = false,
+            flag if flag.starts_with("--tab-width=") => {
+                tab_width = flag["--tab-width=".len()..]
+                    .parse()
+                    .ok()
+                    .filter(|width| *width > 0)
+                    .ok_or_else(|| format!("Invalid tab width in {}", flag))?;
+            }
+            flag if flag.starts_with("--max-errors=") => {
+                max_errors = flag["--max-errors=".len()..]
+                    .parse()
+                    .ok()
+                    .filter(|limit| *limit > 0)
+                    .ok_or_else(|| format!("Invalid error limit in {}", flag))?;
+            }
+            flag if flag.starts_with("--trace=") => {
+                trace = Some(flag["--trace=".len()..].to_string());
+            }
+            flag if flag.starts_with("--const=") => {
+                let (name, value) = flag["--const=".len()..]
+                    .split_once('=')
+                    .and_then(|(name, value)| Some((name, value.parse::<i64>().ok()?)))
+                    .filter(|(name, _)| !name.is_empty())
+                    .ok_or_else(|| format!("Invalid constant in {}, expected NAME=VALUE", flag))?;
+                host_constants.insert(name.to_string(), value);
+            }
+            flag if flag.starts_with("--emit-rust=") => {
+                emit_rust = Some(flag["--emit-rust=".len()..].to_string());
+            }
+            flag if flag.starts_with("--") => return Err(format!("Unknown flag {}", flag)),
+            path if file_path.is_none() => file_path = Some(path.to_string()),
+            argument => arguments.push(argument.parse().map_err(|_| {
+                format!(
+                    "Invalid program argument {}, only integers can be passed",
+                    argument
+                )
+            })?),
+        }
+    }
+    Ok(Options {
+        file_path: file_path.ok_or("Missing source file")?,
+        arithmetic_mode,
+        time_phases,
+        profile,
+        optimize,
+        tab_width,
+        max_errors,
+        emit_rust,
+        trace,
+        host_constants,
+        arguments,
+    })
+}
+
+fn print_timings(timings: &[(&str, Duration)]) {
+    println!("====================TIMINGS=============");
+    for (phase, duration) in timings {
+        println!("{:<16}{:>12.3?}", phase, duration);
+    }
+    let total: Duration = timings.iter().map(|(_, duration)| *duration).sum();
+    println!("{:<16}{:>12.3?}", "total", total);
+}
+
+// most executed opcodes first
+fn print_profile(profile: HashMap<&str, u64>) {
+    println!("====================PROFILE=============");
+    let mut counts: Vec<(&str, u64)> = profile.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    for (opcode, count) in counts {
+        println!("{:<16}{:>12}", opcode, count);
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 2 {
-        eprintln!("Usage: {} <source_file>", args[0]);
-        std::process::exit(1);
-    }
+    let options = match parse_args(&args[1..]) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}", error);
+            eprintln!(
+                "Usage: {} [--checked | --saturating] [--time] [--profile] [--no-opt] [--tab-width=N] [--max-errors=N] [--const=NAME=VALUE] [--emit-rust=FILE] [--trace=FILE] <source_file> [arguments...]",
+                args[0]
+            );
+            std::process::exit(1);
+        }
+    };
 
-    let file_path = &args[1];
+    let file_path = &options.file_path;
 
     // Read the source file
     let source_code = match fs::read_to_string(file_path) {
@@ -35,8 +146,19 @@ fn main() {
     println!("{}", source_code);
     println!("{}", source_code.len());
 
+    let mut timings = Vec::new();
+
     println!("==================RUN LEXICAL ANALYZE PHASE===================");
-    let tokens = Lexer::tokenize(&source_code);
+    let start = Instant::now();
+    let lexed = Lexer::tokenize_with_spans(&source_code, options.tab_width);
+    timings.push(("lexing", start.elapsed()));
+    let (tokens, spans) = match lexed {
+        Ok(lexed) => lexed,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     let t = tokens.iter();
     for to in t {
@@ -45,32 +167,125 @@ fn main() {
 
     println!("=================PARSE TOKEN======================");
 
+    let start = Instant::now();
     let mut parser = Parser::new(tokens);
-    let ast = match parser.parse() {
+    parser.set_max_errors(options.max_errors);
+    let parsed = parser.parse();
+    timings.push(("parsing", start.elapsed()));
+    let ast = match parsed {
         Ok(ast) => {
             println!("{:#?}", ast);
             ast
         }
+        Err(_) => {
+            for e in parser.errors() {
+                eprintln!("Error: {}", e);
+            }
+            std::process::exit(1);
+        }
+    };
+    let mut warnings = parser.warnings().to_vec();
+    // constants are read-only, so they don't count as declared for assignments
+    match simple_compiler::check(&ast, &HashSet::new()) {
+        Ok(found) => warnings.extend(found),
         Err(e) => {
             eprintln!("Error: {}", e);
-            return; // or handle the error case differently
+            std::process::exit(1);
         }
-    };
+    }
 
+    // without the optimizer the code generator gets the AST as parsed,
+    // to tell an optimizer bug from a code generation one
+    let optimized_expression = if options.optimize {
+        let parsed = ast.clone();
+        let start = Instant::now();
+        let optimized = Optimizer::optimize_ast(ast);
+        timings.push(("optimizing", start.elapsed()));
+        println!("=================OPTIMIZER CHANGES===================");
+        println!("{}", ast_diff::diff(&parsed, &optimized));
+        optimized
+    } else {
+        ast
+    };
     println!("=================AFTER OPTIMIZE======================");
-    let optimized_expression = Optimizer::optimize_ast(ast);
     println!("{:#?}", optimized_expression);
+    println!("{}", PrettyPrinter::to_source(&optimized_expression));
 
     println!("====================CODE GENERATE=============");
+    let start = Instant::now();
     let mut code_generator = CodeGenerator::new();
-    let opcodes = code_generator.generate(optimized_expression);
-    let mut a = 0;
-    for op in &opcodes {
-        println!("{} {:#?}", a, op);
-        a = a + 1;
+    let generated = simple_compiler::generate(&mut code_generator, optimized_expression);
+    timings.push(("code generation", start.elapsed()));
+    let opcodes = match generated {
+        Ok(opcodes) => opcodes,
+        Err(e) => {
+            eprintln!("Verification error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let constants = code_generator.constants();
+    let symbols = code_generator.symbols();
+    print!("{}", disassemble(&opcodes, &constants, &symbols));
+    println!("CONSTANTS {:?}", constants);
+    println!("SYMBOLS {:?}", symbols.names());
+    // the program compiled, so everything suspicious found on the way is reported together
+    for warning in &warnings {
+        eprintln!("Warning: {}", warning.located(&spans));
+    }
+
+    if let Some(path) = &options.emit_rust {
+        let written = rust_emitter::emit_rust(&opcodes, &constants, &symbols)
+            .map_err(|e| e.to_string())
+            .and_then(|source| fs::write(path, source).map_err(|e| e.to_string()));
+        if let Err(e) = written {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
     }
 
     println!("================VIRTUAL MACHINE====================");
-    let mut vm = VirtualMachine::new(opcodes);
-    vm.run();
+    let mut vm =
+        VirtualMachine::with_constants(opcodes, constants, symbols, options.host_constants);
+    vm.set_arithmetic_mode(options.arithmetic_mode);
+    vm.set_profiling(options.profile);
+    vm.set_arguments(options.arguments);
+    if let Some(path) = &options.trace {
+        // unbuffered, the program may stop with process::exit before a buffer is flushed
+        match fs::File::create(path) {
+            Ok(file) => vm.set_trace(Box::new(file)),
+            Err(e) => {
+                eprintln!("Error: Could not create trace file {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let start = Instant::now();
+    let result = vm.run();
+    timings.push(("execution", start.elapsed()));
+    if options.time_phases {
+        print_timings(&timings);
+    }
+    if options.profile {
+        print_profile(vm.profile());
+    }
+    if let Err(e) = result {
+        // point at the source the failing instruction was generated from
+        let position = match e {
+            RuntimeError::AssertionFailed { position } => Some(position),
+            _ => e
+                .instruction()
+                .and_then(|ip| code_generator.source_position(ip)),
+        };
+        match position.and_then(|position| spans.get(position)) {
+            Some(span) => eprintln!(
+                "Runtime error: {} (line {}, col {})",
+                e, span.line, span.column
+            ),
+            None => eprintln!("Runtime error: {}", e),
+        }
+        std::process::exit(1);
+    }
+    if let Some(code) = vm.exit_code() {
+        std::process::exit(code);
+    }
 }
@@ -1,27 +1,155 @@
-use crate::parser::{Block, Condition, Expression, Operator, Program, Statement};
+use crate::code_generator::CodeGenerator;
+use crate::parser::{
+    Block, ComparativeOperator, Expression, LogicalOperator, MatchArm, Operator, Parameter,
+    Program, Statement, TypeAnnotation,
+};
+use crate::visitor::{self, Visitor};
+use crate::warning::{Warning, WarningKind};
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 
-pub struct Optimizer;
+// A function whose body is a single arithmetic expression over its parameters,
+// so a call with constant arguments can be evaluated at compile time
+struct PureFunction {
+    parameters: Vec<String>,
+    body: Expression,
+}
+
+pub struct Optimizer {
+    pure_functions: HashMap<String, PureFunction>,
+    names: HashSet<String>, // every name in the program, hoisted values get a fresh one
+    hoisted_count: Cell<usize>,
+}
 
 impl Optimizer {
     pub fn optimize_ast(program: Program) -> Program {
         match program {
-            Program::Statements(statements) => Program::Statements(
-                statements
-                    .into_iter()
-                    .map(|stmt| Self::optimize_statement(stmt))
-                    .collect(),
-            ),
+            Program::Statements(statements) => {
+                let mut names = Names::default();
+                for statement in &statements {
+                    names.visit_statement(statement);
+                }
+                let optimizer = Optimizer {
+                    pure_functions: Self::collect_pure_functions(&statements),
+                    names: names.names,
+                    hoisted_count: Cell::new(0),
+                };
+                Program::Statements(optimizer.optimize_statements(statements))
+            }
         }
     }
 
-    fn optimize_statement(statement: Statement) -> Statement {
-        match statement {
-            Statement::VariableDeclaration { identifier, value } => {
-                Statement::VariableDeclaration {
-                    identifier,
-                    value: Self::constant_fold(&value),
+    // bodies with statements can print, loop or recurse, so they are never
+    // evaluated, and neither are bodies calling any function; i32 functions
+    // aren't either, folding doesn't wrap at 32 bits. Which declaration of a
+    // name declared twice is called depends on when, so those stay calls
+    fn collect_pure_functions(statements: &[Statement]) -> HashMap<String, PureFunction> {
+        let mut pure_functions = HashMap::new();
+        let mut declared = HashSet::new();
+        let redeclared = statements
+            .iter()
+            .filter_map(|statement| match statement {
+                Statement::FunctionDeclaration { name, .. } if !declared.insert(name) => Some(name),
+                _ => None,
+            })
+            .collect::<HashSet<&String>>();
+        for statement in statements {
+            if let Statement::FunctionDeclaration {
+                name,
+                parameters,
+                return_type,
+                body,
+            } = statement
+            {
+                if redeclared.contains(name)
+                    || parameters
+                        .iter()
+                        .any(|param| param.type_annotation != TypeAnnotation::Int)
+                {
+                    continue;
+                }
+                let parameters = parameters
+                    .iter()
+                    .map(|param| param.name.clone())
+                    .collect::<Vec<String>>();
+                match (return_type, &body.return_expression) {
+                    (Some(TypeAnnotation::Int), Some(expression))
+                        if body.statements.is_empty() && Self::is_pure(expression, &parameters) =>
+                    {
+                        pure_functions.insert(
+                            name.clone(),
+                            PureFunction {
+                                parameters,
+                                body: expression.clone(),
+                            },
+                        );
+                    }
+                    _ => {}
                 }
             }
+        }
+        pure_functions
+    }
+
+    fn is_pure(expression: &Expression, parameters: &[String]) -> bool {
+        match expression {
+            Expression::Integer(_) => true,
+            Expression::Identifier { name, .. } => parameters.contains(name),
+            Expression::ArithmeticExpression { left, right, .. } => {
+                Self::is_pure(left, parameters) && Self::is_pure(right, parameters)
+            }
+            _ => false,
+        }
+    }
+
+    // replace the parameters with the arguments, the body is pure so nothing else is left
+    fn substitute(expression: &Expression, arguments: &HashMap<&str, i64>) -> Expression {
+        match expression {
+            Expression::Identifier { name, .. } => Expression::Integer(arguments[name.as_str()]),
+            Expression::ArithmeticExpression {
+                left,
+                operator,
+                right,
+            } => Expression::ArithmeticExpression {
+                left: Box::new(Self::substitute(left, arguments)),
+                operator: operator.clone(),
+                right: Box::new(Self::substitute(right, arguments)),
+            },
+            other => other.clone(),
+        }
+    }
+
+    // a call that doesn't fold to a literal, e.g. dividing by zero, is left for the VM
+    fn evaluate_call(&self, name: &str, arguments: &[Expression]) -> Option<i64> {
+        let function = self.pure_functions.get(name)?;
+        if function.parameters.len() != arguments.len() {
+            return None;
+        }
+        let mut values = HashMap::new();
+        for (parameter, argument) in function.parameters.iter().zip(arguments) {
+            match argument {
+                Expression::Integer(value) => values.insert(parameter.as_str(), *value),
+                _ => return None,
+            };
+        }
+        match self.constant_fold(&Self::substitute(&function.body, &values)) {
+            Expression::Integer(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    // one statement can become several, or none, when a branch is known
+    fn optimize_statement(&self, statement: Statement) -> Vec<Statement> {
+        let statement = match statement {
+            Statement::VariableDeclaration {
+                identifier,
+                value,
+                position,
+            } => Statement::VariableDeclaration {
+                identifier,
+                value: self.constant_fold(&value),
+                position,
+            },
             Statement::FunctionDeclaration {
                 name,
                 parameters,
@@ -31,90 +159,636 @@ impl Optimizer {
                 name,
                 parameters,
                 return_type,
-                body: Self::optimize_block(body),
+                body: self.optimize_block(body),
             },
-            Statement::FunctionCall(exp) => Statement::FunctionCall(Self::constant_fold(&exp)),
-            Statement::Assignment { identifier, value } => Statement::Assignment {
+            // a discarded call stays a call even when it could be evaluated
+            Statement::FunctionCall(Expression::FunctionCall {
+                name,
+                arguments,
+                position,
+            }) => Statement::FunctionCall(Expression::FunctionCall {
+                name,
+                arguments: arguments
+                    .iter()
+                    .map(|argument| self.constant_fold(argument))
+                    .collect(),
+                position,
+            }),
+            Statement::FunctionCall(exp) => Statement::FunctionCall(self.constant_fold(&exp)),
+            Statement::Assignment {
                 identifier,
-                value: Self::constant_fold(&value),
+                value,
+                position,
+            } => Statement::Assignment {
+                identifier,
+                value: self.constant_fold(&value),
+                position,
+            },
+            Statement::IndexAssignment {
+                array,
+                index,
+                value,
+                position,
+            } => Statement::IndexAssignment {
+                array,
+                index: self.constant_fold(&index),
+                value: self.constant_fold(&value),
+                position,
             },
-            Statement::Print(expression) => Statement::Print(Self::constant_fold(&expression)),
+            Statement::Print(expression) => Statement::Print(self.constant_fold(&expression)),
+            Statement::Eprint(expression) => Statement::Eprint(self.constant_fold(&expression)),
             Statement::IfStatement {
                 condition,
                 then_block,
                 else_block,
-            } => Statement::IfStatement {
-                condition: Self::optimize_condition(condition),
-                then_block: Self::optimize_block(then_block),
-                else_block: match else_block {
-                    None => else_block,
-                    Some(block) => Some(Self::optimize_block(block)),
+            } => {
+                let condition = self.fold_condition(&condition);
+                let then_block = self.optimize_block(then_block);
+                let else_block = else_block.map(|block| self.optimize_block(block));
+                // the branch taken replaces the if, its declarations were already in
+                // the enclosing scope. A return is only the last expression of a
+                // block, so a branch returning keeps its if
+                match Self::literal_comparison(&condition) {
+                    Some(true) if then_block.return_expression.is_none() => {
+                        return then_block.statements;
+                    }
+                    Some(false)
+                        if else_block
+                            .as_ref()
+                            .is_none_or(|block| block.return_expression.is_none()) =>
+                    {
+                        return else_block.map(|block| block.statements).unwrap_or_default();
+                    }
+                    _ => Statement::IfStatement {
+                        condition,
+                        then_block,
+                        else_block,
+                    },
+                }
+            }
+            Statement::Assert {
+                condition,
+                position,
+            } => Statement::Assert {
+                condition: self.fold_condition(&condition),
+                position,
+            },
+            Statement::TupleDeclaration {
+                identifiers,
+                value,
+                position,
+            } => Statement::TupleDeclaration {
+                identifiers,
+                value: self.constant_fold(&value),
+                position,
+            },
+            Statement::WhileLoop {
+                label,
+                condition,
+                body,
+            } => {
+                let condition = self.fold_condition(&condition);
+                // the body of a loop false from the start never runs, what is left is
+                // an empty if, which remove_empty_if drops unless its condition can fail or calls
+                if Self::constant_condition(&condition) == Some(false) {
+                    return vec![Statement::IfStatement {
+                        condition,
+                        then_block: Block {
+                            statements: vec![],
+                            return_expression: None,
+                        },
+                        else_block: None,
+                    }];
+                }
+                self.hoist_invariants(label, condition, self.optimize_block(body))
+            }
+            Statement::DoWhileLoop {
+                label,
+                body,
+                condition,
+            } => Statement::DoWhileLoop {
+                label,
+                body: self.optimize_block(body),
+                condition: self.fold_condition(&condition),
+            },
+            Statement::Break { label } => Statement::Break { label },
+            Statement::Exit(code) => Statement::Exit(self.constant_fold(&code)),
+            Statement::Panic { message, position } => Statement::Panic {
+                message: self.constant_fold(&message),
+                position,
+            },
+            Statement::Block(block) => Statement::Block(self.optimize_block(block)),
+            Statement::Match {
+                value,
+                arms,
+                default,
+            } => Statement::Match {
+                value: self.constant_fold(&value),
+                arms: arms
+                    .into_iter()
+                    .map(|arm| MatchArm {
+                        pattern: arm.pattern,
+                        body: self.optimize_block(arm.body),
+                    })
+                    .collect(),
+                default: default.map(|block| self.optimize_block(block)),
+            },
+        };
+        vec![statement]
+    }
+
+    // Loop-invariant code motion: arithmetic over variables the loop never assigns
+    // is computed once before the loop. Only the leading declarations and assignments
+    // of the body are looked at, up to the first statement that prints, calls or
+    // branches, so a hoisted value which overflows stops the program before the
+    // same output the loop would have stopped it before. The loop is guarded by
+    // its condition, the hoisted values aren't computed when the body never runs
+    fn hoist_invariants(
+        &self,
+        label: Option<String>,
+        condition: Expression,
+        body: Block,
+    ) -> Statement {
+        let mut assigned = Assigned::default();
+        assigned.visit_block(&body);
+        let mut hoisted = vec![];
+        let mut statements = vec![];
+        // the condition is evaluated twice once guarded
+        let mut leading = !Self::has_call(&condition);
+        for statement in body.statements {
+            let statement = match statement {
+                Statement::VariableDeclaration {
+                    identifier,
+                    value,
+                    position,
+                } if leading && !Self::has_call(&value) => Statement::VariableDeclaration {
+                    identifier,
+                    value: self.hoist_expression(value, &assigned.names, &mut hoisted),
+                    position,
+                },
+                Statement::Assignment {
+                    identifier,
+                    value,
+                    position,
+                } if leading && !Self::has_call(&value) => Statement::Assignment {
+                    identifier,
+                    value: self.hoist_expression(value, &assigned.names, &mut hoisted),
+                    position,
+                },
+                statement => {
+                    leading = false;
+                    statement
+                }
+            };
+            statements.push(statement);
+        }
+
+        let while_loop = Statement::WhileLoop {
+            label,
+            condition: condition.clone(),
+            body: Block {
+                statements,
+                return_expression: body.return_expression,
+            },
+        };
+        if hoisted.is_empty() {
+            return while_loop;
+        }
+        let mut guarded = hoisted
+            .into_iter()
+            .map(
+                |(identifier, value, position)| Statement::VariableDeclaration {
+                    identifier,
+                    value,
+                    position,
                 },
+            )
+            .collect::<Vec<Statement>>();
+        guarded.push(while_loop);
+        Statement::IfStatement {
+            condition,
+            then_block: Block {
+                statements: guarded,
+                return_expression: None,
             },
+            else_block: None,
         }
     }
 
-    fn optimize_block(block: Block) -> Block {
+    // replace the largest invariant arithmetic expressions which are always
+    // evaluated with a hoisted variable, the right side of && and || and the
+    // branches of an if expression may not run so they are left alone
+    fn hoist_expression(
+        &self,
+        expression: Expression,
+        assigned: &HashSet<String>,
+        hoisted: &mut Vec<(String, Expression, usize)>,
+    ) -> Expression {
+        match expression {
+            Expression::ArithmeticExpression { .. }
+                if Self::is_invariant(&expression, assigned) =>
+            {
+                let position = Self::first_position(&expression)
+                    .expect("Invariant expression without a variable");
+                let name = self.fresh_name();
+                hoisted.push((name.clone(), expression, position));
+                Expression::Identifier { name, position }
+            }
+            Expression::ArithmeticExpression {
+                left,
+                operator,
+                right,
+            } => Expression::ArithmeticExpression {
+                left: Box::new(self.hoist_expression(*left, assigned, hoisted)),
+                operator,
+                right: Box::new(self.hoist_expression(*right, assigned, hoisted)),
+            },
+            Expression::Comparison {
+                left,
+                operator,
+                right,
+                position,
+            } => Expression::Comparison {
+                left: Box::new(self.hoist_expression(*left, assigned, hoisted)),
+                operator,
+                right: Box::new(self.hoist_expression(*right, assigned, hoisted)),
+                position,
+            },
+            Expression::LogicalExpression {
+                left,
+                operator,
+                right,
+            } => Expression::LogicalExpression {
+                left: Box::new(self.hoist_expression(*left, assigned, hoisted)),
+                operator,
+                right,
+            },
+            Expression::IfExpression {
+                condition,
+                then_block,
+                else_block,
+            } => Expression::IfExpression {
+                condition: Box::new(self.hoist_expression(*condition, assigned, hoisted)),
+                then_block,
+                else_block,
+            },
+            Expression::Not(operand) => {
+                Expression::Not(Box::new(self.hoist_expression(*operand, assigned, hoisted)))
+            }
+            other => other,
+        }
+    }
+
+    fn is_invariant(expression: &Expression, assigned: &HashSet<String>) -> bool {
+        match expression {
+            Expression::Integer(_) => true,
+            Expression::Identifier { name, .. } => !assigned.contains(name),
+            Expression::ArithmeticExpression { left, right, .. } => {
+                Self::is_invariant(left, assigned) && Self::is_invariant(right, assigned)
+            }
+            _ => false,
+        }
+    }
+
+    // constant folding leaves no arithmetic without a variable in it
+    fn first_position(expression: &Expression) -> Option<usize> {
+        match expression {
+            Expression::Identifier { position, .. } => Some(*position),
+            Expression::ArithmeticExpression { left, right, .. } => {
+                Self::first_position(left).or_else(|| Self::first_position(right))
+            }
+            _ => None,
+        }
+    }
+
+    // a value known to be an int without running the program; variables aren't,
+    // since any value can be passed where a parameter is declared int
+    fn is_int(expression: &Expression) -> bool {
+        match expression {
+            Expression::Integer(_) => true,
+            Expression::ArithmeticExpression { left, right, .. } => {
+                Self::is_int(left) && Self::is_int(right)
+            }
+            Expression::FunctionCall { name, .. } => CodeGenerator::builtin_arity(name).is_some(),
+            Expression::Sequence { value, .. } => Self::is_int(value),
+            _ => false,
+        }
+    }
+
+    // evaluating it only gives a value: no assignment, no input read and no
+    // call other than to builtins and functions folded at compile time
+    fn has_no_effects(&self, expression: &Expression) -> bool {
+        match expression {
+            Expression::Integer(_)
+            | Expression::String(_)
+            | Expression::Identifier { .. }
+            | Expression::Nil => true,
+            Expression::ArithmeticExpression { left, right, .. }
+            | Expression::Comparison { left, right, .. }
+            | Expression::LogicalExpression { left, right, .. }
+            | Expression::Index {
+                array: left,
+                index: right,
+            }
+            | Expression::ArrayRepeat {
+                value: left,
+                count: right,
+            } => self.has_no_effects(left) && self.has_no_effects(right),
+            Expression::Not(operand) => self.has_no_effects(operand),
+            Expression::Tuple(elements) | Expression::Array(elements) => {
+                elements.iter().all(|element| self.has_no_effects(element))
+            }
+            Expression::FunctionCall {
+                name, arguments, ..
+            } => {
+                let is_pure = matches!(
+                    name.as_str(),
+                    "abs" | "min" | "max" | "wadd" | "wmul" | "len" | "args"
+                ) || self.pure_functions.contains_key(name);
+                is_pure
+                    && arguments
+                        .iter()
+                        .all(|argument| self.has_no_effects(argument))
+            }
+            Expression::IfExpression { .. } | Expression::Sequence { .. } => false,
+        }
+    }
+
+    fn has_call(expression: &Expression) -> bool {
+        let mut calls = Calls::default();
+        calls.visit_expression(expression);
+        calls.found
+    }
+
+    // identifiers are letters only, so a counter is spelled with letters too
+    fn fresh_name(&self) -> String {
+        loop {
+            let mut count = self.hoisted_count.get();
+            self.hoisted_count.set(count + 1);
+            let mut suffix = String::new();
+            loop {
+                suffix.insert(0, (b'a' + (count % 26) as u8) as char);
+                count /= 26;
+                if count == 0 {
+                    break;
+                }
+            }
+            let name = format!("hoisted{}", suffix);
+            if !self.names.contains(&name) {
+                return name;
+            }
+        }
+    }
+
+    fn optimize_statements(&self, statements: Vec<Statement>) -> Vec<Statement> {
+        statements
+            .into_iter()
+            .flat_map(|statement| self.optimize_statement(statement))
+            .filter_map(Self::remove_empty_if)
+            .collect()
+    }
+
+    // an if with nothing in either branch only matters for what its condition does:
+    // a condition which can't fail is dropped along with the if, any other is kept
+    // as an expression statement, for its calls and the runtime errors it raises.
+    // && and || still decide which calls run there
+    fn remove_empty_if(statement: Statement) -> Option<Statement> {
+        let Statement::IfStatement {
+            condition,
+            then_block,
+            else_block,
+        } = statement
+        else {
+            return Some(statement);
+        };
+        let is_empty =
+            |block: &Block| block.statements.is_empty() && block.return_expression.is_none();
+        if !is_empty(&then_block) || !else_block.as_ref().is_none_or(is_empty) {
+            return Some(Statement::IfStatement {
+                condition,
+                then_block,
+                else_block,
+            });
+        }
+        if Self::cannot_fail(&condition) {
+            return None;
+        }
+        Some(Statement::FunctionCall(condition))
+    }
+
+    // Evaluating it neither calls nor stops the program with an error. Arithmetic
+    // can divide by zero or overflow in checked mode, and a variable may hold a
+    // string, which the VM refuses to compare, so only literal integers qualify
+    fn cannot_fail(condition: &Expression) -> bool {
+        match condition {
+            Expression::Integer(_) => true,
+            Expression::Comparison { left, right, .. }
+            | Expression::LogicalExpression { left, right, .. } => {
+                Self::cannot_fail(left) && Self::cannot_fail(right)
+            }
+            Expression::Not(operand) => Self::cannot_fail(operand),
+            _ => false,
+        }
+    }
+
+    fn optimize_block(&self, block: Block) -> Block {
         Block {
-            statements: block
-                .statements
-                .into_iter()
-                .map(Self::optimize_statement)
-                .collect(),
+            statements: self.optimize_statements(block.statements),
             return_expression: block
                 .return_expression
-                .map(|expr| Self::constant_fold(&expr)),
+                .map(|expr| self.constant_fold(&expr)),
         }
     }
 
-    fn optimize_condition(condition: Condition) -> Condition {
+    fn fold_condition(&self, condition: &Expression) -> Expression {
+        Self::remove_double_not(self.constant_fold(condition))
+    }
+
+    // only the truth of a condition matters, so `!!x` is `x` in it, and so is
+    // each side of && and || and the operand of `!`, which are conditions too
+    fn remove_double_not(condition: Expression) -> Expression {
         match condition {
-            Condition::Comparison {
+            Expression::Not(operand) => match Self::remove_double_not(*operand) {
+                Expression::Not(inner) => *inner,
+                operand => Expression::Not(Box::new(operand)),
+            },
+            Expression::LogicalExpression {
                 left,
                 operator,
                 right,
-            } => Condition::Comparison {
-                left: Self::constant_fold(&left),
+            } => Expression::LogicalExpression {
+                left: Box::new(Self::remove_double_not(*left)),
                 operator,
-                right: Self::constant_fold(&right),
+                right: Box::new(Self::remove_double_not(*right)),
             },
+            other => other,
         }
     }
-    pub fn constant_fold(expression: &Expression) -> Expression {
+
+    // The truth of a condition when it doesn't depend on a variable or a call.
+    // A side of && or || that decides the result alone makes it constant, even
+    // when the other side calls, so the calls still have to be kept by the caller
+    pub fn constant_condition(condition: &Expression) -> Option<bool> {
+        match condition {
+            Expression::Not(operand) => Self::constant_condition(operand).map(|truth| !truth),
+            Expression::Comparison {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                // only integers, the VM rejects comparing strings
+                let is_equal = Self::constant_value(left)? == Self::constant_value(right)?;
+                Some(match operator {
+                    ComparativeOperator::Equal => is_equal,
+                    ComparativeOperator::NotEqual => !is_equal,
+                })
+            }
+            Expression::LogicalExpression {
+                left,
+                operator,
+                right,
+            } => {
+                let left = Self::constant_condition(left);
+                let right = Self::constant_condition(right);
+                match (operator, left, right) {
+                    (LogicalOperator::And, Some(false), _)
+                    | (LogicalOperator::And, _, Some(false)) => Some(false),
+                    (LogicalOperator::Or, Some(true), _) | (LogicalOperator::Or, _, Some(true)) => {
+                        Some(true)
+                    }
+                    (_, Some(left), Some(_)) => Some(left),
+                    _ => None,
+                }
+            }
+            // a bare value is true when it is not zero
+            value => Self::constant_value(value).map(|value| value != 0),
+        }
+    }
+
+    // the truth of `2 == 3` once the sides are folded. Strings aren't compared,
+    // the VM rejects them, and a folded branch would hide the error
+    fn literal_comparison(condition: &Expression) -> Option<bool> {
+        match condition {
+            Expression::Comparison { left, right, .. }
+                if matches!(
+                    (left.as_ref(), right.as_ref()),
+                    (Expression::Integer(_), Expression::Integer(_))
+                ) =>
+            {
+                Self::constant_condition(condition)
+            }
+            _ => None,
+        }
+    }
+
+    // an arithmetic that overflows is left to the VM, like in constant_fold
+    fn constant_value(expression: &Expression) -> Option<i64> {
         match expression {
+            Expression::Integer(value) => Some(*value),
             Expression::ArithmeticExpression {
                 left,
                 operator,
                 right,
             } => {
-                let left = Optimizer::constant_fold(left);
-                let right = Optimizer::constant_fold(right);
+                let left = Self::constant_value(left)?;
+                let right = Self::constant_value(right)?;
+                match operator {
+                    Operator::Add => left.checked_add(right),
+                    Operator::Subtract => left.checked_sub(right),
+                    Operator::Multiply => left.checked_mul(right),
+                    Operator::Divide => left.checked_div(right),
+                    Operator::Modulo => left.checked_rem(right),
+                    Operator::Power => Self::checked_pow(left, right),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // a negative exponent is a runtime error, so like an overflow it isn't folded.
+    // An exponent beyond u32 overflows anyway unless the base is -1, 0 or 1
+    fn checked_pow(base: i64, exponent: i64) -> Option<i64> {
+        base.checked_pow(u32::try_from(exponent).ok()?)
+    }
+
+    // && and || leave the value of their last operand, which may be an integer
+    fn is_boolean(expression: &Expression) -> bool {
+        matches!(
+            expression,
+            Expression::Comparison { .. } | Expression::Not(_)
+        )
+    }
+
+    pub fn constant_fold(&self, expression: &Expression) -> Expression {
+        match expression {
+            Expression::ArithmeticExpression {
+                left,
+                operator,
+                right,
+            } => {
+                let left = self.constant_fold(left);
+                let right = self.constant_fold(right);
 
                 match (left, operator, right) {
-                    (Expression::Integer(l), Operator::Add, Expression::Integer(r)) => {
+                    (Expression::String(l), Operator::Add, Expression::String(r)) => {
+                        Expression::String(l + &r)
+                    }
+                    // an overflow is left to the VM, whose arithmetic mode decides the result
+                    (Expression::Integer(l), Operator::Add, Expression::Integer(r))
+                        if l.checked_add(r).is_some() =>
+                    {
                         Expression::Integer(l + r)
                     }
-                    (Expression::Integer(l), Operator::Subtract, Expression::Integer(r)) => {
+                    (Expression::Integer(l), Operator::Subtract, Expression::Integer(r))
+                        if l.checked_sub(r).is_some() =>
+                    {
                         Expression::Integer(l - r)
                     }
-                    (Expression::Integer(l), Operator::Multiply, Expression::Integer(r)) => {
+                    (Expression::Integer(l), Operator::Multiply, Expression::Integer(r))
+                        if l.checked_mul(r).is_some() =>
+                    {
                         Expression::Integer(l * r)
                     }
                     (Expression::Integer(l), Operator::Divide, Expression::Integer(r))
-                        if r != 0 =>
+                        if l.checked_div(r).is_some() =>
                     {
                         Expression::Integer(l / r)
                     }
+                    (Expression::Integer(l), Operator::Modulo, Expression::Integer(r))
+                        if l.checked_rem(r).is_some() =>
+                    {
+                        Expression::Integer(l % r)
+                    }
+                    (Expression::Integer(l), Operator::Power, Expression::Integer(r))
+                        if Self::checked_pow(l, r).is_some() =>
+                    {
+                        Expression::Integer(l.pow(r as u32))
+                    }
 
-                    // Multiplication-specific rules
-                    (Expression::Integer(1), Operator::Multiply, right) => right, // 1 * x -> x
-                    (left, Operator::Multiply, Expression::Integer(1)) => left,   // x * 1 -> x
-                    (Expression::Integer(0), Operator::Multiply, _) => Expression::Integer(0), // 0 * x -> 0
-                    (_, Operator::Multiply, Expression::Integer(0)) => Expression::Integer(0), // x * 0 -> 0
-
-                    // Addition-specific rules
-                    (Expression::Integer(0), Operator::Add, right) => right, // 0 + x -> x
-                    (left, Operator::Add, Expression::Integer(0)) => left,   // x + 0 -> x
+                    // The identities only hold for an int x: a string, an array or
+                    // a boolean would be a type error or print differently
+                    // 1 * x -> x and x * 1 -> x
+                    (Expression::Integer(1), Operator::Multiply, other)
+                    | (other, Operator::Multiply, Expression::Integer(1))
+                        if Self::is_int(&other) =>
+                    {
+                        other
+                    }
+                    // 0 * x -> 0 and x * 0 -> 0, when nothing is lost with x
+                    (Expression::Integer(0), Operator::Multiply, other)
+                    | (other, Operator::Multiply, Expression::Integer(0))
+                        if Self::is_int(&other) && self.has_no_effects(&other) =>
+                    {
+                        Expression::Integer(0)
+                    }
+                    // 0 + x -> x and x + 0 -> x
+                    (Expression::Integer(0), Operator::Add, other)
+                    | (other, Operator::Add, Expression::Integer(0))
+                        if Self::is_int(&other) =>
+                    {
+                        other
+                    }
 
                     // If no optimizations apply, reconstruct the expression
                     (left, operator, right) => Expression::ArithmeticExpression {
@@ -124,7 +798,304 @@ impl Optimizer {
                     },
                 }
             }
+            Expression::Comparison {
+                left,
+                operator,
+                right,
+                position,
+            } => Expression::Comparison {
+                left: Box::new(self.constant_fold(left)),
+                operator: operator.clone(),
+                right: Box::new(self.constant_fold(right)),
+                position: *position,
+            },
+            Expression::LogicalExpression {
+                left,
+                operator,
+                right,
+            } => Expression::LogicalExpression {
+                left: Box::new(self.constant_fold(left)),
+                operator: operator.clone(),
+                right: Box::new(self.constant_fold(right)),
+            },
+            Expression::IfExpression {
+                condition,
+                then_block,
+                else_block,
+            } => Expression::IfExpression {
+                condition: Box::new(self.fold_condition(condition)),
+                then_block: Box::new(self.optimize_block(*then_block.clone())),
+                else_block: Box::new(self.optimize_block(*else_block.clone())),
+            },
+            Expression::FunctionCall {
+                name,
+                arguments,
+                position,
+            } => {
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.constant_fold(argument))
+                    .collect::<Vec<Expression>>();
+                match self.evaluate_call(name, &arguments) {
+                    Some(value) => Expression::Integer(value),
+                    None => Expression::FunctionCall {
+                        name: name.clone(),
+                        arguments,
+                        position: *position,
+                    },
+                }
+            }
+            // there are no boolean literals to fold `!` into, but `!!` of
+            // something already a boolean is that boolean
+            Expression::Not(operand) => match self.constant_fold(operand) {
+                Expression::Not(inner) if Self::is_boolean(&inner) => *inner,
+                operand => Expression::Not(Box::new(operand)),
+            },
+            Expression::Tuple(elements) => Expression::Tuple(
+                elements
+                    .iter()
+                    .map(|element| self.constant_fold(element))
+                    .collect(),
+            ),
+            Expression::Array(elements) => Expression::Array(
+                elements
+                    .iter()
+                    .map(|element| self.constant_fold(element))
+                    .collect(),
+            ),
+            Expression::ArrayRepeat { value, count } => Expression::ArrayRepeat {
+                value: Box::new(self.constant_fold(value)),
+                count: Box::new(self.constant_fold(count)),
+            },
+            Expression::Index { array, index } => Expression::Index {
+                array: Box::new(self.constant_fold(array)),
+                index: Box::new(self.constant_fold(index)),
+            },
+            Expression::Sequence { steps, value } => Expression::Sequence {
+                steps: steps
+                    .iter()
+                    .flat_map(|step| self.optimize_statement(step.clone()))
+                    .collect(),
+                value: Box::new(self.constant_fold(value)),
+            },
             other => other.clone(),
         }
     }
 }
+
+// A loop whose condition is always true only ends through a break, an exit or
+// a return inside it, without one the program never gets past it
+pub fn infinite_loops(program: &Program) -> Vec<Warning> {
+    let mut loops = InfiniteLoops::default();
+    loops.visit_program(program);
+    loops.warnings
+}
+
+#[derive(Default)]
+struct InfiniteLoops {
+    warnings: Vec<Warning>,
+}
+
+impl Visitor<'_> for InfiniteLoops {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let Statement::WhileLoop {
+            label,
+            condition,
+            body,
+        }
+        | Statement::DoWhileLoop {
+            label,
+            body,
+            condition,
+        } = statement
+        {
+            if Optimizer::constant_condition(condition) == Some(true) {
+                let mut exits = LoopExits {
+                    label: label.clone(),
+                    ..LoopExits::default()
+                };
+                exits.visit_block(body);
+                if !exits.found {
+                    self.warnings.push(Warning::new(
+                        WarningKind::InfiniteLoop,
+                        "Loop condition is always true and nothing in the loop leaves it, it never ends"
+                            .to_string(),
+                        Optimizer::first_position(condition),
+                    ));
+                }
+            }
+        }
+        visitor::walk_statement(self, statement);
+    }
+}
+
+// Whether a loop body can leave the loop: a break out of it, an exit, or a return
+#[derive(Default)]
+struct LoopExits {
+    label: Option<String>,
+    depth: usize, // loops nested in the body, an unlabeled break there leaves them instead
+    found: bool,
+}
+
+impl Visitor<'_> for LoopExits {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Break { label: None } if self.depth == 0 => self.found = true,
+            Statement::Break { label: Some(label) } if self.label.as_ref() == Some(label) => {
+                self.found = true
+            }
+            Statement::Exit(_) | Statement::Panic { .. } => self.found = true,
+            Statement::WhileLoop { .. } | Statement::DoWhileLoop { .. } => {
+                self.depth += 1;
+                visitor::walk_statement(self, statement);
+                self.depth -= 1;
+            }
+            // a return in a function declared in the loop leaves the function
+            Statement::FunctionDeclaration { .. } => {}
+            _ => visitor::walk_statement(self, statement),
+        }
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        if block.return_expression.is_some() {
+            self.found = true;
+        }
+        visitor::walk_block(self, block);
+    }
+}
+
+// Every name declared, assigned, called or read anywhere in the program
+#[derive(Default)]
+struct Names {
+    names: HashSet<String>,
+}
+
+impl Visitor<'_> for Names {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::VariableDeclaration { identifier, .. }
+            | Statement::Assignment { identifier, .. }
+            | Statement::IndexAssignment {
+                array: identifier, ..
+            } => {
+                self.names.insert(identifier.clone());
+            }
+            Statement::TupleDeclaration { identifiers, .. } => {
+                self.names.extend(identifiers.iter().cloned());
+            }
+            Statement::FunctionDeclaration { name, .. } => {
+                self.names.insert(name.clone());
+            }
+            _ => {}
+        }
+        visitor::walk_statement(self, statement);
+    }
+
+    fn visit_parameter(&mut self, parameter: &Parameter) {
+        self.names.insert(parameter.name.clone());
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        match expression {
+            Expression::Identifier { name, .. } | Expression::FunctionCall { name, .. } => {
+                self.names.insert(name.clone());
+            }
+            _ => {}
+        }
+        visitor::walk_expression(self, expression);
+    }
+}
+
+// Variables a loop body may change, wherever in the body the change is
+#[derive(Default)]
+struct Assigned {
+    names: HashSet<String>,
+}
+
+impl Visitor<'_> for Assigned {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::VariableDeclaration { identifier, .. }
+            | Statement::Assignment { identifier, .. }
+            | Statement::IndexAssignment {
+                array: identifier, ..
+            } => {
+                self.names.insert(identifier.clone());
+            }
+            Statement::TupleDeclaration { identifiers, .. } => {
+                self.names.extend(identifiers.iter().cloned());
+            }
+            // a nested function only assigns its own locals
+            Statement::FunctionDeclaration { .. } => return,
+            _ => {}
+        }
+        visitor::walk_statement(self, statement);
+    }
+}
+
+#[derive(Default)]
+struct Calls {
+    found: bool,
+}
+
+impl Visitor<'_> for Calls {
+    fn visit_expression(&mut self, expression: &Expression) {
+        // a sequence can assign, it can't be dropped or moved any more than a call
+        if let Expression::FunctionCall { .. } | Expression::Sequence { .. } = expression {
+            self.found = true;
+        }
+        visitor::walk_expression(self, expression);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn optimized(source: &str) -> Program {
+        Optimizer::optimize_ast(
+            Parser::new(Lexer::tokenize(source).unwrap())
+                .parse()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn an_if_on_literals_is_replaced_by_the_branch_taken() {
+        let printed =
+            |value| Program::Statements(vec![Statement::Print(Expression::Integer(value))]);
+        assert_eq!(
+            optimized("if 1 == 1 { print(1); } else { print(2); };"),
+            printed(1)
+        );
+        assert_eq!(
+            optimized("if 1 != 1 { print(1); } else { print(2); };"),
+            printed(2)
+        );
+        assert_eq!(
+            optimized("if 2 == 3 { print(1); };"),
+            Program::Statements(vec![])
+        );
+    }
+
+    #[test]
+    fn an_empty_if_keeps_a_condition_which_can_fail() {
+        for source in [
+            "this zero = 0; if 1 / zero == 1 { };",
+            "this s = \"a\"; if s == \"a\" { } else { };",
+        ] {
+            let Program::Statements(statements) = optimized(source);
+            assert!(matches!(
+                statements[..],
+                [_, Statement::FunctionCall(Expression::Comparison { .. })]
+            ));
+            assert!(crate::run(source).is_err());
+        }
+        assert_eq!(
+            optimized("this n = 1; while 1 != 1 && (2 == 2 || !(3 == 4)) { n = 2; };"),
+            optimized("this n = 1;")
+        );
+    }
+}
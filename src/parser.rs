@@ -1,15 +1,20 @@
-use crate::lexer::Token;
+use crate::lexer::{LexError, Lexer, Span, Token};
+use crate::visitor::{self, Visitor};
+use crate::warning::{Warning, WarningKind};
+use std::collections::HashMap;
+use std::fmt;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Program {
     Statements(Vec<Statement>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Statement {
     VariableDeclaration {
         identifier: String,
         value: Expression,
+        position: usize, // token position of the identifier, to point runtime errors at the source
     },
     FunctionDeclaration {
         name: String,
@@ -21,115 +26,459 @@ pub enum Statement {
     Assignment {
         identifier: String,
         value: Expression,
+        position: usize, // token position of the identifier
+    },
+    // `xs[i] = value;` replaces one element of the array in variable `array`
+    IndexAssignment {
+        array: String,
+        index: Expression,
+        value: Expression,
+        position: usize, // token position of the array's name
     },
     Print(Expression),
+    Eprint(Expression), // like print, to the error output
     IfStatement {
-        condition: Condition,
+        condition: Expression,
         then_block: Block,
         else_block: Option<Block>,
     },
+    Assert {
+        condition: Expression,
+        position: usize, // token position of the assert keyword
+    },
+    TupleDeclaration {
+        identifiers: Vec<String>,
+        value: Expression, // a tuple, or a call to a function returning one
+        position: usize,   // token position of the first identifier
+    },
+    WhileLoop {
+        label: Option<String>, // `'outer: while ...`, for breaking out of nested loops
+        condition: Expression,
+        body: Block,
+    },
+    DoWhileLoop {
+        label: Option<String>,
+        body: Block,
+        condition: Expression, // checked after every iteration, so the body runs at least once
+    },
+    Break {
+        label: Option<String>, // None breaks out of the innermost loop
+    },
+    Exit(Expression), // stops the whole program with the exit code
+    // stops the whole program with a runtime error carrying the message
+    Panic {
+        message: Expression,
+        position: usize, // token position of the panic keyword
+    },
+    Block(Block), // a bare `{ ... };`, its declarations are only visible inside it
+    Match {
+        value: Expression,
+        arms: Vec<MatchArm>,
+        default: Option<Block>, // the `_` arm, run when no pattern is equal to the value
+    },
+}
+
+// Accessors for editor tooling, like a language server
+impl Program {
+    // the top-level statements in source order
+    pub fn statements(&self) -> &[Statement] {
+        match self {
+            Program::Statements(statements) => statements,
+        }
+    }
+
+    // the first declaration of the function, nested ones included; it is the
+    // one a call reaches before any redeclaration has run
+    pub fn find_function(&self, name: &str) -> Option<&Statement> {
+        let mut finder = FunctionFinder { name, found: None };
+        finder.visit_program(self);
+        finder.found
+    }
+
+    // the identifier read at a token position
+    pub fn identifier_at(&self, position: usize) -> Option<&Expression> {
+        let mut finder = IdentifierFinder {
+            position,
+            found: None,
+        };
+        finder.visit_program(self);
+        finder.found
+    }
+
+    // the identifier under a line and column, both starting at 1, with the
+    // spans `Lexer::tokenize_with_spans` gave for the program's tokens
+    pub fn node_at(&self, spans: &[Span], line: usize, column: usize) -> Option<&Expression> {
+        let position = spans
+            .iter()
+            .rposition(|span| span.line == line && span.column <= column)?;
+        let identifier = self.identifier_at(position)?;
+        match identifier {
+            Expression::Identifier { name, .. }
+                if column < spans[position].column + name.chars().count() =>
+            {
+                Some(identifier)
+            }
+            _ => None,
+        }
+    }
+}
+
+// finds the first declaration of a function in source order
+struct FunctionFinder<'ast, 'name> {
+    name: &'name str,
+    found: Option<&'ast Statement>,
+}
+
+impl<'ast> Visitor<'ast> for FunctionFinder<'ast, '_> {
+    fn visit_statement(&mut self, statement: &'ast Statement) {
+        match statement {
+            _ if self.found.is_some() => {}
+            Statement::FunctionDeclaration { name, .. } if name == self.name => {
+                self.found = Some(statement)
+            }
+            _ => visitor::walk_statement(self, statement),
+        }
+    }
+}
+
+// finds the identifier read at a token position
+struct IdentifierFinder<'ast> {
+    position: usize,
+    found: Option<&'ast Expression>,
+}
+
+impl<'ast> Visitor<'ast> for IdentifierFinder<'ast> {
+    fn visit_expression(&mut self, expression: &'ast Expression) {
+        match expression {
+            _ if self.found.is_some() => {}
+            Expression::Identifier { position, .. } if *position == self.position => {
+                self.found = Some(expression)
+            }
+            _ => visitor::walk_expression(self, expression),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchArm {
+    pub pattern: i64,
+    pub body: Block,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Parameter {
     pub name: String,
     pub type_annotation: TypeAnnotation,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Block {
     pub statements: Vec<Statement>,
     pub return_expression: Option<Expression>,
 }
 
-#[derive(Debug, Clone)]
-pub enum Condition {
-    Comparison {
-        left: Expression,
-        operator: ComparativeOperator,
-        right: Expression,
-    },
-}
-
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Expression {
     Integer(i64),
-    Identifier(String),
+    Identifier {
+        name: String,
+        position: usize, // token position, to point runtime errors at the source
+    },
     FunctionCall {
         name: String,
         arguments: Vec<Expression>,
+        position: usize, // token position of the name
     },
     ArithmeticExpression {
         left: Box<Expression>,
         operator: Operator,
         right: Box<Expression>,
     },
+    Comparison {
+        left: Box<Expression>,
+        operator: ComparativeOperator,
+        right: Box<Expression>,
+        position: usize, // token position of the operator
+    },
+    LogicalExpression {
+        left: Box<Expression>,
+        operator: LogicalOperator,
+        right: Box<Expression>,
+    },
+    IfExpression {
+        condition: Box<Expression>,
+        then_block: Box<Block>,
+        else_block: Box<Block>,
+    },
+    String(String),
+    Not(Box<Expression>), // true when the operand is zero or false, otherwise false
+    Tuple(Vec<Expression>), // only returned from functions or destructured
+    // `(x = x + 1, x)`: the steps run in order, then the value is what it yields
+    Sequence {
+        steps: Vec<Statement>, // assignments, or expressions whose value is discarded
+        value: Box<Expression>,
+    },
+    Array(Vec<Expression>), // `[1, 2, 3]`
+    // `[0; 10]`, the count is evaluated when running and the value only once
+    ArrayRepeat {
+        value: Box<Expression>,
+        count: Box<Expression>,
+    },
+    Index {
+        array: Box<Expression>,
+        index: Box<Expression>, // from 0
+    },
+    Nil, // what a bare `return;` returns from a function without a return type
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Operator {
     Add,
     Subtract,
     Multiply,
     Divide,
+    Modulo, // truncated: the result takes the sign of the dividend
+    Power,
+}
+
+// how a chain of the same operator groups, `a - b - c` is `(a - b) - c`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Associativity {
+    Left,
+    Right,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ComparativeOperator {
     Equal,
     NotEqual,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LogicalOperator {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TypeAnnotation {
-    Int,
+    Int,                        // `int` or `i64`
+    I32,                        // arithmetic on it wraps at 32 bits
+    Tuple(Vec<TypeAnnotation>), // fixed-arity tuples of ints, only as return types
+    Array,                      // only for parameters, the elements can be anything
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    UnexpectedToken {
+        expected: String,
+        found: String,
+        pos: usize,
+    },
+    UnexpectedEof {
+        expected: String,
+    },
+    InvalidStatement {
+        pos: usize,
+    },
+    TupleArityMismatch {
+        expected: usize,
+        found: usize,
+        pos: usize,
+    },
+    ChainedComparison {
+        pos: usize,
+    },
+    TooManyErrors {
+        limit: usize,
+    },
+    Lex(LexError), // only from `parse_expression_str`, which lexes its input itself
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken {
+                expected,
+                found,
+                pos,
+            } => write!(
+                f,
+                "Expected {} at position {}, found {}",
+                expected, pos, found
+            ),
+            ParseError::UnexpectedEof { expected } => {
+                write!(f, "Expected {}, but found EOF", expected)
+            }
+            ParseError::InvalidStatement { pos } => {
+                write!(f, "Invalid statement at position {}", pos)
+            }
+            ParseError::TupleArityMismatch {
+                expected,
+                found,
+                pos,
+            } => write!(
+                f,
+                "Cannot destructure {} values into {} identifiers at position {}",
+                found, expected, pos
+            ),
+            ParseError::ChainedComparison { pos } => write!(
+                f,
+                "Chained comparisons are not supported at position {}; use && to join them",
+                pos
+            ),
+            ParseError::TooManyErrors { limit } => {
+                write!(f, "Too many errors, stopped after the first {}", limit)
+            }
+            ParseError::Lex(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<LexError> for ParseError {
+    fn from(e: LexError) -> Self {
+        ParseError::Lex(e)
+    }
+}
+
+// lex and parse a single expression, like `1 + 2 * 3` or `a == b`, without
+// the statement around it; for a REPL or quick evaluation
+pub fn parse_expression_str(input: &str) -> Result<Expression, ParseError> {
+    let tokens = Lexer::tokenize(input)?;
+    Parser::new(tokens).parse_expression_only()
+}
+
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
 pub struct Parser<'a> {
     tokens: Vec<Token<'a>>,
     pos: usize,
+    warnings: Vec<Warning>,
+    errors: Vec<ParseError>,
+    max_errors: usize, // parsing stops at the first error past this many
+    scopes: Vec<HashMap<String, usize>>, // names declared in each enclosing block, with their positions
+    functions: usize,                    // how many function bodies enclose the token being parsed
 }
 
 impl<'a> Parser<'a> {
     pub fn new(tokens: Vec<Token<'a>>) -> Self {
-        Self { tokens, pos: 0 }
+        Self {
+            tokens,
+            pos: 0,
+            warnings: vec![],
+            errors: vec![],
+            max_errors: DEFAULT_MAX_ERRORS,
+            scopes: vec![HashMap::new()],
+            functions: 0,
+        }
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
     }
 
-    pub fn parse(&mut self) -> Result<Program, String> {
+    // every error `parse` found, the one it returned first
+    pub fn errors(&self) -> &[ParseError] {
+        &self.errors
+    }
+
+    pub fn set_max_errors(&mut self, max_errors: usize) {
+        self.max_errors = max_errors.max(1);
+    }
+
+    // A top-level statement that fails to parse is skipped up to its closing `;`
+    // and parsing carries on with the next one, so one run reports the errors of
+    // every broken statement instead of only the first
+    pub fn parse(&mut self) -> Result<Program, ParseError> {
         let mut statements = Vec::new();
         while self.peek().is_some() {
-            statements.push(self.parse_statement()?);
+            // `return;` outside of any block stops the program like `exit(0);`
+            if self.peek() == Some(&Token::Return) && self.lookahead() == Some(&Token::SemiColon) {
+                self.pos += 2;
+                statements.push(Statement::Exit(Expression::Integer(0)));
+                continue;
+            }
+            let start = self.pos;
+            match self.parse_statements() {
+                Ok(parsed) => statements.extend(parsed),
+                Err(e) => {
+                    // only an error found past the limit means some went unreported
+                    if self.errors.len() == self.max_errors {
+                        self.errors.push(ParseError::TooManyErrors {
+                            limit: self.max_errors,
+                        });
+                        break;
+                    }
+                    self.errors.push(e);
+                    self.skip_statement(start);
+                }
+            }
+        }
+        match self.errors.first() {
+            Some(e) => Err(e.clone()),
+            None => Ok(Program::Statements(statements)),
         }
-        Ok(Program::Statements(statements))
     }
 
-    fn next(&mut self) {
-        self.pos += 1;
+    // moves past the `;` ending the top-level statement starting at `start`,
+    // the first one outside of brackets at or after where the error was found
+    fn skip_statement(&mut self, start: usize) {
+        let error_pos = self.pos;
+        let mut depth = 0usize;
+        self.pos = start;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::LeftBracket | Token::LeftSquare => depth += 1,
+                Token::RightBracket | Token::RightSquare => depth = depth.saturating_sub(1),
+                Token::SemiColon if depth == 0 && self.pos >= error_pos => {
+                    self.next();
+                    break;
+                }
+                _ => {}
+            }
+            self.next();
+        }
+        // blocks left open by the error never got to pop their scope
+        self.scopes.truncate(1);
+    }
+
+    // the tokens have to be exactly one expression, nothing may follow it
+    pub fn parse_expression_only(&mut self) -> Result<Expression, ParseError> {
+        let expression = self.parse_condition()?;
+        if self.peek().is_some() {
+            return Err(self.unexpected("the end of the expression"));
+        }
+        Ok(expression)
     }
 
-    fn get_current_and_next(&mut self) -> Option<&Token<'a>> {
-        let token = self.tokens.get(self.pos);
+    fn next(&mut self) {
         self.pos += 1;
-        token
     }
 
     fn peek(&self) -> Option<&Token<'a>> {
         self.tokens.get(self.pos)
     }
 
-    fn expect(&mut self, expected: Token<'a>) -> Result<(), String> {
-        if let Some(token) = self.peek() {
-            if *token == expected {
-                self.get_current_and_next();
-                Ok(())
-            } else {
-                Err(format!(
-                    "Expected {:?} at position {:?}, found {:?}",
-                    expected, self.pos, token
-                ))
-            }
+    fn expect(&mut self, expected: Token<'a>) -> Result<(), ParseError> {
+        if self.peek() == Some(&expected) {
+            self.next();
+            Ok(())
         } else {
-            Err(format!("Expected {:?}, but found EOF", expected))
+            Err(self.unexpected(&format!("{:?}", expected)))
+        }
+    }
+
+    // the error for finding the current token where `expected` should be
+    fn unexpected(&self, expected: &str) -> ParseError {
+        match self.peek() {
+            Some(token) => ParseError::UnexpectedToken {
+                expected: expected.to_string(),
+                found: format!("{:?}", token),
+                pos: self.pos,
+            },
+            None => ParseError::UnexpectedEof {
+                expected: expected.to_string(),
+            },
         }
     }
 
@@ -137,24 +486,35 @@ impl<'a> Parser<'a> {
         self.tokens.get(self.pos + 1)
     }
 
-    fn parse_statement(&mut self) -> Result<Statement, String> {
+    // a chained declaration desugars into one statement per binding,
+    // everything else parses into a single statement
+    fn parse_statements(&mut self) -> Result<Vec<Statement>, ParseError> {
+        if let Some(Token::This | Token::Let) = self.peek() {
+            let declarations = self.parse_variable_declaration()?;
+            self.expect(Token::SemiColon)?;
+            Ok(declarations)
+        } else {
+            Ok(vec![self.parse_statement()?])
+        }
+    }
+
+    fn parse_statement(&mut self) -> Result<Statement, ParseError> {
         match self.peek() {
-            Some(Token::This) => {
-                let declaration = self.parse_variable_declaration()?;
-                self.expect(Token::SemiColon)?;
-                Ok(declaration)
-            }
             Some(Token::Identifier(_)) => {
-                if self.lookahead() == Some(&Token::Equal) {
+                if self.lookahead() == Some(&Token::Equal) || self.at_index_assignment() {
                     let assignment = self.parse_assignment()?;
                     self.expect(Token::SemiColon)?;
                     Ok(assignment)
+                } else if let Some(Token::PlusPlus | Token::MinusMinus) = self.lookahead() {
+                    let step = self.parse_step()?;
+                    self.expect(Token::SemiColon)?;
+                    Ok(step)
                 } else if self.lookahead() == Some(&Token::LeftParen) {
                     let function_call = self.parse_function_call_expression()?;
                     self.expect(Token::SemiColon)?;
                     Ok(Statement::FunctionCall(function_call))
                 } else {
-                    Err("Invalid statement".to_string())
+                    Err(ParseError::InvalidStatement { pos: self.pos })
                 }
             }
             Some(Token::Func) => {
@@ -162,13 +522,80 @@ impl<'a> Parser<'a> {
                 self.expect(Token::SemiColon)?;
                 Ok(func_decl)
             }
-            Some(Token::Print) => {
+            Some(Token::Print | Token::Eprint) => {
+                let to_error_output = self.peek() == Some(&Token::Eprint);
                 self.next(); // consume the Print token
                 self.expect(Token::LeftParen)?;
-                let expression = self.parse_expression()?;
+                // a comparison is printed as true or false
+                let expression = self.parse_condition()?;
+                self.expect(Token::RightParen)?;
+                self.expect(Token::SemiColon)?;
+                if to_error_output {
+                    Ok(Statement::Eprint(expression))
+                } else {
+                    Ok(Statement::Print(expression))
+                }
+            }
+            Some(Token::Assert) => {
+                let position = self.pos;
+                self.next(); // consume the Assert token
+                self.expect(Token::LeftParen)?;
+                let condition = self.parse_condition()?;
+                self.expect(Token::RightParen)?;
+                self.expect(Token::SemiColon)?;
+                Ok(Statement::Assert {
+                    condition,
+                    position,
+                })
+            }
+            Some(Token::Label(name)) => {
+                let label = name.to_string();
+                self.next(); // consume the Label token
+                self.expect(Token::Colon)?;
+                match self.peek() {
+                    Some(Token::While) => self.parse_while_loop(Some(label)),
+                    Some(Token::Do) => self.parse_do_while_loop(Some(label)),
+                    _ => Err(self.unexpected("a while loop after the label")),
+                }
+            }
+            Some(Token::While) => self.parse_while_loop(None),
+            Some(Token::Do) => self.parse_do_while_loop(None),
+            Some(Token::Break) => {
+                self.next(); // consume the Break token
+                let mut label = None;
+                if let Some(Token::Label(name)) = self.peek() {
+                    label = Some(name.to_string());
+                    self.next(); // consume the Label token
+                }
+                self.expect(Token::SemiColon)?;
+                Ok(Statement::Break { label })
+            }
+            Some(Token::LeftBracket) => {
+                let block = self.parse_block()?;
+                self.expect(Token::SemiColon)?;
+                Ok(Statement::Block(block))
+            }
+            Some(Token::Exit) => {
+                self.next(); // consume the Exit token
+                self.expect(Token::LeftParen)?;
+                let code = self.parse_expression()?;
+                self.expect(Token::RightParen)?;
+                self.expect(Token::SemiColon)?;
+                Ok(Statement::Exit(code))
+            }
+            Some(Token::Panic) => {
+                let position = self.pos;
+                self.next(); // consume the Panic token
+                self.expect(Token::LeftParen)?;
+                let message = self.parse_expression()?;
                 self.expect(Token::RightParen)?;
                 self.expect(Token::SemiColon)?;
-                Ok(Statement::Print(expression))
+                Ok(Statement::Panic { message, position })
+            }
+            Some(Token::Match) => {
+                let match_statement = self.parse_match()?;
+                self.expect(Token::SemiColon)?;
+                Ok(match_statement)
             }
             Some(Token::If) => {
                 self.next(); // consume the If token
@@ -187,41 +614,173 @@ impl<'a> Parser<'a> {
                 })
             }
 
-            _ => Err("Invalid statement".to_string()),
+            Some(_) => Err(ParseError::InvalidStatement { pos: self.pos }),
+            None => Err(self.unexpected("a statement")),
         }
     }
 
-    fn parse_variable_declaration(&mut self) -> Result<Statement, String> {
-        self.expect(Token::This)?;
-        let name = if let Some(Token::Identifier(name)) = self.get_current_and_next() {
-            name.to_string()
+    // match x { 1 => { ... }, 2 => { ... }, _ => { ... } }
+    fn parse_match(&mut self) -> Result<Statement, ParseError> {
+        self.expect(Token::Match)?;
+        let value = self.parse_expression()?;
+        self.expect(Token::LeftBracket)?;
+        let mut arms = Vec::new();
+        let mut default = None;
+        while self.peek() != Some(&Token::RightBracket) {
+            if self.peek() == Some(&Token::Underscore) {
+                self.next(); // consume the Underscore token
+                self.expect(Token::FatArrow)?;
+                default = Some(self.parse_block()?);
+                // the default arm has to be the last one
+                if self.peek() == Some(&Token::Comma) {
+                    self.next();
+                }
+                break;
+            }
+
+            let pattern = self.parse_pattern()?;
+            self.expect(Token::FatArrow)?;
+            let body = self.parse_block()?;
+            arms.push(MatchArm { pattern, body });
+
+            if self.peek() == Some(&Token::Comma) {
+                self.next();
+            } else {
+                break;
+            }
+        }
+        self.expect(Token::RightBracket)?;
+
+        Ok(Statement::Match {
+            value,
+            arms,
+            default,
+        })
+    }
+
+    // an integer literal, optionally negative
+    fn parse_pattern(&mut self) -> Result<i64, ParseError> {
+        let sign = if self.peek() == Some(&Token::Minus) {
+            self.next(); // consume the Minus token
+            -1
         } else {
-            return Err("Expected an identifier after 'this'".to_string());
+            1
         };
+        match self.peek() {
+            Some(Token::Integer(value)) => {
+                let pattern = sign * value;
+                self.next();
+                Ok(pattern)
+            }
+            _ => Err(self.unexpected("an integer pattern")),
+        }
+    }
+
+    // this a = 1, b = 2, c = 3
+    // this q, r = divmod(10, 3)
+    // `let` can be written instead of `this`, they declare the same way
+    fn parse_variable_declaration(&mut self) -> Result<Vec<Statement>, ParseError> {
+        if let Some(Token::This | Token::Let) = self.peek() {
+            self.next(); // consume the This or Let token
+        } else {
+            return Err(self.unexpected("'this' or 'let'"));
+        }
+        if self.lookahead() == Some(&Token::Comma) {
+            return Ok(vec![self.parse_tuple_declaration()?]);
+        }
+        let mut declarations = Vec::new();
+        loop {
+            let position = self.pos;
+            let name = self.get_identifier("an identifier after 'this' or 'let'")?;
+            self.declare(&name, position);
+            self.expect(Token::Equal)?;
+            let value = self.parse_value()?;
+            declarations.push(Statement::VariableDeclaration {
+                identifier: name,
+                value,
+                position,
+            });
+
+            if let Some(Token::Comma) = self.peek() {
+                self.next();
+            } else {
+                break;
+            }
+        }
+        Ok(declarations)
+    }
+
+    fn parse_tuple_declaration(&mut self) -> Result<Statement, ParseError> {
+        let mut positions = vec![self.pos];
+        let mut identifiers = vec![self.get_identifier("an identifier after 'this' or 'let'")?];
+        while self.peek() == Some(&Token::Comma) {
+            self.next(); // consume the Comma token
+            positions.push(self.pos);
+            identifiers.push(self.get_identifier("an identifier to destructure into")?);
+        }
+        for (name, &position) in identifiers.iter().zip(&positions) {
+            self.declare(name, position);
+        }
         self.expect(Token::Equal)?;
-        let value = self.parse_expression()?;
-        Ok(Statement::VariableDeclaration {
-            identifier: name,
+
+        let position = self.pos;
+        let value = self.parse_value()?;
+        // calls are only known to return the right number of values at runtime
+        if let Expression::Tuple(elements) = &value {
+            if elements.len() != identifiers.len() {
+                return Err(ParseError::TupleArityMismatch {
+                    expected: identifiers.len(),
+                    found: elements.len(),
+                    pos: position,
+                });
+            }
+        }
+        Ok(Statement::TupleDeclaration {
+            identifiers,
             value,
+            position: positions[0],
         })
     }
 
-    fn parse_function_declaration(&mut self) -> Result<Statement, String> {
+    // warn when `this` introduces a name the same block already declared,
+    // the second declaration silently replaces the first
+    fn declare(&mut self, name: &str, position: usize) {
+        let scope = self
+            .scopes
+            .last_mut()
+            .expect("the program scope is never popped");
+        if let Some(&previous) = scope.get(name) {
+            self.warnings.push(
+                Warning::new(
+                    WarningKind::Redeclaration,
+                    format!("Variable {} is declared again", name),
+                    Some(position),
+                )
+                .with_first_declaration(previous),
+            );
+        } else {
+            scope.insert(name.to_string(), position);
+        }
+    }
+
+    fn parse_function_declaration(&mut self) -> Result<Statement, ParseError> {
         self.expect(Token::Func)?;
-        let name = self.get_identifier()?;
+        let name = self.get_identifier("a function name")?;
         self.expect(Token::LeftParen)?;
         let parameters = self.parse_parameter_list()?;
         self.expect(Token::RightParen)?;
 
         let return_type = if let Some(Token::Arrow) = self.peek() {
             self.next(); // consume the arrow
-            self.expect(Token::Identifier("int"))?;
-            Some(TypeAnnotation::Int) // Assume type annotation as Int for simplicity
+            Some(self.parse_return_type()?)
         } else {
             None
         };
 
-        let body = self.parse_block()?;
+        self.functions += 1;
+        let body = self.parse_block();
+        self.functions -= 1;
+        let body = body?;
 
         Ok(Statement::FunctionDeclaration {
             name,
@@ -231,18 +790,59 @@ impl<'a> Parser<'a> {
         })
     }
 
-    fn parse_parameter_list(&mut self) -> Result<Vec<Parameter>, String> {
+    fn parse_integer_type(&mut self) -> Result<TypeAnnotation, ParseError> {
+        let type_annotation = match self.peek() {
+            Some(Token::Identifier("int" | "i64")) => TypeAnnotation::Int,
+            Some(Token::Identifier("i32")) => TypeAnnotation::I32,
+            _ => return Err(self.unexpected("int, i64 or i32")),
+        };
+        self.next(); // consume the type name
+        Ok(type_annotation)
+    }
+
+    // an integer type, or `array` for a parameter that is indexed
+    fn parse_parameter_type(&mut self) -> Result<TypeAnnotation, ParseError> {
+        if self.peek() != Some(&Token::Identifier("array")) {
+            return self
+                .parse_integer_type()
+                .map_err(|_| self.unexpected("int, i64, i32 or array"));
+        }
+        self.next(); // consume the type name
+        Ok(TypeAnnotation::Array)
+    }
+
+    // int, or (int, int, ...) for functions returning several values
+    fn parse_return_type(&mut self) -> Result<TypeAnnotation, ParseError> {
+        if self.peek() != Some(&Token::LeftParen) {
+            return self.parse_integer_type();
+        }
+        self.next(); // consume the LeftParen token
+        let mut elements = vec![self.parse_integer_type()?];
+        // a tuple has at least two elements
+        self.expect(Token::Comma)?;
+        loop {
+            elements.push(self.parse_integer_type()?);
+            if self.peek() == Some(&Token::Comma) {
+                self.next();
+            } else {
+                break;
+            }
+        }
+        self.expect(Token::RightParen)?;
+        Ok(TypeAnnotation::Tuple(elements))
+    }
+
+    fn parse_parameter_list(&mut self) -> Result<Vec<Parameter>, ParseError> {
         let mut parameters = Vec::new();
         while let Some(Token::Identifier(name)) = self.peek() {
             let param_name = name.to_string();
             self.next();
             self.expect(Token::Colon)?;
 
-            // Assume Int for simplicity
-            self.expect(Token::Identifier("int"))?;
+            let type_annotation = self.parse_parameter_type()?;
             parameters.push(Parameter {
                 name: param_name,
-                type_annotation: TypeAnnotation::Int,
+                type_annotation,
             });
 
             // Continue with the next tokens
@@ -255,24 +855,55 @@ impl<'a> Parser<'a> {
         Ok(parameters)
     }
 
-    fn parse_block(&mut self) -> Result<Block, String> {
+    fn parse_block(&mut self) -> Result<Block, ParseError> {
         self.expect(Token::LeftBracket)?;
         let mut statements = Vec::new();
         let mut return_expression: Option<Expression> = None;
+        let mut is_unreachable_reported = false;
+        self.scopes.push(HashMap::new());
 
-        while let Some(token) = self.peek() {
-            if *token == Token::RightBracket {
-                break;
+        while self.peek().is_some() && self.peek() != Some(&Token::RightBracket) {
+            // warn once per block about code following a guaranteed return
+            let is_returned =
+                return_expression.is_some() || statements.iter().any(Self::is_always_returning);
+            if is_returned && !is_unreachable_reported {
+                self.warnings.push(Warning::new(
+                    WarningKind::UnreachableCode,
+                    "Unreachable code".to_string(),
+                    Some(self.pos),
+                ));
+                is_unreachable_reported = true;
             }
-            if *token == Token::Return {
+
+            // outside of a function, `return;` stops the program like `exit(0);`
+            // wherever it is, there is nothing to return a value to
+            if self.peek() == Some(&Token::Return) && self.functions == 0 {
+                if self.lookahead() != Some(&Token::SemiColon) {
+                    return Err(ParseError::InvalidStatement { pos: self.pos });
+                }
+                self.pos += 2;
+                statements.push(Statement::Exit(Expression::Integer(0)));
+            } else if self.peek() == Some(&Token::Return) {
                 self.next(); // consume the Return token
-                return_expression = Some(self.parse_expression()?);
+                let expression = if self.peek() == Some(&Token::SemiColon) {
+                    Expression::Nil
+                } else {
+                    self.parse_value()?
+                };
                 self.expect(Token::SemiColon)?;
-                break;
+                if return_expression.is_none() {
+                    return_expression = Some(expression);
+                }
+            } else {
+                let parsed = self.parse_statements()?;
+                // a block can't hold statements after its return, they are never run anyway
+                if return_expression.is_none() {
+                    statements.extend(parsed);
+                }
             }
-            statements.push(self.parse_statement()?);
         }
 
+        self.scopes.pop();
         self.expect(Token::RightBracket)?;
         Ok(Block {
             statements,
@@ -280,16 +911,73 @@ impl<'a> Parser<'a> {
         })
     }
 
-    fn parse_function_call_expression(&mut self) -> Result<Expression, String> {
-        let name = self.get_identifier()?;
+    fn parse_while_loop(&mut self, label: Option<String>) -> Result<Statement, ParseError> {
+        self.next(); // consume the While token
+        let condition = self.parse_condition()?;
+        let body = self.parse_block()?;
+        self.expect(Token::SemiColon)?;
+        Ok(Statement::WhileLoop {
+            label,
+            condition,
+            body,
+        })
+    }
+
+    fn parse_do_while_loop(&mut self, label: Option<String>) -> Result<Statement, ParseError> {
+        self.next(); // consume the Do token
+        let body = self.parse_block()?;
+        self.expect(Token::While)?;
+        let condition = self.parse_condition()?;
+        self.expect(Token::SemiColon)?;
+        Ok(Statement::DoWhileLoop {
+            label,
+            body,
+            condition,
+        })
+    }
+
+    // an if statement returns on every path when both of its branches do
+
+    fn is_always_returning(statement: &Statement) -> bool {
+        match statement {
+            Statement::IfStatement {
+                then_block,
+                else_block: Some(else_block),
+                ..
+            } => Self::is_block_returning(then_block) && Self::is_block_returning(else_block),
+            Statement::Match {
+                arms,
+                default: Some(default),
+                ..
+            } => {
+                arms.iter().all(|arm| Self::is_block_returning(&arm.body))
+                    && Self::is_block_returning(default)
+            }
+            Statement::Exit(_) | Statement::Panic { .. } => true,
+            Statement::Block(block) => Self::is_block_returning(block),
+            _ => false,
+        }
+    }
+
+    fn is_block_returning(block: &Block) -> bool {
+        block.return_expression.is_some() || block.statements.iter().any(Self::is_always_returning)
+    }
+
+    fn parse_function_call_expression(&mut self) -> Result<Expression, ParseError> {
+        let position = self.pos;
+        let name = self.get_identifier("a function name")?;
         self.expect(Token::LeftParen)?;
         let arguments = self.parse_argument_list()?;
         self.expect(Token::RightParen)?;
 
-        Ok(Expression::FunctionCall { name, arguments })
+        Ok(Expression::FunctionCall {
+            name,
+            arguments,
+            position,
+        })
     }
 
-    fn parse_argument_list(&mut self) -> Result<Vec<Expression>, String> {
+    fn parse_argument_list(&mut self) -> Result<Vec<Expression>, ParseError> {
         let mut arguments = Vec::new();
         while let Some(token) = self.peek() {
             if *token == Token::RightParen {
@@ -305,14 +993,19 @@ impl<'a> Parser<'a> {
         Ok(arguments)
     }
 
-    fn parse_expression(&mut self) -> Result<Expression, String> {
+    fn parse_expression(&mut self) -> Result<Expression, ParseError> {
         let left = self.parse_term()?;
 
         // process the next token
         if let Some(token) = self.peek() {
             if matches!(
                 token,
-                Token::Divide | Token::Minus | Token::Plus | Token::Multiply
+                Token::Divide
+                    | Token::Minus
+                    | Token::Plus
+                    | Token::Multiply
+                    | Token::StarStar
+                    | Token::Modulo
             ) {
                 let expression = self.parse_arithmetic_expression(left)?;
                 return Ok(expression);
@@ -322,8 +1015,25 @@ impl<'a> Parser<'a> {
         Ok(left)
     }
 
-    fn parse_term(&mut self) -> Result<Expression, String> {
-        let token = self.peek().ok_or("Unexpected end of input".to_string())?;
+    // a term followed by any number of indexes, `grid[i][j]`
+    fn parse_term(&mut self) -> Result<Expression, ParseError> {
+        let mut term = self.parse_operand()?;
+        while self.peek() == Some(&Token::LeftSquare) {
+            self.next(); // consume the LeftSquare token
+            let index = self.parse_expression()?;
+            self.expect(Token::RightSquare)?;
+            term = Expression::Index {
+                array: Box::new(term),
+                index: Box::new(index),
+            };
+        }
+        Ok(term)
+    }
+
+    fn parse_operand(&mut self) -> Result<Expression, ParseError> {
+        let token = self
+            .peek()
+            .ok_or_else(|| self.unexpected("an expression"))?;
         match token {
             Token::Integer(value) => {
                 let int_expression = Expression::Integer(*value);
@@ -338,37 +1048,280 @@ impl<'a> Parser<'a> {
                     Ok(function_call)
                 } else {
                     // It's a standalone identifier
+                    let position = self.pos;
+                    self.next();
+                    Ok(Expression::Identifier {
+                        name: identifier,
+                        position,
+                    })
+                }
+            }
+            Token::LeftParen => self.parse_sequence(),
+            // a negative literal, like the ones constant folding can produce
+            Token::Minus => match self.lookahead() {
+                Some(Token::Integer(value)) => {
+                    let int_expression = Expression::Integer(-value);
+                    self.next(); // consume the Minus token
                     self.next();
-                    Ok(Expression::Identifier(identifier))
+                    Ok(int_expression)
                 }
+                _ => Err(self.unexpected("an expression")),
+            },
+            Token::String(value) => {
+                let string_expression = Expression::String(value.clone());
+                self.next();
+                Ok(string_expression)
+            }
+            Token::If => self.parse_if_expression(),
+            Token::LeftSquare => self.parse_array(),
+            // `!` binds tighter than any binary operator, a whole condition
+            // has to be parenthesized: !(a == b)
+            Token::Not => {
+                self.next(); // consume the Not token
+                let operand = if self.peek() == Some(&Token::LeftParen) {
+                    self.next(); // consume the LeftParen token
+                    let condition = self.parse_condition()?;
+                    self.expect(Token::RightParen)?;
+                    condition
+                } else {
+                    self.parse_term()?
+                };
+                Ok(Expression::Not(Box::new(operand)))
+            }
+            _ => Err(self.unexpected("an expression")),
+        }
+    }
+
+    // `[a, b, c]`, or `[value; count]` for count copies of the value
+    fn parse_array(&mut self) -> Result<Expression, ParseError> {
+        self.expect(Token::LeftSquare)?;
+        let mut elements = vec![];
+        if self.peek() != Some(&Token::RightSquare) {
+            let first = self.parse_expression()?;
+            if self.peek() == Some(&Token::SemiColon) {
+                self.next(); // consume the SemiColon token
+                let count = self.parse_expression()?;
+                self.expect(Token::RightSquare)?;
+                return Ok(Expression::ArrayRepeat {
+                    value: Box::new(first),
+                    count: Box::new(count),
+                });
             }
-            Token::LeftParen => {
-                let expr = self.parse_expression()?;
+            elements.push(first);
+            while self.peek() == Some(&Token::Comma) {
+                self.next(); // consume the Comma token
+                elements.push(self.parse_expression()?);
+            }
+        }
+        self.expect(Token::RightSquare)?;
+        Ok(Expression::Array(elements))
+    }
+
+    // A parenthesized expression, or a sequence `(x = x + 1, y++, x + y)` whose
+    // elements are evaluated in order and which yields the last one. Elements
+    // before the last may be assignments or steps, the last is an expression,
+    // or a condition to group it: `a == 1 && (b == 2 || b == 3)`
+    fn parse_sequence(&mut self) -> Result<Expression, ParseError> {
+        self.expect(Token::LeftParen)?;
+        let mut steps = vec![];
+        loop {
+            let element = match (self.peek(), self.lookahead()) {
+                (Some(Token::Identifier(_)), Some(Token::Equal)) => self.parse_assignment()?,
+                (Some(Token::Identifier(_)), Some(Token::PlusPlus | Token::MinusMinus)) => {
+                    self.parse_step()?
+                }
+                _ => Statement::FunctionCall(self.parse_condition()?),
+            };
+            if self.peek() == Some(&Token::Comma) {
+                self.next(); // consume the Comma token
+                steps.push(element);
+                continue;
+            }
+            let Statement::FunctionCall(value) = element else {
+                return Err(self.unexpected("a value to end the sequence"));
+            };
+            self.expect(Token::RightParen)?;
+            if steps.is_empty() {
+                return Ok(value);
+            }
+            return Ok(Expression::Sequence {
+                steps,
+                value: Box::new(value),
+            });
+        }
+    }
+
+    // an expression, or a tuple `(a, b);` where several values can be taken:
+    // returned from a function or destructured by a declaration. Declarations
+    // and assignments of one name parse it too, for the semantic check to
+    // reject, rather than reading it as a sequence. A list starting with an
+    // assignment can't be a tuple, it is a sequence there too
+    fn parse_value(&mut self) -> Result<Expression, ParseError> {
+        if self.peek() == Some(&Token::LeftParen)
+            && !matches!(
+                (self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2)),
+                (
+                    Some(Token::Identifier(_)),
+                    Some(Token::Equal | Token::PlusPlus | Token::MinusMinus)
+                )
+            )
+        {
+            let start = self.pos;
+            self.next(); // consume the LeftParen token
+            let first = self.parse_expression()?;
+            if self.peek() == Some(&Token::Comma) {
+                let mut elements = vec![first];
+                while self.peek() == Some(&Token::Comma) {
+                    self.next(); // consume the Comma token
+                    elements.push(self.parse_expression()?);
+                }
                 self.expect(Token::RightParen)?;
-                Ok(expr)
+                // `(a, b) * 2` is a sequence inside an expression, not a tuple
+                if self.peek() == Some(&Token::SemiColon) {
+                    return Ok(Expression::Tuple(elements));
+                }
+            }
+            // only a parenthesized term, parse it again as part of the whole expression
+            self.pos = start;
+        }
+        self.parse_expression()
+    }
+
+    // if a == b { a } else { b }
+    fn parse_if_expression(&mut self) -> Result<Expression, ParseError> {
+        self.expect(Token::If)?;
+        let condition = self.parse_condition()?;
+        let then_block = self.parse_expression_block()?;
+        // the else branch is mandatory, an expression always yields a value
+        self.expect(Token::Else)?;
+        let else_block = self.parse_expression_block()?;
+
+        Ok(Expression::IfExpression {
+            condition: Box::new(condition),
+            then_block: Box::new(then_block),
+            else_block: Box::new(else_block),
+        })
+    }
+
+    // { this t = a * 2; t + 1 }
+    // unlike a function body, an expression block ends with a trailing expression
+    // without `return`, which is kept in `return_expression` as the block's value
+    fn parse_expression_block(&mut self) -> Result<Block, ParseError> {
+        self.expect(Token::LeftBracket)?;
+        let mut statements = Vec::new();
+        loop {
+            let is_statement = match self.peek() {
+                Some(Token::This | Token::Let | Token::Print | Token::Eprint | Token::Assert) => {
+                    true
+                }
+                Some(Token::Identifier(_)) => {
+                    self.lookahead() == Some(&Token::Equal) || self.at_index_assignment()
+                }
+                _ => false,
+            };
+            if is_statement {
+                statements.extend(self.parse_statements()?);
+                continue;
             }
-            _ => Err("Invalid term".to_string()),
+
+            let expression = self.parse_expression()?;
+            // a function call followed by a semicolon is a statement, not the value
+            if let (Expression::FunctionCall { .. }, Some(Token::SemiColon)) =
+                (&expression, self.peek())
+            {
+                self.next(); // consume the SemiColon token
+                statements.push(Statement::FunctionCall(expression));
+                continue;
+            }
+
+            self.expect(Token::RightBracket)?;
+            return Ok(Block {
+                statements,
+                return_expression: Some(expression),
+            });
         }
     }
 
-    fn parse_assignment(&mut self) -> Result<Statement, String> {
+    fn parse_assignment(&mut self) -> Result<Statement, ParseError> {
         // Parse the identifier
-        let identifier = match self.peek() {
-            Some(Token::Identifier(name)) => name.to_string(),
-            _ => return Err("Expected identifier for assignment".to_string()),
+        let position = self.pos;
+        let identifier = self.get_identifier("an identifier for assignment")?;
+        // `xs[i] = value` assigns one element
+        let index = if self.peek() == Some(&Token::LeftSquare) {
+            self.next(); // consume the LeftSquare token
+            let index = self.parse_expression()?;
+            self.expect(Token::RightSquare)?;
+            Some(index)
+        } else {
+            None
         };
-
-        self.next();
         // Expect and consume the '=' token
         self.expect(Token::Equal)?;
 
-        // Parse the expression after '='
-        let value = self.parse_expression()?;
+        // Parse the value after '=', a tuple is parsed to be reported as one
+        let value = self.parse_value()?;
+
+        match index {
+            Some(index) => Ok(Statement::IndexAssignment {
+                array: identifier,
+                index,
+                value,
+                position,
+            }),
+            None => Ok(Statement::Assignment {
+                identifier,
+                value,
+                position,
+            }),
+        }
+    }
+
+    // `xs[i] = value`, told apart from an indexing expression by the `=`
+    // after the bracket closing the index
+    fn at_index_assignment(&self) -> bool {
+        if self.lookahead() != Some(&Token::LeftSquare) {
+            return false;
+        }
+        let mut depth = 0;
+        for (offset, token) in self.tokens[self.pos + 1..].iter().enumerate() {
+            match token {
+                Token::LeftSquare => depth += 1,
+                Token::RightSquare => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return self.tokens.get(self.pos + offset + 2) == Some(&Token::Equal);
+                    }
+                }
+                _ => {}
+            }
+        }
+        false
+    }
 
-        Ok(Statement::Assignment { identifier, value })
+    // `i++` and `i--` are only statements, sugar for `i = i + 1` and `i = i - 1`
+    fn parse_step(&mut self) -> Result<Statement, ParseError> {
+        let position = self.pos;
+        let identifier = self.get_identifier("an identifier to increment")?;
+        let operator = match self.peek() {
+            Some(Token::PlusPlus) => Operator::Add,
+            _ => Operator::Subtract,
+        };
+        self.next(); // consume the PlusPlus or MinusMinus token
+        Ok(Statement::Assignment {
+            identifier: identifier.clone(),
+            value: Expression::ArithmeticExpression {
+                left: Box::new(Expression::Identifier {
+                    name: identifier,
+                    position,
+                }),
+                operator,
+                right: Box::new(Expression::Integer(1)),
+            },
+            position,
+        })
     }
 
-    fn parse_arithmetic_expression(&mut self, left: Expression) -> Result<Expression, String> {
+    fn parse_arithmetic_expression(&mut self, left: Expression) -> Result<Expression, ParseError> {
         // because we need to consume the identifier first and check on the mathematics operator
         // to know if it's a arithmetic exp
         // so the current token at this step is an operator
@@ -381,10 +1334,10 @@ impl<'a> Parser<'a> {
         &mut self,
         min_precedence: u8,
         left: Expression,
-    ) -> Result<Expression, String> {
+    ) -> Result<Expression, ParseError> {
         let mut res = left;
         while let Some(operator) = self.peek_operator() {
-            let precedence = self.operator_precedence(&operator);
+            let (precedence, associativity) = self.operator_precedence(&operator);
             if precedence < min_precedence {
                 break;
             }
@@ -392,7 +1345,13 @@ impl<'a> Parser<'a> {
             self.next(); // Consume the operator
                          // parse the current term to pass along
             let current_term = self.parse_term()?;
-            let right = self.parse_expression_with_precedence(precedence + 1, current_term)?;
+            // the right side takes the operators binding tighter, and for a right
+            // associative operator the same one again: 2 ** 3 ** 2 is 2 ** (3 ** 2)
+            let right_precedence = match associativity {
+                Associativity::Left => precedence + 1,
+                Associativity::Right => precedence,
+            };
+            let right = self.parse_expression_with_precedence(right_precedence, current_term)?;
             res = Expression::ArithmeticExpression {
                 left: Box::new(res),
                 operator,
@@ -408,39 +1367,435 @@ impl<'a> Parser<'a> {
             Some(Token::Plus) => Some(Operator::Add),
             Some(Token::Minus) => Some(Operator::Subtract),
             Some(Token::Multiply) => Some(Operator::Multiply),
+            Some(Token::StarStar) => Some(Operator::Power),
             Some(Token::Divide) => Some(Operator::Divide),
+            Some(Token::Modulo) => Some(Operator::Modulo),
             _ => None,
         }
     }
 
-    fn operator_precedence(&self, operator: &Operator) -> u8 {
+    fn operator_precedence(&self, operator: &Operator) -> (u8, Associativity) {
         match operator {
-            Operator::Multiply | Operator::Divide => 2,
-            Operator::Add | Operator::Subtract => 1,
+            Operator::Power => (3, Associativity::Right),
+            Operator::Multiply | Operator::Divide | Operator::Modulo => (2, Associativity::Left),
+            Operator::Add | Operator::Subtract => (1, Associativity::Left),
         }
     }
 
-    fn parse_condition(&mut self) -> Result<Condition, String> {
+    // conditions are boolean expressions, from the loosest binding operator:
+    // ||, then &&, then a single comparison between two arithmetic expressions
+    fn parse_condition(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_logical_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next(); // consume the Or token
+            let right = self.parse_logical_and()?;
+            left = Expression::LogicalExpression {
+                left: Box::new(left),
+                operator: LogicalOperator::Or,
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_logical_and(&mut self) -> Result<Expression, ParseError> {
+        let mut left = self.parse_comparison()?;
+        while self.peek() == Some(&Token::And) {
+            self.next(); // consume the And token
+            let right = self.parse_comparison()?;
+            left = Expression::LogicalExpression {
+                left: Box::new(left),
+                operator: LogicalOperator::And,
+                right: Box::new(right),
+            };
+        }
+        Ok(left)
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expression, ParseError> {
         let left = self.parse_expression()?;
-        let operator = match self.get_current_and_next() {
+        let operator = match self.peek() {
             Some(Token::CompareEqual) => ComparativeOperator::Equal,
             Some(Token::CompareNotEqual) => ComparativeOperator::NotEqual,
-            _ => return Err("Unsupported comparative operator".to_string()),
+            // a bare expression is true when it is not zero
+            _ => return Ok(left),
         };
+        let position = self.pos;
+        self.next(); // consume the comparative operator
         let right = self.parse_expression()?;
+        // `a == b == c` would compare a boolean with c
+        if let Some(Token::CompareEqual | Token::CompareNotEqual) = self.peek() {
+            return Err(ParseError::ChainedComparison { pos: self.pos });
+        }
 
-        Ok(Condition::Comparison {
-            left,
+        Ok(Expression::Comparison {
+            left: Box::new(left),
             operator,
-            right,
+            right: Box::new(right),
+            position,
         })
     }
 
-    fn get_identifier(&mut self) -> Result<String, String> {
-        if let Some(Token::Identifier(name)) = self.get_current_and_next() {
-            Ok(name.to_string())
+    fn get_identifier(&mut self, expected: &str) -> Result<String, ParseError> {
+        if let Some(Token::Identifier(name)) = self.peek() {
+            let name = name.to_string();
+            self.next();
+            Ok(name)
         } else {
-            Err("Expected function name".to_string())
+            Err(self.unexpected(expected))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<Program, ParseError> {
+        Parser::new(Lexer::tokenize(source)?).parse()
+    }
+
+    #[test]
+    fn too_many_errors_is_only_reported_past_the_limit() {
+        let errors = |source: &str| {
+            let mut parser = Parser::new(Lexer::tokenize(source).unwrap());
+            parser.set_max_errors(2);
+            let _ = parser.parse();
+            parser.errors().to_vec()
+        };
+        let two = errors("this a = ; this b = ; print(1);");
+        assert_eq!(two.len(), 2);
+        assert!(!two.contains(&ParseError::TooManyErrors { limit: 2 }));
+        let three = errors("this a = ; this b = ; this c = ; this d = ;");
+        assert_eq!(three.len(), 3);
+        assert_eq!(three[2], ParseError::TooManyErrors { limit: 2 });
+    }
+
+    #[test]
+    fn let_declares_like_this() {
+        let source =
+            "KEYWORD n = 1; fn f() -> int { KEYWORD m = n; return m; }; KEYWORD a, b = (1, 2);";
+        let with_this = parse(&source.replace("KEYWORD", "this"));
+        assert!(with_this.is_ok());
+        assert_eq!(parse(&source.replace("KEYWORD", "let")), with_this);
+    }
+
+    #[test]
+    fn parses_into_the_expected_ast() {
+        let expected = Program::Statements(vec![
+            Statement::VariableDeclaration {
+                identifier: "n".to_string(),
+                value: Expression::ArithmeticExpression {
+                    left: Box::new(Expression::Integer(1)),
+                    operator: Operator::Add,
+                    right: Box::new(Expression::ArithmeticExpression {
+                        left: Box::new(Expression::Integer(2)),
+                        operator: Operator::Multiply,
+                        right: Box::new(Expression::Identifier {
+                            name: "x".to_string(),
+                            position: 7,
+                        }),
+                    }),
+                },
+                position: 1,
+            },
+            Statement::Print(Expression::Not(Box::new(Expression::Identifier {
+                name: "n".to_string(),
+                position: 12,
+            }))),
+        ]);
+        assert_eq!(parse("this n = 1 + 2 * x; print(!n);"), Ok(expected));
+    }
+
+    #[test]
+    fn a_lone_expression_parses_with_precedence() {
+        assert_eq!(
+            parse_expression_str("1 + 2 * 3"),
+            Ok(Expression::ArithmeticExpression {
+                left: Box::new(Expression::Integer(1)),
+                operator: Operator::Add,
+                right: Box::new(Expression::ArithmeticExpression {
+                    left: Box::new(Expression::Integer(2)),
+                    operator: Operator::Multiply,
+                    right: Box::new(Expression::Integer(3)),
+                }),
+            })
+        );
+        assert!(matches!(
+            parse_expression_str("a == b && c != 0"),
+            Ok(Expression::LogicalExpression {
+                operator: LogicalOperator::And,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn a_lone_expression_rejects_leftover_tokens() {
+        assert!(matches!(
+            parse_expression_str("1 + 2;"),
+            Err(ParseError::UnexpectedToken { ref found, pos: 3, .. }) if found == "SemiColon"
+        ));
+        assert!(matches!(
+            parse_expression_str("1 @ 2"),
+            Err(ParseError::Lex(LexError::UnexpectedCharacter { .. }))
+        ));
+    }
+
+    #[test]
+    fn finds_a_function_by_name() {
+        let program = parse(
+            "print(1); fn outer() { fn inner() -> int { return 2; }; print(inner()); }; outer();",
+        )
+        .unwrap();
+        assert_eq!(program.statements().len(), 3);
+        assert!(matches!(
+            program.find_function("outer"),
+            Some(Statement::FunctionDeclaration { name, .. }) if name == "outer"
+        ));
+        assert!(matches!(
+            program.find_function("inner"),
+            Some(Statement::FunctionDeclaration {
+                return_type: Some(TypeAnnotation::Int),
+                ..
+            })
+        ));
+        assert_eq!(program.find_function("missing"), None);
+    }
+
+    #[test]
+    fn locates_the_identifier_under_a_cursor() {
+        let source = "this total = 1;\nprint(total + 2);";
+        let (tokens, spans) = Lexer::tokenize_with_spans(source, 4).unwrap();
+        let program = Parser::new(tokens).parse().unwrap();
+        let expected = Expression::Identifier {
+            name: "total".to_string(),
+            position: 7,
+        };
+        // `total` covers columns 7 to 11 of line 2
+        assert_eq!(program.node_at(&spans, 2, 7), Some(&expected));
+        assert_eq!(program.node_at(&spans, 2, 11), Some(&expected));
+        assert_eq!(program.node_at(&spans, 2, 12), None);
+        assert_eq!(program.node_at(&spans, 2, 15), None);
+        // a declared name is not an expression
+        assert_eq!(program.node_at(&spans, 1, 6), None);
+    }
+
+    #[test]
+    fn errors_say_what_was_expected_and_where() {
+        assert_eq!(
+            parse("print(1) print(2);"),
+            Err(ParseError::UnexpectedToken {
+                expected: "SemiColon".to_string(),
+                found: "Print".to_string(),
+                pos: 4,
+            })
+        );
+        assert_eq!(
+            parse("this n = 1"),
+            Err(ParseError::UnexpectedEof {
+                expected: "SemiColon".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("print(1); n;"),
+            Err(ParseError::InvalidStatement { pos: 5 })
+        );
+        assert!(matches!(
+            parse("this a, b = (1, 2, 3);"),
+            Err(ParseError::TupleArityMismatch {
+                expected: 2,
+                found: 3,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn code_after_a_return_warns_once() {
+        let tokens =
+            Lexer::tokenize("fn f(n: int) -> int { return n; print(n); print(n); }; print(f(1));")
+                .unwrap();
+        let mut parser = Parser::new(tokens);
+        parser.parse().unwrap();
+        let warnings = parser.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, WarningKind::UnreachableCode);
+        // the first print, after `{ return n ;`
+        assert_eq!(warnings[0].span, Some(13));
+    }
+
+    #[test]
+    fn code_after_an_if_returning_on_both_branches_warns() {
+        let tokens = Lexer::tokenize(
+            "fn f(n: int) -> int { if n == 0 { return 0; } else { return 1; }; print(n); }; print(f(1));",
+        )
+        .unwrap();
+        let mut parser = Parser::new(tokens);
+        parser.parse().unwrap();
+        assert_eq!(parser.warnings().len(), 1);
+    }
+
+    #[test]
+    fn redeclaring_in_the_same_block_warns_once_with_both_positions() {
+        let source = "this x = 1;\nx = 2;\nthis x = 3;\n{ this x = 4; };";
+        let (tokens, spans) = Lexer::tokenize_with_spans(source, 4).unwrap();
+        let mut parser = Parser::new(tokens);
+        parser.parse().unwrap();
+        let warnings = parser.warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].kind, WarningKind::Redeclaration);
+        assert_eq!(
+            warnings[0].located(&spans),
+            "Variable x is declared again (line 3, col 6), first declared at line 1, col 6"
+        );
+    }
+
+    #[test]
+    fn chained_bindings_declare_each_variable() {
+        let program = parse("this a = 1, b = a + 1, c = b * 3;").unwrap();
+        let declared: Vec<&str> = program
+            .statements()
+            .iter()
+            .map(|statement| match statement {
+                Statement::VariableDeclaration { identifier, .. } => identifier.as_str(),
+                other => panic!("expected a declaration, found {:?}", other),
+            })
+            .collect();
+        assert_eq!(declared, vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn chained_bindings_are_usable() {
+        let bytecode = crate::compile("this a = 1, b = a + 1, c = b * 3;").unwrap();
+        let mut vm = crate::virtual_machine::VirtualMachine::new(
+            bytecode.opcodes,
+            bytecode.constants,
+            bytecode.symbols,
+        );
+        vm.run().unwrap();
+        assert_eq!(vm.global("c"), Some(crate::virtual_machine::Value::Int(6)));
+    }
+
+    #[test]
+    fn chained_bindings_need_a_value_each() {
+        assert!(parse("this a = 1, b;").is_err());
+    }
+
+    #[test]
+    fn a_value_returned_outside_of_a_function_is_rejected() {
+        assert_eq!(
+            parse("this x = 1; if x == 1 { return x; };"),
+            Err(ParseError::InvalidStatement { pos: 10 })
+        );
+        let Ok(Program::Statements(statements)) = parse("if 1 == 1 { return; };") else {
+            panic!("a bare return parses");
+        };
+        let Statement::IfStatement { then_block, .. } = &statements[0] else {
+            panic!("not an if: {:?}", statements[0]);
+        };
+        assert_eq!(
+            then_block.statements,
+            vec![Statement::Exit(Expression::Integer(0))]
+        );
+    }
+
+    #[test]
+    fn a_tuple_followed_by_an_operator_is_a_sequence() {
+        let Ok(Program::Statements(statements)) = parse("this x = 1; this y = (x, 5) * 2;") else {
+            panic!("the declaration parses");
+        };
+        assert!(matches!(
+            &statements[1],
+            Statement::VariableDeclaration {
+                value: Expression::ArithmeticExpression {
+                    left,
+                    operator: Operator::Multiply,
+                    ..
+                },
+                ..
+            } if matches!(left.as_ref(), Expression::Sequence { steps, .. } if steps.len() == 1)
+        ));
+
+        let Ok(Program::Statements(statements)) = parse(
+            "fn f(a: int) -> int { return (a, 5) * 2; }; fn g() -> (int, int) { return (1, 2); };",
+        ) else {
+            panic!("the returns parse");
+        };
+        let returned = |statement: &Statement| match statement {
+            Statement::FunctionDeclaration { body, .. } => body.return_expression.clone(),
+            _ => None,
+        };
+        assert!(matches!(
+            returned(&statements[0]),
+            Some(Expression::ArithmeticExpression { .. })
+        ));
+        assert!(matches!(
+            returned(&statements[1]),
+            Some(Expression::Tuple(elements)) if elements.len() == 2
+        ));
+    }
+
+    // the expression with every logical operation and comparison in parentheses
+    fn grouped(expression: &Expression) -> String {
+        match expression {
+            Expression::LogicalExpression {
+                left,
+                operator,
+                right,
+            } => format!(
+                "({} {} {})",
+                grouped(left),
+                if *operator == LogicalOperator::And {
+                    "&&"
+                } else {
+                    "||"
+                },
+                grouped(right)
+            ),
+            Expression::Comparison {
+                left,
+                operator,
+                right,
+                ..
+            } => format!(
+                "({} {} {})",
+                grouped(left),
+                if *operator == ComparativeOperator::Equal {
+                    "=="
+                } else {
+                    "!="
+                },
+                grouped(right)
+            ),
+            Expression::Identifier { name, .. } => name.clone(),
+            Expression::Integer(value) => value.to_string(),
+            other => panic!("unexpected operand {:?}", other),
         }
     }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(
+            grouped(&parse_expression_str("a == 1 || b == 2 && c == 3").unwrap()),
+            "((a == 1) || ((b == 2) && (c == 3)))"
+        );
+        assert_eq!(
+            grouped(&parse_expression_str("a == 1 && b == 2 || c == 3").unwrap()),
+            "(((a == 1) && (b == 2)) || (c == 3))"
+        );
+    }
+
+    #[test]
+    fn conditions_can_be_grouped_with_parentheses() {
+        assert_eq!(
+            grouped(&parse_expression_str("a == 1 && (b == 3 || b == 2)").unwrap()),
+            "((a == 1) && ((b == 3) || (b == 2)))"
+        );
+        assert_eq!(
+            grouped(&parse_expression_str("(a == 1) && (b == 2)").unwrap()),
+            "((a == 1) && (b == 2))"
+        );
+        assert!(parse("this a = 1; if (a == 1) { print(a); };").is_ok());
+    }
 }
@@ -0,0 +1,509 @@
+use crate::parser::{
+    Block, ComparativeOperator, Expression, LogicalOperator, Operator, Parameter, Program,
+    Statement, TypeAnnotation,
+};
+
+const INDENT: &str = "    ";
+
+pub struct PrettyPrinter {
+    output: String,
+    indent: usize,
+}
+
+impl PrettyPrinter {
+    // render the AST back into source code which parses into an equivalent AST
+    pub fn to_source(program: &Program) -> String {
+        let mut printer = Self {
+            output: String::new(),
+            indent: 0,
+        };
+        match program {
+            Program::Statements(statements) => {
+                for statement in statements {
+                    printer.print_statement(statement);
+                }
+            }
+        }
+        printer.output
+    }
+
+    // a single expression, as it would be written in a statement
+    pub fn expression_to_source(expression: &Expression) -> String {
+        Self::expression(expression)
+    }
+
+    fn print_statement(&mut self, statement: &Statement) {
+        let line = match statement {
+            Statement::VariableDeclaration {
+                identifier, value, ..
+            } => {
+                format!("this {} = {};", identifier, Self::expression(value))
+            }
+            Statement::FunctionDeclaration {
+                name,
+                parameters,
+                return_type,
+                body,
+            } => {
+                let return_type = match return_type {
+                    Some(type_annotation) => {
+                        format!(" -> {}", Self::type_annotation(type_annotation))
+                    }
+                    None => String::new(),
+                };
+                self.print_line(&format!(
+                    "fn {}({}){} {{",
+                    name,
+                    Self::parameters(parameters),
+                    return_type
+                ));
+                self.print_block(body);
+                "};".to_string()
+            }
+            Statement::DoWhileLoop {
+                label,
+                body,
+                condition,
+            } => {
+                let label = match label {
+                    Some(label) => format!("'{}: ", label),
+                    None => String::new(),
+                };
+                self.print_line(&format!("{}do {{", label));
+                self.print_block(body);
+                format!("}} while {};", Self::expression(condition))
+            }
+            Statement::FunctionCall(expression) => format!("{};", Self::expression(expression)),
+            Statement::Assignment {
+                identifier, value, ..
+            } => {
+                format!("{} = {};", identifier, Self::expression(value))
+            }
+            Statement::IndexAssignment {
+                array,
+                index,
+                value,
+                ..
+            } => format!(
+                "{}[{}] = {};",
+                array,
+                Self::expression(index),
+                Self::expression(value)
+            ),
+            Statement::Print(expression) => format!("print({});", Self::expression(expression)),
+            Statement::Eprint(expression) => format!("eprint({});", Self::expression(expression)),
+            Statement::IfStatement {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                self.print_line(&format!("if {} {{", Self::expression(condition)));
+                self.print_block(then_block);
+                if let Some(else_block) = else_block {
+                    self.print_line("} else {");
+                    self.print_block(else_block);
+                }
+                "};".to_string()
+            }
+            Statement::Assert { condition, .. } => {
+                format!("assert({});", Self::expression(condition))
+            }
+            Statement::WhileLoop {
+                label,
+                condition,
+                body,
+            } => {
+                let label = match label {
+                    Some(label) => format!("'{}: ", label),
+                    None => String::new(),
+                };
+                self.print_line(&format!(
+                    "{}while {} {{",
+                    label,
+                    Self::expression(condition)
+                ));
+                self.print_block(body);
+                "};".to_string()
+            }
+            Statement::Break { label: Some(label) } => format!("break '{};", label),
+            Statement::Break { label: None } => "break;".to_string(),
+            Statement::Exit(code) => format!("exit({});", Self::expression(code)),
+            Statement::Panic { message, .. } => format!("panic({});", Self::expression(message)),
+            Statement::Block(block) => {
+                self.print_line("{");
+                self.print_block(block);
+                "};".to_string()
+            }
+            Statement::Match {
+                value,
+                arms,
+                default,
+            } => {
+                self.print_line(&format!("match {} {{", Self::expression(value)));
+                self.indent += 1;
+                for arm in arms {
+                    self.print_line(&format!("{} => {{", arm.pattern));
+                    self.print_block(&arm.body);
+                    self.print_line("},");
+                }
+                if let Some(default) = default {
+                    self.print_line("_ => {");
+                    self.print_block(default);
+                    self.print_line("},");
+                }
+                self.indent -= 1;
+                "};".to_string()
+            }
+            Statement::TupleDeclaration {
+                identifiers, value, ..
+            } => {
+                format!(
+                    "this {} = {};",
+                    identifiers.join(", "),
+                    Self::expression(value)
+                )
+            }
+        };
+        self.print_line(&line);
+    }
+
+    fn print_block(&mut self, block: &Block) {
+        self.indent += 1;
+        for statement in &block.statements {
+            self.print_statement(statement);
+        }
+        match &block.return_expression {
+            Some(Expression::Nil) => self.print_line("return;"),
+            Some(return_expression) => {
+                self.print_line(&format!("return {};", Self::expression(return_expression)))
+            }
+            None => {}
+        }
+        self.indent -= 1;
+    }
+
+    fn print_line(&mut self, line: &str) {
+        self.output.push_str(&INDENT.repeat(self.indent));
+        self.output.push_str(line);
+        self.output.push('\n');
+    }
+
+    fn parameters(parameters: &[Parameter]) -> String {
+        parameters
+            .iter()
+            .map(|parameter| {
+                format!(
+                    "{}: {}",
+                    parameter.name,
+                    Self::type_annotation(&parameter.type_annotation)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn type_annotation(type_annotation: &TypeAnnotation) -> String {
+        match type_annotation {
+            TypeAnnotation::Int => "int".to_string(),
+            TypeAnnotation::I32 => "i32".to_string(),
+            TypeAnnotation::Array => "array".to_string(),
+            TypeAnnotation::Tuple(elements) => format!(
+                "({})",
+                elements
+                    .iter()
+                    .map(Self::type_annotation)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    // quoted, with the escapes the lexer understands
+    fn string(value: &str) -> String {
+        let mut quoted = String::from('"');
+        for c in value.chars() {
+            match c {
+                '"' => quoted.push_str("\\\""),
+                '\\' => quoted.push_str("\\\\"),
+                '\n' => quoted.push_str("\\n"),
+                '\t' => quoted.push_str("\\t"),
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        quoted
+    }
+
+    fn expression(expression: &Expression) -> String {
+        match expression {
+            Expression::Integer(value) => value.to_string(),
+            Expression::String(value) => Self::string(value),
+            // only ever returned, print_block writes it as a bare `return;`
+            Expression::Nil => String::new(),
+            Expression::Identifier { name, .. } => name.clone(),
+            Expression::FunctionCall {
+                name, arguments, ..
+            } => format!(
+                "{}({})",
+                name,
+                arguments
+                    .iter()
+                    .map(Self::expression)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Expression::ArithmeticExpression {
+                left,
+                operator,
+                right,
+            } => Self::binary(expression, left, Self::operator(operator), right),
+            Expression::Comparison {
+                left,
+                operator,
+                right,
+                ..
+            } => {
+                let operator = match operator {
+                    ComparativeOperator::Equal => "==",
+                    ComparativeOperator::NotEqual => "!=",
+                };
+                Self::binary(expression, left, operator, right)
+            }
+            Expression::LogicalExpression {
+                left,
+                operator,
+                right,
+            } => {
+                let operator = match operator {
+                    LogicalOperator::And => "&&",
+                    LogicalOperator::Or => "||",
+                };
+                Self::binary(expression, left, operator, right)
+            }
+            Expression::IfExpression {
+                condition,
+                then_block,
+                else_block,
+            } => format!(
+                "if {} {} else {}",
+                Self::expression(condition),
+                Self::expression_block(then_block),
+                Self::expression_block(else_block)
+            ),
+            Expression::Not(operand) => format!("!{}", Self::operand(operand, u8::MAX)),
+            Expression::Tuple(elements) => format!(
+                "({})",
+                elements
+                    .iter()
+                    .map(Self::expression)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Expression::Sequence { steps, value } => Self::sequence(steps, value),
+            Expression::Array(elements) => format!(
+                "[{}]",
+                elements
+                    .iter()
+                    .map(Self::expression)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Expression::ArrayRepeat { value, count } => {
+                format!("[{}; {}]", Self::expression(value), Self::expression(count))
+            }
+            Expression::Index { array, index } => format!(
+                "{}[{}]",
+                Self::operand(array, u8::MAX),
+                Self::expression(index)
+            ),
+        }
+    }
+
+    // a list starting with an expression would be read back as a tuple where
+    // one can be taken, the extra parentheses keep it a sequence everywhere
+    fn sequence(steps: &[Statement], value: &Expression) -> String {
+        let mut elements = steps
+            .iter()
+            .map(|step| match step {
+                Statement::Assignment {
+                    identifier, value, ..
+                } => {
+                    format!("{} = {}", identifier, Self::expression(value))
+                }
+                Statement::FunctionCall(expression) => Self::expression(expression),
+                _ => unreachable!("Sequence step {:?}", step),
+            })
+            .collect::<Vec<_>>();
+        elements.push(Self::expression(value));
+        let sequence = format!("({})", elements.join(", "));
+        match steps.first() {
+            Some(Statement::Assignment { .. }) => sequence,
+            _ => format!("({})", sequence),
+        }
+    }
+
+    // expression blocks are rendered on a single line ending with their value
+    fn expression_block(block: &Block) -> String {
+        let mut printer = Self {
+            output: String::new(),
+            indent: 0,
+        };
+        for statement in &block.statements {
+            printer.print_statement(statement);
+        }
+        if let Some(value) = &block.return_expression {
+            printer.print_line(&Self::expression(value));
+        }
+        format!(
+            "{{ {} }}",
+            printer.output.lines().collect::<Vec<_>>().join(" ")
+        )
+    }
+
+    fn binary(
+        parent: &Expression,
+        left: &Expression,
+        operator: &str,
+        right: &Expression,
+    ) -> String {
+        let precedence = Self::precedence(parent);
+        // an equal precedence operand on the side the operator doesn't group
+        // from needs parentheses to keep its grouping, ** groups from the right
+        let (left, right) = match parent {
+            Expression::ArithmeticExpression {
+                operator: Operator::Power,
+                ..
+            } => (
+                Self::operand(left, precedence + 1),
+                Self::operand(right, precedence),
+            ),
+            _ => (
+                Self::operand(left, precedence),
+                Self::operand(right, precedence + 1),
+            ),
+        };
+        format!("{} {} {}", left, operator, right)
+    }
+
+    fn operand(expression: &Expression, min_precedence: u8) -> String {
+        if Self::precedence(expression) < min_precedence {
+            format!("({})", Self::expression(expression))
+        } else {
+            Self::expression(expression)
+        }
+    }
+
+    fn operator(operator: &Operator) -> &'static str {
+        match operator {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Modulo => "%",
+            Operator::Power => "**",
+        }
+    }
+
+    fn precedence(expression: &Expression) -> u8 {
+        match expression {
+            Expression::LogicalExpression {
+                operator: LogicalOperator::Or,
+                ..
+            } => 1,
+            Expression::LogicalExpression {
+                operator: LogicalOperator::And,
+                ..
+            } => 2,
+            Expression::Comparison { .. } => 3,
+            Expression::ArithmeticExpression {
+                operator: Operator::Add | Operator::Subtract,
+                ..
+            } => 4,
+            Expression::ArithmeticExpression {
+                operator: Operator::Power,
+                ..
+            } => 6,
+            Expression::ArithmeticExpression { .. } => 5,
+            _ => u8::MAX,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::fs;
+
+    fn parse(source: &str) -> Option<Program> {
+        let tokens = Lexer::tokenize(source).ok()?;
+        Parser::new(tokens).parse().ok()
+    }
+
+    // the AST's Debug output with every token position set to 0, since
+    // printed source puts tokens at other positions than the original
+    fn without_positions(program: &Program) -> String {
+        let debug = format!("{:?}", program);
+        let mut output = String::new();
+        let mut rest = debug.as_str();
+        while let Some(start) = rest.find("position: ") {
+            let (before, after) = rest.split_at(start + "position: ".len());
+            output.push_str(before);
+            output.push('0');
+            rest = after.trim_start_matches(|c: char| c.is_ascii_digit());
+        }
+        output.push_str(rest);
+        output
+    }
+
+    fn assert_round_trip(source: &str) {
+        let program = parse(source).expect("the source parses");
+        let printed = PrettyPrinter::to_source(&program);
+        let reparsed =
+            parse(&printed).unwrap_or_else(|| panic!("the printed source parses:\n{}", printed));
+        assert_eq!(
+            without_positions(&reparsed),
+            without_positions(&program),
+            "printed as:\n{}",
+            printed
+        );
+    }
+
+    #[test]
+    fn desugared_decrement_round_trips() {
+        assert_round_trip("this m = 3; m--; m++;");
+    }
+
+    #[test]
+    fn tuples_round_trip_without_extra_parentheses() {
+        let program = parse("this t = (1, 2);").unwrap();
+        assert_eq!(PrettyPrinter::to_source(&program), "this t = (1, 2);\n");
+        assert_round_trip("fn pair() -> (int, int) { return (1, 2); }; this a, b = pair();");
+    }
+
+    #[test]
+    fn sequences_round_trip() {
+        assert_round_trip(
+            "fn show(n: int) -> int { return n; }; this x = 1; this y = (x = x + 1, x * 2); \
+             print((x++, x * 10)); print((show(7), show(8), 9));",
+        );
+    }
+
+    #[test]
+    fn sample_programs_round_trip() {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/src/test_files");
+        for entry in fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            // the expected outputs sit in a directory of their own
+            if !path.is_file() {
+                continue;
+            }
+            let source = fs::read_to_string(&path).unwrap();
+            // samples showing parse errors have nothing to print
+            if parse(&source).is_some() {
+                assert_round_trip(&source);
+            }
+        }
+    }
+}
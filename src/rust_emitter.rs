@@ -0,0 +1,192 @@
+use crate::code_generator::OpCode;
+use crate::interner::{Interner, Symbol};
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+pub enum EmitError {
+    Unsupported { opcode: &'static str, index: usize },
+}
+
+impl fmt::Display for EmitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmitError::Unsupported { opcode, index } => write!(
+                f,
+                "Cannot emit {} at instruction {} as Rust, only arithmetic, print and global variables are supported",
+                opcode, index
+            ),
+        }
+    }
+}
+
+// Translate the opcodes into a standalone Rust program doing the same thing:
+// each instruction becomes a statement over a stack of i64, and each global a
+// local of the generated `main`. Only straight-line code is covered, so
+// programs with functions, branches, loops, strings or comparisons can't be
+// emitted. Arithmetic wraps around like the VM's default mode
+pub fn emit_rust(
+    opcodes: &[OpCode],
+    constants: &[i64],
+    symbols: &Interner,
+) -> Result<String, EmitError> {
+    let mut globals = BTreeSet::new();
+    let mut body = String::new();
+    for (index, opcode) in opcodes.iter().enumerate() {
+        let statement = match opcode {
+            OpCode::PUSHC(constant) => format!("stack.push({});", literal(constants[*constant])),
+            OpCode::POP => "stack.pop();".to_string(),
+            OpCode::DUP => "stack.push(stack[stack.len() - 1]);".to_string(),
+            OpCode::PRINT => "println!(\"{}\", stack.pop().unwrap());".to_string(),
+            OpCode::PRINTERR => "eprintln!(\"{}\", stack.pop().unwrap());".to_string(),
+            OpCode::ADD => binary("a.wrapping_add(b)"),
+            OpCode::SUB => binary("a.wrapping_sub(b)"),
+            OpCode::MUL => binary("a.wrapping_mul(b)"),
+            OpCode::DIV => binary("{ assert!(b != 0, \"Division by zero\"); a.wrapping_div(b) }"),
+            OpCode::MOD => binary("{ assert!(b != 0, \"Division by zero\"); a.wrapping_rem(b) }"),
+            // by squaring, i64::wrapping_pow only takes a u32 exponent
+            OpCode::POW => binary(
+                "{ assert!(b >= 0, \"Negative exponent\"); let (mut result, mut a, mut b) = (1i64, a, b); \
+                 while b > 0 { if b & 1 == 1 { result = result.wrapping_mul(a); } a = a.wrapping_mul(a); b >>= 1; } result }",
+            ),
+            OpCode::MIN => binary("a.min(b)"),
+            OpCode::MAX => binary("a.max(b)"),
+            OpCode::WADD => binary("a.wrapping_add(b)"),
+            OpCode::WMUL => binary("a.wrapping_mul(b)"),
+            OpCode::ABS => {
+                "let a = stack.pop().unwrap(); stack.push(a.wrapping_abs());".to_string()
+            }
+            OpCode::WRAP32 => {
+                "let a = stack.pop().unwrap(); stack.push(a as i32 as i64);".to_string()
+            }
+            OpCode::STORE(symbol) => {
+                let name = global(symbols, *symbol, &mut globals);
+                format!("{} = stack.pop().unwrap();", name)
+            }
+            OpCode::STOREI(symbol, value) => {
+                let name = global(symbols, *symbol, &mut globals);
+                format!("{} = {};", name, literal(*value))
+            }
+            OpCode::LOAD(symbol) => {
+                let name = global(symbols, *symbol, &mut globals);
+                format!("stack.push({});", name)
+            }
+            OpCode::INCR(symbol, step) => {
+                let name = global(symbols, *symbol, &mut globals);
+                format!("{} = {}.wrapping_add({});", name, name, literal(*step))
+            }
+            other => {
+                return Err(EmitError::Unsupported {
+                    opcode: other.name(),
+                    index,
+                })
+            }
+        };
+        body.push_str(&format!("    {{ {} }}\n", statement));
+    }
+
+    let mut output = String::from("// generated by simple_compiler\n");
+    output.push_str("#[allow(unused_mut, unused_assignments, unused_variables, non_snake_case)]\n");
+    output.push_str("fn main() {\n");
+    output.push_str("    let mut stack: Vec<i64> = Vec::new();\n");
+    for name in &globals {
+        output.push_str(&format!("    let mut {}: i64 = 0;\n", name));
+    }
+    output.push_str(&body);
+    output.push_str("}\n");
+    Ok(output)
+}
+
+// the operands are popped in reverse, the right one is on top
+fn binary(result: &str) -> String {
+    format!(
+        "let b = stack.pop().unwrap(); let a = stack.pop().unwrap(); stack.push({});",
+        result
+    )
+}
+
+// `-9223372036854775808i64` negates a literal out of the i64 range
+fn literal(value: i64) -> String {
+    match value {
+        i64::MIN => "i64::MIN".to_string(),
+        value => format!("{}i64", value),
+    }
+}
+
+// the prefix keeps names clear of Rust's keywords. Source identifiers are
+// letters, with `#` and a number added for a variable shadowed in a bare block
+// (`x#1`): `#` becomes `_` and other letters than ASCII `_u` and their code
+// point, neither of which a source identifier can contain, so names can't collide
+fn global(symbols: &Interner, symbol: Symbol, globals: &mut BTreeSet<String>) -> String {
+    let mut name = String::from("var_");
+    for character in symbols.resolve(symbol).chars() {
+        match character {
+            character if character.is_ascii_alphanumeric() => name.push(character),
+            '#' => name.push('_'),
+            character => name.push_str(&format!("_u{:x}_", character as u32)),
+        }
+    }
+    globals.insert(name.clone());
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process::Command;
+
+    fn emit(source: &str) -> Result<String, EmitError> {
+        let bytecode = crate::compile(source).unwrap();
+        emit_rust(&bytecode.opcodes, &bytecode.constants, &bytecode.symbols)
+    }
+
+    #[test]
+    fn generated_rust_compiles_and_prints_like_the_vm() {
+        let source = "this width = 7; this height = width * 3 - 4; print(width * height); \
+                      { this width = 2; print(width); }; print(9223372036854775807 + height);";
+        let bytecode = crate::compile(source).unwrap();
+        let rust = emit_rust(&bytecode.opcodes, &bytecode.constants, &bytecode.symbols).unwrap();
+
+        let mut vm = crate::virtual_machine::VirtualMachine::new(
+            bytecode.opcodes,
+            bytecode.constants,
+            bytecode.symbols,
+        );
+        vm.run().unwrap();
+        let height = match vm.global("height") {
+            Some(crate::virtual_machine::Value::Int(height)) => height,
+            other => panic!("height is {:?}", other),
+        };
+        let expected = format!("{}\n2\n{}\n", 7 * height, i64::MAX.wrapping_add(height));
+
+        let directory = std::env::temp_dir().join(format!("emit_rust_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let source_file = directory.join("program.rs");
+        let binary = directory.join("program");
+        fs::write(&source_file, rust).unwrap();
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let compiled = Command::new(rustc)
+            .arg(&source_file)
+            .arg("-o")
+            .arg(&binary)
+            .output()
+            .unwrap();
+        assert!(
+            compiled.status.success(),
+            "{}",
+            String::from_utf8_lossy(&compiled.stderr)
+        );
+        let run = Command::new(&binary).output().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(String::from_utf8(run.stdout).unwrap(), expected);
+    }
+
+    #[test]
+    fn branches_are_not_emitted() {
+        assert!(matches!(
+            emit("this n = 1; while n != 3 { n = n + 1; };"),
+            Err(EmitError::Unsupported { .. })
+        ));
+    }
+}
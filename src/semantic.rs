@@ -0,0 +1,968 @@
+use crate::code_generator::CodeGenerator;
+use crate::parser::{Block, Expression, Parameter, Program, Statement, TypeAnnotation};
+use crate::visitor::{self, Visitor};
+use crate::warning::{Warning, WarningKind};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum SemanticError {
+    UndeclaredAssignment {
+        name: String,
+        function: Option<String>, // None at the top level
+    },
+    ReservedName {
+        name: String,
+    },
+    BuiltinArity {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    BreakOutsideLoop,
+    UndefinedLabel {
+        label: String,
+    },
+    ReturnTypeMismatch {
+        function: String,
+        first: ValueKind,
+        second: ValueKind,
+    },
+    UseBeforeAssignment {
+        name: String,
+        function: String,
+    },
+    TupleAsValue {
+        values: usize,
+    },
+    DestructureMismatch {
+        identifiers: usize,
+        values: usize,
+    },
+    ReturnArityMismatch {
+        function: String,
+        expected: usize,
+        found: usize,
+    },
+    MissingReturnValue {
+        function: String, // declared with a return type, but with a bare `return;`
+    },
+    IndexedInteger {
+        name: String,
+        function: String,
+    },
+}
+
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SemanticError::UndeclaredAssignment { name, function } => {
+                write!(f, "Assignment to undeclared variable {}", name)?;
+                if let Some(function) = function {
+                    write!(f, " in function {}", function)?;
+                }
+                write!(f, ", declare it first with `this {} = ...;`", name)
+            }
+            SemanticError::ReservedName { name } => {
+                write!(f, "Cannot declare function {}, the name is a builtin", name)
+            }
+            SemanticError::BuiltinArity {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Builtin {} takes {} argument(s), but {} were given",
+                name, expected, found
+            ),
+            SemanticError::BreakOutsideLoop => write!(f, "Cannot break outside of a while loop"),
+            SemanticError::UndefinedLabel { label } => {
+                write!(
+                    f,
+                    "Cannot break '{}, no enclosing loop has that label",
+                    label
+                )
+            }
+            SemanticError::ReturnTypeMismatch {
+                function,
+                first,
+                second,
+            } => write!(
+                f,
+                "Function {} returns {} and {}, without a return type it has to return one kind of value",
+                function, first, second
+            ),
+            SemanticError::UseBeforeAssignment { name, function } => write!(
+                f,
+                "Variable {} is read in function {} before it is assigned on every path to the read",
+                name, function
+            ),
+            SemanticError::TupleAsValue { values } => write!(
+                f,
+                "A tuple of {} values is used where a single value is expected, destructure it with `this a, b = ...;`",
+                values
+            ),
+            SemanticError::DestructureMismatch {
+                identifiers,
+                values,
+            } => write!(
+                f,
+                "Cannot destructure {} value(s) into {} identifiers",
+                values, identifiers
+            ),
+            SemanticError::ReturnArityMismatch {
+                function,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Function {} returns {} value(s), but its return type has {}",
+                function, found, expected
+            ),
+            SemanticError::MissingReturnValue { function } => write!(
+                f,
+                "Function {} has a return type, so it can't return without a value",
+                function
+            ),
+            SemanticError::IndexedInteger { name, function } => write!(
+                f,
+                "Parameter {} of function {} is an int and can't be indexed, annotate it `{}: array`",
+                name, function, name
+            ),
+        }
+    }
+}
+
+// What a returned value clearly is, from its expression alone
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueKind {
+    Int,
+    String,
+    Tuple(usize),
+    Nil, // a bare `return;`
+}
+
+impl fmt::Display for ValueKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueKind::Int => write!(f, "an int"),
+            ValueKind::String => write!(f, "a string"),
+            ValueKind::Tuple(size) => write!(f, "a tuple of {}", size),
+            ValueKind::Nil => write!(f, "nothing"),
+        }
+    }
+}
+
+// The return type of a function declared without one: int when everything it
+// clearly returns is an int, a tuple of ints when it is a tuple. Parameters are
+// ints, but values that could be anything, like other variables or calls to
+// functions, don't decide it
+pub fn inferred_return_type(parameters: &[Parameter], body: &Block) -> Option<TypeAnnotation> {
+    let kinds = returned_kinds(parameters, body);
+    match kinds.first() {
+        Some(kind) if kinds.iter().any(|other| other != kind) => None,
+        Some(ValueKind::Int) => Some(TypeAnnotation::Int),
+        Some(ValueKind::Tuple(size)) => {
+            Some(TypeAnnotation::Tuple(vec![TypeAnnotation::Int; *size]))
+        }
+        Some(ValueKind::String | ValueKind::Nil) | None => None,
+    }
+}
+
+// the first two kinds of value a function body returns which differ
+fn mismatched_returns(parameters: &[Parameter], body: &Block) -> Option<(ValueKind, ValueKind)> {
+    let mut kinds = returned_kinds(parameters, body).into_iter();
+    let first = kinds.next()?;
+    kinds
+        .find(|kind| *kind != first)
+        .map(|second| (first, second))
+}
+
+fn returned_kinds(parameters: &[Parameter], body: &Block) -> Vec<ValueKind> {
+    let mut values = vec![];
+    returned_values(body, &mut values);
+    values
+        .into_iter()
+        .filter_map(|value| value_kind(value, parameters))
+        .collect()
+}
+
+// the return of every block of a function body, not the value of an if
+// expression or the returns of the functions declared in it
+fn returned_values<'a>(block: &'a Block, values: &mut Vec<&'a Expression>) {
+    for statement in &block.statements {
+        match statement {
+            Statement::IfStatement {
+                then_block,
+                else_block,
+                ..
+            } => {
+                returned_values(then_block, values);
+                if let Some(else_block) = else_block {
+                    returned_values(else_block, values);
+                }
+            }
+            Statement::WhileLoop { body, .. }
+            | Statement::DoWhileLoop { body, .. }
+            | Statement::Block(body) => returned_values(body, values),
+            Statement::Match { arms, default, .. } => {
+                for arm in arms {
+                    returned_values(&arm.body, values);
+                }
+                if let Some(default) = default {
+                    returned_values(default, values);
+                }
+            }
+            _ => {}
+        }
+    }
+    values.extend(&block.return_expression);
+}
+
+fn value_kind(expression: &Expression, parameters: &[Parameter]) -> Option<ValueKind> {
+    let kind = |expression| value_kind(expression, parameters);
+    match expression {
+        Expression::Integer(_) | Expression::Comparison { .. } | Expression::Not(_) => {
+            Some(ValueKind::Int)
+        }
+        Expression::String(_) => Some(ValueKind::String),
+        Expression::Nil => Some(ValueKind::Nil),
+        Expression::Tuple(elements) => Some(ValueKind::Tuple(elements.len())),
+        // + with a string on either side concatenates
+        Expression::ArithmeticExpression { left, right, .. } => match (kind(left), kind(right)) {
+            (Some(ValueKind::String), _) | (_, Some(ValueKind::String)) => Some(ValueKind::String),
+            (Some(ValueKind::Int), Some(ValueKind::Int)) => Some(ValueKind::Int),
+            _ => None,
+        },
+        Expression::FunctionCall { name, .. } if CodeGenerator::builtin_arity(name).is_some() => {
+            Some(ValueKind::Int)
+        }
+        Expression::IfExpression {
+            then_block,
+            else_block,
+            ..
+        } => {
+            let then_kind = kind(then_block.return_expression.as_ref()?)?;
+            let else_kind = kind(else_block.return_expression.as_ref()?)?;
+            (then_kind == else_kind).then_some(then_kind)
+        }
+        Expression::Identifier { name, .. }
+            if parameters.iter().any(|parameter| {
+                parameter.name == *name && parameter.type_annotation != TypeAnnotation::Array
+            }) =>
+        {
+            Some(ValueKind::Int)
+        }
+        Expression::Sequence { value, .. } => kind(value),
+        _ => None,
+    }
+}
+
+// Check the program for mistakes the grammar can't catch:
+// every assignment has to target a variable introduced by `this` or a parameter,
+// builtins can't be redeclared and are called with their number of arguments,
+// `break` is inside a loop of the same function with the label it names,
+// a function doesn't read one of its locals before assigning it, and tuples
+// are only returned, destructured into as many names, or discarded
+pub fn check(program: &Program) -> Result<(), SemanticError> {
+    check_with_globals(program, &HashSet::new())
+}
+
+// `check` for a program the host starts with globals of its own,
+// which the program can assign like the globals it declares
+pub fn check_with_globals(
+    program: &Program,
+    seeded: &HashSet<String>,
+) -> Result<(), SemanticError> {
+    let mut globals = Declarations::default();
+    globals.visit_program(program);
+    globals.names.extend(seeded.iter().cloned());
+    let mut arities = Arities::default();
+    arities.visit_program(program);
+
+    let mut checker = Checker {
+        globals: globals.names,
+        arities: arities.values,
+        function: None,
+        locals: HashSet::new(),
+        integers: HashSet::new(),
+        loops: vec![],
+        error: None,
+    };
+    checker.visit_program(program);
+    match checker.error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+// Functions that are declared but never called, other than by themselves.
+// `main` is called by the program when it is declared, so it is always used
+pub fn unused_functions(program: &Program) -> Vec<Warning> {
+    let mut usage = FunctionUsage::default();
+    usage.visit_program(program);
+    usage
+        .declared
+        .into_iter()
+        .filter(|name| name != "main" && !usage.called.contains(name))
+        .map(|name| {
+            Warning::new(
+                WarningKind::UnusedFunction,
+                format!("Function {} is declared but never called", name),
+                None,
+            )
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct FunctionUsage {
+    declared: Vec<String>, // in declaration order, so warnings come out in source order
+    called: HashSet<String>,
+    function: Option<String>,
+}
+
+impl Visitor<'_> for FunctionUsage {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let Statement::FunctionDeclaration { name, .. } = statement {
+            if !self.declared.contains(name) {
+                self.declared.push(name.clone());
+            }
+            let enclosing_function = self.function.replace(name.clone());
+            visitor::walk_statement(self, statement);
+            self.function = enclosing_function;
+            return;
+        }
+        visitor::walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Expression::FunctionCall { name, .. } = expression {
+            // recursion alone doesn't make a function used
+            if self.function.as_ref() != Some(name) {
+                self.called.insert(name.clone());
+            }
+        }
+        visitor::walk_expression(self, expression);
+    }
+}
+
+// Names declared in a scope, wherever they appear in it: like the code generator,
+// a declaration holds for the whole function, or the whole program for globals
+#[derive(Default)]
+struct Declarations {
+    names: HashSet<String>,
+}
+
+impl Visitor<'_> for Declarations {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::VariableDeclaration { identifier, .. } => {
+                self.names.insert(identifier.clone());
+            }
+            Statement::TupleDeclaration { identifiers, .. } => {
+                self.names.extend(identifiers.iter().cloned());
+            }
+            // a function body is a scope of its own, and so is a bare block
+            Statement::FunctionDeclaration { .. } | Statement::Block(_) => return,
+            _ => {}
+        }
+        visitor::walk_statement(self, statement);
+    }
+}
+
+// How many values each function returns, from its return type, or the one
+// inferred without it
+#[derive(Default)]
+struct Arities {
+    values: HashMap<String, usize>,
+}
+
+impl Visitor<'_> for Arities {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if let Statement::FunctionDeclaration {
+            name,
+            parameters,
+            return_type,
+            body,
+        } = statement
+        {
+            let return_type = return_type
+                .clone()
+                .or_else(|| inferred_return_type(parameters, body));
+            self.values
+                .entry(name.clone())
+                .or_insert(CodeGenerator::return_count(&return_type));
+        }
+        visitor::walk_statement(self, statement);
+    }
+}
+
+struct Checker {
+    globals: HashSet<String>,
+    arities: HashMap<String, usize>,
+    function: Option<String>,
+    locals: HashSet<String>,
+    integers: HashSet<String>,  // parameters annotated with an integer type
+    loops: Vec<Option<String>>, // labels of the enclosing loops
+    error: Option<SemanticError>,
+}
+
+impl Checker {
+    // the number of values an expression leaves, more than one for a tuple
+    fn arity(&self, expression: &Expression) -> usize {
+        match expression {
+            Expression::Tuple(elements) => elements.len(),
+            Expression::FunctionCall { name, .. } => self.arities.get(name).copied().unwrap_or(1),
+            Expression::Sequence { value, .. } => self.arity(value),
+            _ => 1,
+        }
+    }
+
+    fn indexed_integer(&mut self, name: &str) {
+        self.error.get_or_insert(SemanticError::IndexedInteger {
+            name: name.to_string(),
+            function: self.function.clone().unwrap_or_default(),
+        });
+    }
+
+    fn single(&mut self, expression: &Expression) {
+        let values = self.arity(expression);
+        if values != 1 {
+            self.error
+                .get_or_insert(SemanticError::TupleAsValue { values });
+        }
+    }
+
+    // the places a statement takes a value, a tuple is only destructured or discarded
+    fn check_values(&mut self, statement: &Statement) {
+        match statement {
+            Statement::VariableDeclaration { value, .. }
+            | Statement::Assignment { value, .. }
+            | Statement::Print(value)
+            | Statement::Eprint(value)
+            | Statement::Exit(value)
+            | Statement::Panic { message: value, .. }
+            | Statement::Assert {
+                condition: value, ..
+            }
+            | Statement::IfStatement {
+                condition: value, ..
+            }
+            | Statement::WhileLoop {
+                condition: value, ..
+            }
+            | Statement::DoWhileLoop {
+                condition: value, ..
+            }
+            | Statement::Match { value, .. } => self.single(value),
+            Statement::IndexAssignment { index, value, .. } => {
+                self.single(index);
+                self.single(value);
+            }
+            Statement::TupleDeclaration {
+                identifiers, value, ..
+            } => {
+                let values = self.arity(value);
+                if values != identifiers.len() {
+                    self.error
+                        .get_or_insert(SemanticError::DestructureMismatch {
+                            identifiers: identifiers.len(),
+                            values,
+                        });
+                }
+            }
+            Statement::FunctionDeclaration {
+                name,
+                return_type: Some(return_type),
+                body,
+                ..
+            } => {
+                let expected = CodeGenerator::return_count(&Some(return_type.clone()));
+                let mut values = vec![];
+                returned_values(body, &mut values);
+                for value in values {
+                    if *value == Expression::Nil {
+                        self.error.get_or_insert(SemanticError::MissingReturnValue {
+                            function: name.clone(),
+                        });
+                        continue;
+                    }
+                    let found = self.arity(value);
+                    if found != expected {
+                        self.error
+                            .get_or_insert(SemanticError::ReturnArityMismatch {
+                                function: name.clone(),
+                                expected,
+                                found,
+                            });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Visitor<'_> for Checker {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if self.error.is_some() {
+            return;
+        }
+        self.check_values(statement);
+        if self.error.is_some() {
+            return;
+        }
+        match statement {
+            Statement::Assignment { identifier, .. }
+            | Statement::IndexAssignment {
+                array: identifier, ..
+            } if !self.locals.contains(identifier) && !self.globals.contains(identifier) => {
+                self.error = Some(SemanticError::UndeclaredAssignment {
+                    name: identifier.clone(),
+                    function: self.function.clone(),
+                });
+                return;
+            }
+            Statement::IndexAssignment { array, .. } if self.integers.contains(array) => {
+                self.indexed_integer(array);
+                return;
+            }
+            Statement::FunctionDeclaration { name, .. }
+                if CodeGenerator::builtin_arity(name).is_some() =>
+            {
+                self.error = Some(SemanticError::ReservedName { name: name.clone() });
+                return;
+            }
+            Statement::FunctionDeclaration {
+                name,
+                parameters,
+                return_type,
+                body,
+            } => {
+                if let (None, Some((first, second))) =
+                    (return_type, mismatched_returns(parameters, body))
+                {
+                    self.error = Some(SemanticError::ReturnTypeMismatch {
+                        function: name.clone(),
+                        first,
+                        second,
+                    });
+                    return;
+                }
+                let mut locals = Declarations::default();
+                locals.visit_block(body);
+                // a parameter declared again with `this` can hold anything
+                let integers = parameters
+                    .iter()
+                    .filter(|parameter| parameter.type_annotation != TypeAnnotation::Array)
+                    .map(|parameter| parameter.name.clone())
+                    .filter(|name| !locals.names.contains(name))
+                    .collect();
+                locals
+                    .names
+                    .extend(parameters.iter().map(|parameter| parameter.name.clone()));
+
+                let mut initialization = Initialization {
+                    function: name.clone(),
+                    declared: locals.names.clone(),
+                    assigned: Some(
+                        parameters
+                            .iter()
+                            .map(|parameter| parameter.name.clone())
+                            .collect(),
+                    ),
+                    error: None,
+                };
+                initialization.visit_block(body);
+                if let Some(error) = initialization.error {
+                    self.error = Some(error);
+                    return;
+                }
+
+                let enclosing_function = self.function.replace(name.clone());
+                let enclosing_locals = std::mem::replace(&mut self.locals, locals.names);
+                let enclosing_integers = std::mem::replace(&mut self.integers, integers);
+                let enclosing_loops = std::mem::take(&mut self.loops);
+                self.visit_block(body);
+                self.function = enclosing_function;
+                self.locals = enclosing_locals;
+                self.integers = enclosing_integers;
+                self.loops = enclosing_loops;
+                return;
+            }
+            Statement::Block(block) => {
+                // the block's own declarations can be assigned only inside it
+                let mut declarations = Declarations::default();
+                declarations.visit_block(block);
+                let enclosing_locals = self.locals.clone();
+                let enclosing_integers = self.integers.clone();
+                self.integers
+                    .retain(|name| !declarations.names.contains(name));
+                self.locals.extend(declarations.names);
+                self.visit_block(block);
+                self.locals = enclosing_locals;
+                self.integers = enclosing_integers;
+                return;
+            }
+            Statement::WhileLoop { label, .. } | Statement::DoWhileLoop { label, .. } => {
+                self.loops.push(label.clone());
+                visitor::walk_statement(self, statement);
+                self.loops.pop();
+                return;
+            }
+            Statement::Break { label: None } if self.loops.is_empty() => {
+                self.error = Some(SemanticError::BreakOutsideLoop);
+                return;
+            }
+            Statement::Break { label: Some(label) }
+                if !self
+                    .loops
+                    .iter()
+                    .any(|loop_label| loop_label.as_ref() == Some(label)) =>
+            {
+                self.error = Some(SemanticError::UndefinedLabel {
+                    label: label.clone(),
+                });
+                return;
+            }
+            _ => {}
+        }
+        visitor::walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if let Expression::Index { array, .. } = expression {
+            if let Expression::Identifier { name, .. } = array.as_ref() {
+                if self.integers.contains(name) {
+                    self.indexed_integer(name);
+                }
+            }
+        }
+        // the operands of an expression are single values, a tuple's elements too
+        match expression {
+            Expression::ArithmeticExpression { left, right, .. }
+            | Expression::Comparison { left, right, .. }
+            | Expression::LogicalExpression { left, right, .. }
+            | Expression::Index {
+                array: left,
+                index: right,
+            }
+            | Expression::ArrayRepeat {
+                value: left,
+                count: right,
+            } => {
+                self.single(left);
+                self.single(right);
+            }
+            Expression::FunctionCall {
+                arguments: values, ..
+            }
+            | Expression::Tuple(values)
+            | Expression::Array(values) => {
+                for value in values {
+                    self.single(value);
+                }
+            }
+            Expression::Not(operand) => self.single(operand),
+            Expression::IfExpression {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                self.single(condition);
+                for value in [&then_block.return_expression, &else_block.return_expression]
+                    .into_iter()
+                    .flatten()
+                {
+                    self.single(value);
+                }
+            }
+            _ => {}
+        }
+        if let Expression::FunctionCall {
+            name, arguments, ..
+        } = expression
+        {
+            match CodeGenerator::builtin_arity(name) {
+                Some(expected) if expected != arguments.len() => {
+                    self.error.get_or_insert(SemanticError::BuiltinArity {
+                        name: name.clone(),
+                        expected,
+                        found: arguments.len(),
+                    });
+                }
+                _ => {}
+            }
+        }
+        // an expression block can't jump out of the expression it is part of
+        if let Expression::IfExpression { .. } = expression {
+            let enclosing_loops = std::mem::take(&mut self.loops);
+            visitor::walk_expression(self, expression);
+            self.loops = enclosing_loops;
+            return;
+        }
+        visitor::walk_expression(self, expression);
+    }
+}
+
+// Locals of a function read before they are assigned. Without the check the
+// read falls through to a global of the same name, or fails when running.
+// A local is assigned after a statement when it is on every path through it:
+// both branches of an if, every arm of a match, but not the body of a while
+// loop, which can run zero times. Names the function never declares are left
+// to the globals, the host can still give them as constants
+struct Initialization {
+    function: String,
+    declared: HashSet<String>, // locals in scope, parameters included
+    assigned: Option<HashSet<String>>, // None once a return, break or exit was passed
+    error: Option<SemanticError>,
+}
+
+impl Initialization {
+    fn assign(&mut self, name: &str) {
+        if let Some(assigned) = &mut self.assigned {
+            assigned.insert(name.to_string());
+        }
+    }
+
+    fn read(&mut self, name: &str) {
+        let unassigned = self
+            .assigned
+            .as_ref()
+            .is_some_and(|assigned| !assigned.contains(name));
+        if unassigned && self.declared.contains(name) {
+            self.error = Some(SemanticError::UseBeforeAssignment {
+                name: name.to_string(),
+                function: self.function.clone(),
+            });
+        }
+    }
+
+    // run a path from the current state, and give the state it ends in
+    fn branch(&mut self, path: impl FnOnce(&mut Self)) -> Option<HashSet<String>> {
+        let before = self.assigned.clone();
+        path(self);
+        std::mem::replace(&mut self.assigned, before)
+    }
+
+    // the block of an if expression, whose last expression is its value
+    fn visit_value_block(&mut self, block: &Block) {
+        for statement in &block.statements {
+            self.visit_statement(statement);
+        }
+        if let Some(value) = &block.return_expression {
+            self.visit_expression(value);
+        }
+    }
+}
+
+// assigned after either path, a path that never gets there doesn't count
+fn merge(
+    first: Option<HashSet<String>>,
+    second: Option<HashSet<String>>,
+) -> Option<HashSet<String>> {
+    match (first, second) {
+        (None, other) | (other, None) => other,
+        (Some(first), Some(second)) => Some(&first & &second),
+    }
+}
+
+impl Visitor<'_> for Initialization {
+    fn visit_statement(&mut self, statement: &Statement) {
+        if self.error.is_some() {
+            return;
+        }
+        match statement {
+            Statement::VariableDeclaration {
+                identifier, value, ..
+            }
+            | Statement::Assignment {
+                identifier, value, ..
+            } => {
+                self.visit_expression(value);
+                self.assign(identifier);
+            }
+            Statement::TupleDeclaration {
+                identifiers, value, ..
+            } => {
+                self.visit_expression(value);
+                for identifier in identifiers {
+                    self.assign(identifier);
+                }
+            }
+            Statement::IndexAssignment { array, .. } => {
+                self.read(array);
+                visitor::walk_statement(self, statement);
+            }
+            // checked on its own, with its own locals
+            Statement::FunctionDeclaration { .. } => {}
+            Statement::IfStatement {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                self.visit_expression(condition);
+                let then_assigned = self.branch(|this| this.visit_block(then_block));
+                let else_assigned = match else_block {
+                    Some(else_block) => self.branch(|this| this.visit_block(else_block)),
+                    None => self.assigned.clone(),
+                };
+                self.assigned = merge(then_assigned, else_assigned);
+            }
+            Statement::WhileLoop {
+                condition, body, ..
+            } => {
+                self.visit_expression(condition);
+                self.branch(|this| this.visit_block(body));
+            }
+            Statement::DoWhileLoop {
+                body, condition, ..
+            } => {
+                let before = self.assigned.clone();
+                self.visit_block(body);
+                self.visit_expression(condition);
+                // a break can leave the body before its assignments
+                let mut breaks = Breaks::default();
+                breaks.visit_block(body);
+                if breaks.found {
+                    self.assigned = before;
+                }
+            }
+            Statement::Break { .. } => self.assigned = None,
+            Statement::Exit(value) | Statement::Panic { message: value, .. } => {
+                self.visit_expression(value);
+                self.assigned = None;
+            }
+            Statement::Block(block) => {
+                // the block's declarations are new variables, unassigned even
+                // when an outer one of the same name is
+                let mut declarations = Declarations::default();
+                declarations.visit_block(block);
+                let before = self.assigned.clone();
+                let enclosing_declared = self.declared.clone();
+                if let Some(assigned) = &mut self.assigned {
+                    assigned.retain(|name| !declarations.names.contains(name));
+                }
+                self.declared.extend(declarations.names.iter().cloned());
+                self.visit_block(block);
+                self.declared = enclosing_declared;
+                self.assigned = match (before, self.assigned.take()) {
+                    (Some(mut before), Some(after)) => {
+                        before.extend(
+                            after
+                                .into_iter()
+                                .filter(|name| !declarations.names.contains(name)),
+                        );
+                        Some(before)
+                    }
+                    _ => None,
+                };
+            }
+            Statement::Match {
+                value,
+                arms,
+                default,
+            } => {
+                self.visit_expression(value);
+                let mut assigned = match default {
+                    Some(default) => self.branch(|this| this.visit_block(default)),
+                    None => self.assigned.clone(),
+                };
+                for arm in arms {
+                    let arm_assigned = self.branch(|this| this.visit_block(&arm.body));
+                    assigned = merge(assigned, arm_assigned);
+                }
+                self.assigned = assigned;
+            }
+            _ => visitor::walk_statement(self, statement),
+        }
+    }
+
+    // a block run as statements, where the last expression is a return
+    fn visit_block(&mut self, block: &Block) {
+        visitor::walk_block(self, block);
+        if block.return_expression.is_some() {
+            self.assigned = None;
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &Expression) {
+        if self.error.is_some() {
+            return;
+        }
+        match expression {
+            Expression::Identifier { name, .. } => self.read(name),
+            // the right side doesn't always run
+            Expression::LogicalExpression { left, right, .. } => {
+                self.visit_expression(left);
+                self.branch(|this| this.visit_expression(right));
+            }
+            Expression::IfExpression {
+                condition,
+                then_block,
+                else_block,
+            } => {
+                self.visit_expression(condition);
+                let then_assigned = self.branch(|this| this.visit_value_block(then_block));
+                let else_assigned = self.branch(|this| this.visit_value_block(else_block));
+                self.assigned = merge(then_assigned, else_assigned);
+            }
+            _ => visitor::walk_expression(self, expression),
+        }
+    }
+}
+
+// whether a loop body breaks, out of it or out of a loop inside it
+#[derive(Default)]
+struct Breaks {
+    found: bool,
+}
+
+impl Visitor<'_> for Breaks {
+    fn visit_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Break { .. } => self.found = true,
+            Statement::FunctionDeclaration { .. } => {}
+            _ => visitor::walk_statement(self, statement),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn checked(source: &str) -> Result<(), SemanticError> {
+        check(
+            &Parser::new(Lexer::tokenize(source).unwrap())
+                .parse()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn a_local_read_before_it_is_assigned_on_every_path_is_an_error() {
+        assert!(matches!(
+            checked(
+                "this x = 100; \
+                 fn bump(step: int) -> int { if step != 0 { this x = step; }; return x + 1; }; \
+                 print(bump(1));"
+            ),
+            Err(SemanticError::UseBeforeAssignment { name, function })
+                if name == "x" && function == "bump"
+        ));
+    }
+
+    #[test]
+    fn a_local_assigned_on_every_path_can_be_read() {
+        assert!(checked(
+            "fn branches(flag: int) -> int { \
+                 if flag == 1 { this value = 1; } else { value = 2; }; return value; }; \
+                 print(branches(1));"
+        )
+        .is_ok());
+    }
+}
@@ -0,0 +1,435 @@
+use crate::code_generator::{CodeGenerator, OpCode};
+use crate::interner::{Interner, Symbol};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum VerifyError {
+    UndefinedFunction {
+        name: String,
+        position: usize,
+    },
+    StackUnderflow {
+        function: String,
+        position: usize,
+    },
+    InconsistentStackDepth {
+        function: String,
+        position: usize,
+    },
+    ReturnStackMismatch {
+        function: String,
+        position: usize,
+        expected: usize,
+        found: usize,
+    },
+    MissingReturn {
+        function: String,
+    },
+    JumpOutOfRange {
+        position: usize,
+        target: usize,
+    },
+    ReturnOutsideFunction {
+        position: usize,
+    },
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyError::UndefinedFunction { name, position } => {
+                write!(f, "Call to undefined function {} at {}", name, position)
+            }
+            VerifyError::StackUnderflow { function, position } => {
+                write!(f, "Stack underflow in {} at {}", function, position)
+            }
+            VerifyError::InconsistentStackDepth { function, position } => write!(
+                f,
+                "Branches of {} reach {} with different stack depths",
+                function, position
+            ),
+            VerifyError::ReturnStackMismatch {
+                function,
+                position,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Function {} returns at {} with {} value(s) on the stack, expected {}",
+                function, position, found, expected
+            ),
+            VerifyError::MissingReturn { function } => {
+                write!(f, "Function {} can reach EXIT without returning", function)
+            }
+            VerifyError::JumpOutOfRange { position, target } => {
+                write!(f, "Jump at {} targets {} past the end", position, target)
+            }
+            VerifyError::ReturnOutsideFunction { position } => {
+                write!(f, "Return at {} is outside of a function", position)
+            }
+        }
+    }
+}
+
+struct Signature {
+    arity: usize,
+    prologue: usize, // instructions storing the parameters after ENTER
+    return_count: usize,
+}
+
+// Check that every jump lands inside the program and that every function body
+// leaves exactly the values its signature promises when it reaches RET: one per
+// element for tuples, otherwise one, which is nil for void functions. The top
+// level is walked too, so calls there have to name a declared function
+pub fn verify(opcodes: &[OpCode], symbols: &Interner) -> Result<(), VerifyError> {
+    verify_jumps(opcodes)?;
+    let signatures = collect_signatures(opcodes);
+    verify_body(opcodes, &signatures, symbols, TOP_LEVEL, None, 0)?;
+    for (position, opcode) in opcodes.iter().enumerate() {
+        if let OpCode::DECLARE(name, _) = opcode {
+            let signature = &signatures[name];
+            // stack depth is relative to the frame, parameters are already stored
+            let body_start = position + 2 + signature.prologue;
+            verify_body(
+                opcodes,
+                &signatures,
+                symbols,
+                symbols.resolve(*name),
+                Some(signature.return_count),
+                body_start,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+const TOP_LEVEL: &str = "top level";
+
+// jumping to the end of the program is how the top level halts
+fn verify_jumps(opcodes: &[OpCode]) -> Result<(), VerifyError> {
+    for (position, opcode) in opcodes.iter().enumerate() {
+        if let Some(target) = opcode.jump_target() {
+            if target > opcodes.len() {
+                return Err(VerifyError::JumpOutOfRange { position, target });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn collect_signatures(opcodes: &[OpCode]) -> HashMap<Symbol, Signature> {
+    let mut signatures = HashMap::new();
+    for (position, opcode) in opcodes.iter().enumerate() {
+        if let OpCode::DECLARE(name, return_type) = opcode {
+            // the prologue stores every parameter right after ENTER,
+            // wrapping the i32 ones first
+            let prologue = opcodes[position + 2..]
+                .iter()
+                .take_while(|opcode| matches!(opcode, OpCode::StoreLocal(_) | OpCode::WRAP32))
+                .collect::<Vec<&OpCode>>();
+            let arity = prologue
+                .iter()
+                .filter(|opcode| matches!(opcode, OpCode::StoreLocal(_)))
+                .count();
+            signatures.insert(
+                *name,
+                Signature {
+                    arity,
+                    prologue: prologue.len(),
+                    return_count: CodeGenerator::return_count(return_type),
+                },
+            );
+        }
+    }
+    signatures
+}
+
+// `return_count` is None for the top level, which may only run off the end
+fn verify_body(
+    opcodes: &[OpCode],
+    signatures: &HashMap<Symbol, Signature>,
+    symbols: &Interner,
+    name: &str,
+    return_count: Option<usize>,
+    body_start: usize,
+) -> Result<(), VerifyError> {
+    let mut depths: HashMap<usize, usize> = HashMap::new();
+    let mut worklist = vec![(body_start, 0usize)];
+
+    while let Some((position, depth)) = worklist.pop() {
+        if let Some(&seen) = depths.get(&position) {
+            if seen != depth {
+                return Err(VerifyError::InconsistentStackDepth {
+                    function: name.to_string(),
+                    position,
+                });
+            }
+            continue;
+        }
+        depths.insert(position, depth);
+
+        let Some(opcode) = opcodes.get(position) else {
+            if return_count.is_none() {
+                continue;
+            }
+            return Err(VerifyError::MissingReturn {
+                function: name.to_string(),
+            });
+        };
+
+        let (pops, pushes) = match opcode {
+            OpCode::RET | OpCode::EXIT if return_count.is_none() => {
+                return Err(VerifyError::ReturnOutsideFunction { position })
+            }
+            OpCode::RET => {
+                let expected = return_count.expect("checked above");
+                if depth != expected {
+                    return Err(VerifyError::ReturnStackMismatch {
+                        function: name.to_string(),
+                        position,
+                        expected,
+                        found: depth,
+                    });
+                }
+                continue;
+            }
+            OpCode::EXIT => {
+                return Err(VerifyError::MissingReturn {
+                    function: name.to_string(),
+                })
+            }
+            OpCode::JUMP(address) => {
+                worklist.push((*address, depth));
+                continue;
+            }
+            // the program ends here, whatever else is on the stack
+            OpCode::HALT | OpCode::PANIC => {
+                pop(depth, 1, name, position)?;
+                continue;
+            }
+            OpCode::JmpIfFalse(address)
+            | OpCode::JmpIfTrue(address)
+            | OpCode::JZ(address)
+            | OpCode::JNZ(address) => {
+                let depth = pop(depth, 1, name, position)?;
+                worklist.push((*address, depth));
+                worklist.push((position + 1, depth));
+                continue;
+            }
+            OpCode::DECLARE(..) => {
+                // nested declarations are skipped over like the VM does
+                let exit = opcodes[position..]
+                    .iter()
+                    .position(|opcode| matches!(opcode, OpCode::EXIT))
+                    .map_or(opcodes.len(), |offset| position + offset);
+                worklist.push((exit + 1, depth));
+                continue;
+            }
+            OpCode::CALL(_)
+            | OpCode::TailCall(_)
+            | OpCode::CallAddr(_)
+            | OpCode::TailCallAddr(_) => {
+                let callee_signature = callee(opcodes, opcode)
+                    .and_then(|callee| {
+                        signatures
+                            .get(&callee)
+                            .ok_or_else(|| symbols.resolve(callee).to_string())
+                    })
+                    .map_err(|name| VerifyError::UndefinedFunction { name, position })?;
+                (callee_signature.arity, callee_signature.return_count)
+            }
+            OpCode::PUSHC(_)
+            | OpCode::PUSHB(_)
+            | OpCode::PUSHS(_)
+            | OpCode::NIL
+            | OpCode::LOAD(_)
+            | OpCode::LoadLocal(_)
+            | OpCode::READ
+            | OpCode::ARGS => (0, 1),
+            OpCode::STOREI(_, _) | OpCode::INCR(_, _) | OpCode::IncrLocal(_, _) => (0, 0),
+            OpCode::ABS | OpCode::NOT | OpCode::WRAP32 | OpCode::ARG | OpCode::LEN => (1, 1),
+            OpCode::DUP => (1, 2),
+            OpCode::MakeArray(count) => (*count, 1),
+            OpCode::RepeatArray | OpCode::LoadIndex => (2, 1),
+            OpCode::StoreIndex(_) | OpCode::StoreIndexLocal(_) => (2, 0),
+            OpCode::POP
+            | OpCode::PRINT
+            | OpCode::PRINTERR
+            | OpCode::STORE(_)
+            | OpCode::StoreLocal(_)
+            | OpCode::ASSERT(_) => (1, 0),
+            OpCode::ADD
+            | OpCode::SUB
+            | OpCode::MUL
+            | OpCode::DIV
+            | OpCode::MOD
+            | OpCode::POW
+            | OpCode::MIN
+            | OpCode::MAX
+            | OpCode::WADD
+            | OpCode::WMUL
+            | OpCode::EQUAL
+            | OpCode::NotEqual => (2, 1),
+            OpCode::ENTER => (0, 0),
+        };
+
+        let depth = pop(depth, pops, name, position)? + pushes;
+        worklist.push((position + 1, depth));
+    }
+
+    Ok(())
+}
+
+// resolved calls are named after the DECLARE right before their address,
+// the error is the name to report when there is none
+fn callee(opcodes: &[OpCode], opcode: &OpCode) -> Result<Symbol, String> {
+    match opcode {
+        OpCode::CALL(name) | OpCode::TailCall(name) => Ok(*name),
+        OpCode::CallAddr(address) | OpCode::TailCallAddr(address) => {
+            match address
+                .checked_sub(1)
+                .and_then(|position| opcodes.get(position))
+            {
+                Some(OpCode::DECLARE(name, _)) => Ok(*name),
+                _ => Err(format!("<address {}>", address)),
+            }
+        }
+        _ => unreachable!("not a call opcode"),
+    }
+}
+
+fn pop(depth: usize, count: usize, function: &str, position: usize) -> Result<usize, VerifyError> {
+    depth
+        .checked_sub(count)
+        .ok_or_else(|| VerifyError::StackUnderflow {
+            function: function.to_string(),
+            position,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::TypeAnnotation;
+
+    #[test]
+    fn accepts_balanced_code() {
+        let opcodes = [
+            OpCode::PUSHC(0),
+            OpCode::JZ(4),
+            OpCode::PUSHC(0),
+            OpCode::PRINT,
+            OpCode::PUSHC(0),
+            OpCode::POP,
+        ];
+        assert!(verify(&opcodes, &Interner::new()).is_ok());
+    }
+
+    #[test]
+    fn rejects_popping_an_empty_stack() {
+        let opcodes = [OpCode::PUSHC(0), OpCode::ADD];
+        assert!(matches!(
+            verify(&opcodes, &Interner::new()),
+            Err(VerifyError::StackUnderflow { position: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_branches_meeting_with_different_depths() {
+        // falling through pushes one more value than jumping over it
+        let opcodes = [
+            OpCode::PUSHC(0),
+            OpCode::JZ(3),
+            OpCode::PUSHC(0),
+            OpCode::PRINT,
+        ];
+        assert!(matches!(
+            verify(&opcodes, &Interner::new()),
+            Err(VerifyError::InconsistentStackDepth { position: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_a_jump_past_the_end() {
+        let opcodes = [OpCode::PUSHC(0), OpCode::JUMP(5)];
+        assert!(matches!(
+            verify(&opcodes, &Interner::new()),
+            Err(VerifyError::JumpOutOfRange {
+                position: 1,
+                target: 5
+            })
+        ));
+    }
+
+    #[test]
+    fn rejects_a_call_to_an_undeclared_function() {
+        let mut symbols = Interner::new();
+        let name = symbols.intern("missing");
+        let opcodes = [OpCode::CALL(name)];
+        assert!(matches!(
+            verify(&opcodes, &symbols),
+            Err(VerifyError::UndefinedFunction { position: 0, ref name }) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn rejects_a_return_outside_a_function() {
+        let opcodes = [OpCode::PUSHC(0), OpCode::RET];
+        assert!(matches!(
+            verify(&opcodes, &Interner::new()),
+            Err(VerifyError::ReturnOutsideFunction { position: 1 })
+        ));
+    }
+
+    #[test]
+    fn accepts_the_functions_the_generator_emits() {
+        // compile runs the verifier, and fails with its error
+        assert!(crate::compile(include_str!("test_files/return_effects.txt")).is_ok());
+    }
+
+    #[test]
+    fn rejects_a_function_falling_through_to_its_end() {
+        let mut symbols = Interner::new();
+        let name = symbols.intern("sign");
+        let opcodes = [
+            OpCode::DECLARE(name, Some(TypeAnnotation::Int)),
+            OpCode::ENTER,
+            OpCode::PUSHC(0),
+            OpCode::POP,
+            OpCode::EXIT,
+        ];
+        assert!(matches!(
+            verify(&opcodes, &symbols),
+            Err(VerifyError::MissingReturn { ref function }) if function == "sign"
+        ));
+    }
+
+    #[test]
+    fn rejects_a_return_with_the_wrong_number_of_values() {
+        let mut symbols = Interner::new();
+        let name = symbols.intern("pair");
+        let opcodes = [
+            OpCode::DECLARE(
+                name,
+                Some(TypeAnnotation::Tuple(vec![
+                    TypeAnnotation::Int,
+                    TypeAnnotation::Int,
+                ])),
+            ),
+            OpCode::ENTER,
+            OpCode::PUSHC(0),
+            OpCode::RET,
+            OpCode::EXIT,
+        ];
+        assert!(matches!(
+            verify(&opcodes, &symbols),
+            Err(VerifyError::ReturnStackMismatch {
+                position: 3,
+                expected: 2,
+                found: 1,
+                ..
+            })
+        ));
+    }
+}
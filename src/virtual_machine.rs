@@ -1,88 +1,779 @@
+use crate::ast_json;
 use crate::code_generator::OpCode;
-use std::collections::HashMap;
+use crate::disassembler;
+use crate::interner::{Interner, Symbol};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    AssertionFailed {
+        position: usize,
+    },
+    InvalidInput(String),
+    TraceFailed(String),  // the trace writer failed, with the io error
+    OutputFailed(String), // writing print's or eprint's output failed, with the io error
+    InputFailed(String),  // reading a line for read() failed, with the io error
+    ArithmeticOverflow {
+        op: &'static str,
+        ip: usize,
+    },
+    DivisionByZero {
+        ip: usize,
+    },
+    NegativeExponent {
+        exponent: i64,
+        ip: usize,
+    },
+    NilValue {
+        op: &'static str,
+        ip: usize,
+    },
+    UndefinedVariable {
+        name: String,
+        ip: usize,
+    },
+    AssignToConstant {
+        name: String,
+        ip: usize,
+    },
+    UndefinedFunction {
+        name: String,
+        ip: usize,
+    },
+    StackUnderflow {
+        op: &'static str,
+        ip: usize,
+    },
+    TypeMismatch {
+        op: &'static str,
+        expected: &'static str,
+        found: &'static str,
+        ip: usize,
+    },
+    ArgumentOutOfRange {
+        index: i64,
+        count: usize,
+        ip: usize,
+    },
+    IndexOutOfBounds {
+        index: i64,
+        length: usize,
+        ip: usize,
+    },
+    NegativeArrayLength {
+        count: i64,
+        ip: usize,
+    },
+    UserPanic {
+        message: String,
+        ip: usize,
+    },
+    ExitCodeOutOfRange {
+        code: i64,
+        ip: usize,
+    },
+}
+
+impl RuntimeError {
+    // the instruction which failed, an assertion points at its source instead
+    pub fn instruction(&self) -> Option<usize> {
+        match self {
+            RuntimeError::AssertionFailed { .. }
+            | RuntimeError::InvalidInput(_)
+            | RuntimeError::TraceFailed(_)
+            | RuntimeError::OutputFailed(_)
+            | RuntimeError::InputFailed(_) => None,
+            RuntimeError::ArithmeticOverflow { ip, .. }
+            | RuntimeError::DivisionByZero { ip }
+            | RuntimeError::NegativeExponent { ip, .. }
+            | RuntimeError::NilValue { ip, .. }
+            | RuntimeError::UndefinedVariable { ip, .. }
+            | RuntimeError::AssignToConstant { ip, .. }
+            | RuntimeError::UndefinedFunction { ip, .. }
+            | RuntimeError::StackUnderflow { ip, .. }
+            | RuntimeError::TypeMismatch { ip, .. }
+            | RuntimeError::ArgumentOutOfRange { ip, .. }
+            | RuntimeError::IndexOutOfBounds { ip, .. }
+            | RuntimeError::NegativeArrayLength { ip, .. }
+            | RuntimeError::UserPanic { ip, .. }
+            | RuntimeError::ExitCodeOutOfRange { ip, .. } => Some(*ip),
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::AssertionFailed { position } => {
+                write!(f, "Assertion failed at position {}", position)
+            }
+            RuntimeError::InvalidInput(input) => {
+                write!(f, "Invalid integer input: {:?}", input)
+            }
+            RuntimeError::TraceFailed(error) => {
+                write!(f, "Could not write the execution trace: {}", error)
+            }
+            RuntimeError::InputFailed(error) => {
+                write!(f, "Could not read from the input: {}", error)
+            }
+            RuntimeError::OutputFailed(error) => {
+                write!(f, "Could not write the program's output: {}", error)
+            }
+            RuntimeError::ArithmeticOverflow { op, ip } => {
+                write!(f, "Arithmetic overflow on {} at instruction {}", op, ip)
+            }
+            RuntimeError::DivisionByZero { ip } => {
+                write!(f, "Division by zero at instruction {}", ip)
+            }
+            RuntimeError::NegativeExponent { exponent, ip } => write!(
+                f,
+                "Negative exponent {} at instruction {}, integers have no fractions",
+                exponent, ip
+            ),
+            RuntimeError::NilValue { op, ip } => write!(
+                f,
+                "{} at instruction {} used the result of a void function",
+                op, ip
+            ),
+            RuntimeError::UndefinedVariable { name, ip } => {
+                write!(f, "Undefined variable {} at instruction {}", name, ip)
+            }
+            RuntimeError::AssignToConstant { name, ip } => write!(
+                f,
+                "Cannot assign to {} at instruction {}, it is a constant given by the host",
+                name, ip
+            ),
+            RuntimeError::UndefinedFunction { name, ip } => {
+                write!(f, "Undefined function {} at instruction {}", name, ip)
+            }
+            RuntimeError::StackUnderflow { op, ip } => {
+                write!(f, "Stack underflow on {} at instruction {}", op, ip)
+            }
+            RuntimeError::TypeMismatch {
+                op,
+                expected,
+                found,
+                ip,
+            } => write!(
+                f,
+                "{} at instruction {} expected {} but found {}",
+                op, ip, expected, found
+            ),
+            RuntimeError::ArgumentOutOfRange { index, count, ip } => write!(
+                f,
+                "No program argument {} at instruction {}, {} were given",
+                index, ip, count
+            ),
+            RuntimeError::IndexOutOfBounds { index, length, ip } => write!(
+                f,
+                "Index {} out of bounds at instruction {}, the array has {} element(s)",
+                index, ip, length
+            ),
+            RuntimeError::NegativeArrayLength { count, ip } => write!(
+                f,
+                "Cannot make an array of {} elements at instruction {}",
+                count, ip
+            ),
+            RuntimeError::UserPanic { message, ip } => {
+                write!(f, "Panicked at instruction {}: {}", ip, message)
+            }
+            RuntimeError::ExitCodeOutOfRange { code, ip } => write!(
+                f,
+                "Exit code {} at instruction {} is outside of the i32 range",
+                code, ip
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Bool(bool), // Result of comparisons, counts as 1 or 0 where an integer is expected
+    String(String),
+    Array(Vec<Value>), // copied like every other value, only an element write changes it in place
+    Nil,               // Returned by void functions, only good for being discarded
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Bool(_) => "bool",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Nil => "nil",
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Array(elements) => write!(
+                f,
+                "[{}]",
+                elements
+                    .iter()
+                    .map(Value::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            Value::Nil => write!(f, "nil"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithmeticMode {
+    Wrapping,   // Results wrap around on overflow, the default
+    Checked,    // Overflow stops the program with ArithmeticOverflow
+    Saturating, // Results clamp to the smallest or largest value on overflow
+}
 
 pub struct VirtualMachine {
-    stack: Vec<i64>,
-    variables: HashMap<String, i64>,
+    stack: Vec<Value>,
+    variables: BTreeMap<Symbol, Value>, // ordered, so dumps list globals the same way every run
     instructions: Vec<OpCode>,
+    constants: Vec<i64>,
+    symbols: Interner,
     instruction_pointer: usize,
-    call_stack: Vec<usize>,
     stack_frames: Vec<Frame>,
-    functions: HashMap<String, usize>,
+    functions: BTreeMap<Symbol, usize>,
+    input: Box<dyn BufRead>,
+    arithmetic_mode: ArithmeticMode,
+    exit_code: Option<i32>, // set by HALT, None when the program ran off its end
+    profile: Option<HashMap<&'static str, u64>>, // executions of each opcode, None unless profiling
+    arguments: Vec<i64>,    // what `args()` and `arg(i)` read
+    trace: Option<Box<dyn Write>>, // one JSON line per executed instruction, None unless tracing
+    output: Box<dyn Write>, // where print writes, stdout unless replaced
+    error_output: Box<dyn Write>, // where eprint writes, stderr unless replaced
+    read_only: HashSet<Symbol>, // the globals set by with_constants
 }
 
 #[derive(Debug)]
 struct Frame {
-    local_variables: HashMap<String, i64>,
+    locals: Vec<Option<Value>>, // Indexed by the slots the code generator assigns
     return_address: usize,
 }
 
 impl VirtualMachine {
-    pub fn new(instructions: Vec<OpCode>) -> Self {
+    pub fn new(instructions: Vec<OpCode>, constants: Vec<i64>, symbols: Interner) -> Self {
+        Self::with_input(
+            instructions,
+            constants,
+            symbols,
+            // locked for each read only, a lock held for the VM's lifetime would
+            // block any other VM reading on the same thread
+            Box::new(BufReader::new(io::stdin())),
+        )
+    }
+
+    // seeded globals behave like globals declared by the program,
+    // so function locals with the same name shadow them
+    pub fn with_globals(
+        instructions: Vec<OpCode>,
+        constants: Vec<i64>,
+        symbols: Interner,
+        globals: HashMap<String, i64>,
+    ) -> Self {
+        let mut vm = Self::new(instructions, constants, symbols);
+        // interned in name order, the map's own order changes from run to run
+        let globals: BTreeMap<String, i64> = globals.into_iter().collect();
+        for (name, value) in globals {
+            let symbol = vm.symbols.intern(&name);
+            vm.variables.insert(symbol, Value::Int(value));
+        }
+        vm
+    }
+
+    // constants are read like seeded globals, but storing to one, even from a
+    // declaration, is a runtime error. Function locals can still shadow them
+    pub fn with_constants(
+        instructions: Vec<OpCode>,
+        constants: Vec<i64>,
+        symbols: Interner,
+        values: HashMap<String, i64>,
+    ) -> Self {
+        let names: Vec<String> = values.keys().cloned().collect();
+        let mut vm = Self::with_globals(instructions, constants, symbols, values);
+        for name in names {
+            let symbol = vm.symbols.intern(&name);
+            vm.read_only.insert(symbol);
+        }
+        vm
+    }
+
+    pub fn with_input(
+        instructions: Vec<OpCode>,
+        constants: Vec<i64>,
+        symbols: Interner,
+        input: Box<dyn BufRead>,
+    ) -> Self {
+        // every function can be called before its DECLARE runs, a name declared
+        // several times starts out as its first declaration
+        let mut functions = BTreeMap::new();
+        for (position, opcode) in instructions.iter().enumerate() {
+            if let OpCode::DECLARE(name, _) = opcode {
+                functions.entry(*name).or_insert(position + 1);
+            }
+        }
         Self {
             stack: vec![],
-            variables: HashMap::new(),
+            variables: BTreeMap::new(),
             instructions,
+            constants,
+            symbols,
             instruction_pointer: 0,
-            call_stack: vec![],
             stack_frames: vec![],
-            functions: HashMap::new(),
+            functions,
+            input,
+            arithmetic_mode: ArithmeticMode::Wrapping,
+            exit_code: None,
+            profile: None,
+            arguments: vec![],
+            trace: None,
+            output: Box::new(io::stdout()),
+            error_output: Box::new(io::stderr()),
+            read_only: HashSet::new(),
         }
     }
 
-    pub fn run(&mut self) {
-        while self.instruction_pointer < self.instructions.len() {
-            self.execute(&self.get_current_opcode().clone());
+    pub fn set_arithmetic_mode(&mut self, mode: ArithmeticMode) {
+        self.arithmetic_mode = mode;
+    }
+
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    // execute a single instruction, false once the program has finished
+    pub fn step(&mut self) -> Result<bool, RuntimeError> {
+        if self.instruction_pointer >= self.instructions.len() {
+            return Ok(false);
         }
+        let ip = self.instruction_pointer;
+        let opcode = self.get_current_opcode().clone();
+        self.execute(&opcode)?;
+        self.write_trace(ip, &opcode)?;
+        Ok(self.instruction_pointer < self.instructions.len())
+    }
+
+    // Newline-delimited JSON, a line for each instruction once it has run:
+    // {"ip":3,"opcode":"PUSHC","instruction":"PUSHC 0 ; 5","stack":[5]}
+    // with the stack from bottom to top, nil as null. An instruction which
+    // fails gets no line
+    pub fn set_trace(&mut self, writer: Box<dyn Write>) {
+        self.trace = Some(writer);
+    }
+
+    // print's output, with the frames allocated for calls
+    pub fn set_output(&mut self, writer: Box<dyn Write>) {
+        self.output = writer;
+    }
+
+    // eprint's output, to capture it apart from print's
+    pub fn set_error_output(&mut self, writer: Box<dyn Write>) {
+        self.error_output = writer;
+    }
+
+    fn write_trace(&mut self, ip: usize, opcode: &OpCode) -> Result<(), RuntimeError> {
+        let Some(trace) = &mut self.trace else {
+            return Ok(());
+        };
+        let stack = self
+            .stack
+            .iter()
+            .map(json_value)
+            .collect::<Vec<_>>()
+            .join(",");
+        let instruction = disassembler::instruction(opcode, &self.constants, &self.symbols);
+        writeln!(
+            trace,
+            "{{\"ip\":{},\"opcode\":{},\"instruction\":{},\"stack\":[{}]}}",
+            ip,
+            ast_json::string(opcode.name()),
+            ast_json::string(&instruction),
+            stack
+        )
+        .map_err(|e| RuntimeError::TraceFailed(e.to_string()))
     }
 
-    fn execute(&mut self, opcode: &OpCode) {
+    // the loaded program with the instruction about to run marked, for debugging
+    pub fn dump_instructions(&self) -> String {
+        disassembler::listing(
+            &self.instructions,
+            &self.constants,
+            &self.symbols,
+            Some(self.instruction_pointer),
+        )
+    }
+
+    // the arguments the program was started with, none by default
+    pub fn set_arguments(&mut self, arguments: Vec<i64>) {
+        self.arguments = arguments;
+    }
+
+    // counting opcodes costs a lookup per instruction, so it is off by default
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profile = enabled.then(HashMap::new);
+    }
+
+    // how many times each opcode ran, empty when profiling is off
+    pub fn profile(&self) -> HashMap<&'static str, u64> {
+        self.profile.clone().unwrap_or_default()
+    }
+
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    // every global with its value, one per line in the order they were first named
+    pub fn dump_globals(&self) -> String {
+        self.variables
+            .iter()
+            .map(|(symbol, value)| format!("{} = {}\n", self.symbols.resolve(*symbol), value))
+            .collect()
+    }
+
+    pub fn global(&self, name: &str) -> Option<Value> {
+        let symbol = self.symbols.get(name)?;
+        self.variables.get(&symbol).cloned()
+    }
+
+    fn execute(&mut self, opcode: &OpCode) -> Result<(), RuntimeError> {
+        if let Some(profile) = &mut self.profile {
+            *profile.entry(opcode.name()).or_insert(0) += 1;
+        }
         match opcode {
-            OpCode::PUSH(value) => self.stack.push(*value),
-            // OpCode::POP => {
-            //     self.stack.pop().expect("Stack underflow on POP");
-            // }
+            OpCode::PUSHC(index) => {
+                let value = *self
+                    .constants
+                    .get(*index)
+                    .unwrap_or_else(|| panic!("Undefined constant index: {}", index));
+                self.stack.push(Value::Int(value));
+            }
+            OpCode::POP => {
+                self.pop("POP")?;
+            }
+            OpCode::NIL => self.stack.push(Value::Nil),
+            OpCode::PUSHB(value) => self.stack.push(Value::Bool(*value)),
+            OpCode::PUSHS(symbol) => {
+                let value = self.symbols.resolve(*symbol).to_string();
+                self.stack.push(Value::String(value));
+            }
+            OpCode::DUP => {
+                let value = self.pop("DUP")?;
+                self.stack.push(value.clone());
+                self.stack.push(value);
+            }
             OpCode::PRINT => {
-                if let Some(value) = self.stack.pop() {
-                    println!("{}", value);
-                } else {
-                    panic!("Stack underflow on PRINT");
+                let value = self.pop("PRINT")?;
+                if value == Value::Nil {
+                    return Err(RuntimeError::NilValue {
+                        op: "PRINT",
+                        ip: self.instruction_pointer,
+                    });
+                }
+                writeln!(self.output, "{}", value)
+                    .map_err(|e| RuntimeError::OutputFailed(e.to_string()))?;
+            }
+            OpCode::PRINTERR => {
+                let value = self.pop("PRINTERR")?;
+                if value == Value::Nil {
+                    return Err(RuntimeError::NilValue {
+                        op: "PRINTERR",
+                        ip: self.instruction_pointer,
+                    });
+                }
+                writeln!(self.error_output, "{}", value)
+                    .map_err(|e| RuntimeError::OutputFailed(e.to_string()))?;
+            }
+            OpCode::READ => {
+                let mut line = String::new();
+                self.input
+                    .read_line(&mut line)
+                    .map_err(|e| RuntimeError::InputFailed(e.to_string()))?;
+                let value = line
+                    .trim()
+                    .parse::<i64>()
+                    .map_err(|_| RuntimeError::InvalidInput(line.trim().to_string()))?;
+                self.stack.push(Value::Int(value));
+            }
+            OpCode::ARGS => self.stack.push(Value::Int(self.arguments.len() as i64)),
+            OpCode::ARG => {
+                let index = self.pop_int("ARG")?;
+                let argument = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| self.arguments.get(index))
+                    .ok_or(RuntimeError::ArgumentOutOfRange {
+                        index,
+                        count: self.arguments.len(),
+                        ip: self.instruction_pointer,
+                    })?;
+                self.stack.push(Value::Int(*argument));
+            }
+            OpCode::ASSERT(position) => {
+                let condition = self.pop_int("ASSERT")?;
+                if condition == 0 {
+                    return Err(RuntimeError::AssertionFailed {
+                        position: *position,
+                    });
                 }
             }
 
             // Arithmetic
-            OpCode::ADD => self.binary_operation(|a, b| a + b),
-            OpCode::SUB => self.binary_operation(|a, b| a - b),
-            OpCode::MUL => self.binary_operation(|a, b| a * b),
-            OpCode::DIV => self.binary_operation(|a, b| a / b),
+            OpCode::ADD => self.add()?,
+            OpCode::SUB => self.arithmetic_operation(
+                "SUB",
+                i64::checked_sub,
+                i64::wrapping_sub,
+                i64::saturating_sub,
+            )?,
+            OpCode::MUL => self.arithmetic_operation(
+                "MUL",
+                i64::checked_mul,
+                i64::wrapping_mul,
+                i64::saturating_mul,
+            )?,
+            OpCode::DIV => {
+                self.check_divisor()?;
+                self.arithmetic_operation(
+                    "DIV",
+                    i64::checked_div,
+                    i64::wrapping_div,
+                    i64::saturating_div,
+                )?
+            }
+            OpCode::MOD => {
+                self.check_divisor()?;
+                // only i64::MIN % -1 overflows, and 0 is the right answer there
+                self.arithmetic_operation(
+                    "MOD",
+                    i64::checked_rem,
+                    i64::wrapping_rem,
+                    i64::wrapping_rem,
+                )?
+            }
+            OpCode::POW => {
+                self.check_exponent()?;
+                self.arithmetic_operation(
+                    "POW",
+                    |a, b| power(a, b, i64::checked_mul),
+                    |a, b| power(a, b, |x, y| Some(x.wrapping_mul(y))).unwrap(),
+                    // an overflow saturates toward the sign of the exact result
+                    |a, b| {
+                        power(a, b, i64::checked_mul).unwrap_or(if a < 0 && b % 2 == 1 {
+                            i64::MIN
+                        } else {
+                            i64::MAX
+                        })
+                    },
+                )?
+            }
+
+            OpCode::ABS => {
+                let value = self.pop_int("ABS")?;
+                let result = match self.arithmetic_mode {
+                    ArithmeticMode::Wrapping => value.wrapping_abs(),
+                    ArithmeticMode::Saturating => value.saturating_abs(),
+                    ArithmeticMode::Checked => {
+                        value
+                            .checked_abs()
+                            .ok_or(RuntimeError::ArithmeticOverflow {
+                                op: "ABS",
+                                ip: self.instruction_pointer,
+                            })?
+                    }
+                };
+                self.stack.push(Value::Int(result));
+            }
+            OpCode::WRAP32 => {
+                let value = self.pop_int("WRAP32")?;
+                let result = match self.arithmetic_mode {
+                    ArithmeticMode::Wrapping => value as i32 as i64,
+                    ArithmeticMode::Saturating => value.clamp(i32::MIN as i64, i32::MAX as i64),
+                    ArithmeticMode::Checked => {
+                        i32::try_from(value).map_err(|_| RuntimeError::ArithmeticOverflow {
+                            op: "WRAP32",
+                            ip: self.instruction_pointer,
+                        })? as i64
+                    }
+                };
+                self.stack.push(Value::Int(result));
+            }
+            OpCode::MIN => self.binary_operation("MIN", |a, b| Value::Int(a.min(b)))?,
+            OpCode::MAX => self.binary_operation("MAX", |a, b| Value::Int(a.max(b)))?,
+            // the arithmetic mode doesn't apply to these
+            OpCode::WADD => self.binary_operation("WADD", |a, b| Value::Int(a.wrapping_add(b)))?,
+            OpCode::WMUL => self.binary_operation("WMUL", |a, b| Value::Int(a.wrapping_mul(b)))?,
 
             // Variable operations
             OpCode::STORE(name) => {
-                let top_value = self.stack.pop().expect("Stack underflow on STORE");
-
-                // check if currently inside a function
-                if !self.stack_frames.is_empty() {
-                    // push variables to the function's local variables list
-                    self.stack_frames
-                        .last_mut()
-                        .unwrap()
-                        .local_variables
-                        .insert(name.clone(), top_value);
-                } else {
-                    self.variables.insert(name.clone(), top_value);
-                }
+                self.check_writable(*name)?;
+                let top_value = self.pop("STORE")?;
+                self.variables.insert(*name, top_value);
+            }
+            OpCode::STOREI(name, value) => {
+                self.check_writable(*name)?;
+                self.variables.insert(*name, Value::Int(*value));
             }
             OpCode::LOAD(name) => {
+                let value = self.variables.get(name).cloned().ok_or_else(|| {
+                    RuntimeError::UndefinedVariable {
+                        name: self.symbols.resolve(*name).to_string(),
+                        ip: self.instruction_pointer,
+                    }
+                })?;
+                self.stack.push(value);
+            }
+            OpCode::INCR(name, step) => {
+                self.check_writable(*name)?;
+                let value = self.variables.get(name).cloned().ok_or_else(|| {
+                    RuntimeError::UndefinedVariable {
+                        name: self.symbols.resolve(*name).to_string(),
+                        ip: self.instruction_pointer,
+                    }
+                })?;
+                let value = self.increment(value, *step)?;
+                self.variables.insert(*name, value);
+            }
+            OpCode::IncrLocal(slot, step) => {
                 let value = self
-                    .get_variable(name)
-                    .unwrap_or_else(|| panic!("Undefined variable: {}", name));
+                    .stack_frames
+                    .last()
+                    .expect("No frame on IncrLocal")
+                    .locals
+                    .get(*slot)
+                    .cloned()
+                    .flatten()
+                    .unwrap_or_else(|| panic!("Uninitialized local slot: {}", slot));
+                let value = self.increment(value, *step)?;
+                let frame = self.stack_frames.last_mut().expect("No frame on IncrLocal");
+                frame.locals[*slot] = Some(value);
+            }
+            OpCode::StoreLocal(slot) => {
+                let top_value = self.pop("StoreLocal")?;
+                let frame = self
+                    .stack_frames
+                    .last_mut()
+                    .expect("No frame on StoreLocal");
+                if *slot >= frame.locals.len() {
+                    frame.locals.resize(slot + 1, None);
+                }
+                frame.locals[*slot] = Some(top_value);
+            }
+            OpCode::LoadLocal(slot) => {
+                let value = self
+                    .stack_frames
+                    .last()
+                    .expect("No frame on LoadLocal")
+                    .locals
+                    .get(*slot)
+                    .cloned()
+                    .flatten()
+                    .unwrap_or_else(|| panic!("Uninitialized local slot: {}", slot));
                 self.stack.push(value);
             }
 
-            OpCode::DECLARE(name) => {
+            // Arrays
+            OpCode::MakeArray(count) => {
+                let start =
+                    self.stack
+                        .len()
+                        .checked_sub(*count)
+                        .ok_or(RuntimeError::StackUnderflow {
+                            op: "MakeArray",
+                            ip: self.instruction_pointer,
+                        })?;
+                let elements = self.stack.split_off(start);
+                if elements.contains(&Value::Nil) {
+                    return Err(RuntimeError::NilValue {
+                        op: "MakeArray",
+                        ip: self.instruction_pointer,
+                    });
+                }
+                self.stack.push(Value::Array(elements));
+            }
+            OpCode::RepeatArray => {
+                let count = self.pop_int("RepeatArray")?;
+                let value = self.pop("RepeatArray")?;
+                if value == Value::Nil {
+                    return Err(RuntimeError::NilValue {
+                        op: "RepeatArray",
+                        ip: self.instruction_pointer,
+                    });
+                }
+                let length =
+                    usize::try_from(count).map_err(|_| RuntimeError::NegativeArrayLength {
+                        count,
+                        ip: self.instruction_pointer,
+                    })?;
+                self.stack.push(Value::Array(vec![value; length]));
+            }
+            OpCode::LoadIndex => {
+                let index = self.pop_int("LoadIndex")?;
+                let elements = self.pop_array("LoadIndex")?;
+                let slot = array_slot(index, elements.len(), self.instruction_pointer)?;
+                self.stack.push(elements[slot].clone());
+            }
+            OpCode::StoreIndex(name) => {
+                let (index, value) = self.pop_element("StoreIndex")?;
+                self.check_writable(*name)?;
+                let ip = self.instruction_pointer;
+                let target = self.variables.get_mut(name).ok_or_else(|| {
+                    RuntimeError::UndefinedVariable {
+                        name: self.symbols.resolve(*name).to_string(),
+                        ip,
+                    }
+                })?;
+                store_element(target, index, value, "StoreIndex", ip)?;
+            }
+            OpCode::StoreIndexLocal(slot) => {
+                let (index, value) = self.pop_element("StoreIndexLocal")?;
+                let ip = self.instruction_pointer;
+                let target = self
+                    .stack_frames
+                    .last_mut()
+                    .expect("No frame on StoreIndexLocal")
+                    .locals
+                    .get_mut(*slot)
+                    .and_then(Option::as_mut)
+                    .unwrap_or_else(|| panic!("Uninitialized local slot: {}", slot));
+                store_element(target, index, value, "StoreIndexLocal", ip)?;
+            }
+            // a string's length counts characters, not bytes
+            OpCode::LEN => {
+                let length = match self.pop("LEN")? {
+                    Value::Array(elements) => elements.len(),
+                    Value::String(value) => value.chars().count(),
+                    Value::Nil => {
+                        return Err(RuntimeError::NilValue {
+                            op: "LEN",
+                            ip: self.instruction_pointer,
+                        })
+                    }
+                    value => {
+                        return Err(RuntimeError::TypeMismatch {
+                            op: "LEN",
+                            expected: "array or string",
+                            found: value.type_name(),
+                            ip: self.instruction_pointer,
+                        })
+                    }
+                };
+                self.stack.push(Value::Int(length as i64));
+            }
+
+            OpCode::DECLARE(name, _) => {
                 // skip declare opcode to go to enter opcode
-                self.functions
-                    .insert(name.clone(), self.instruction_pointer + 1);
+                self.functions.insert(*name, self.instruction_pointer + 1);
 
                 // skip handle function until
                 while !matches!(self.instructions[self.instruction_pointer], OpCode::EXIT) {
@@ -91,35 +782,17 @@ impl VirtualMachine {
             }
 
             // Function operations
-            OpCode::CALL(name) => {
-                let next_instruction = self.instruction_pointer + 1;
-                // Locate function and set up a new frame
-                let frame = Frame {
-                    local_variables: HashMap::new(),
-                    return_address: next_instruction,
-                };
-                self.stack_frames.push(frame);
-                println!("Allocate stack frame for function: {:?}", name);
-                // Jump to the function's start (implement function mapping logic)
-                self.call_stack.push(next_instruction);
-                self.instruction_pointer = self.find_function_start(name);
-            }
-            OpCode::TailCall(name) => {
-                // Tail call replaces the current frame
-                let frame = self
-                    .stack_frames
-                    .last_mut()
-                    .expect("No frame for tail call");
-                frame.local_variables.clear();
-                println!("Tail call - reuse stack frame for function: {}", name);
-                // Jump to the function's start
-                self.instruction_pointer = self.find_function_start(name);
+            OpCode::CALL(name) => self.call(*name, self.find_function_start(*name)?)?,
+            OpCode::TailCall(name) => self.tail_call(*name, self.find_function_start(*name)?)?,
+            OpCode::CallAddr(address) => self.call(self.function_name(*address), *address)?,
+            OpCode::TailCallAddr(address) => {
+                self.tail_call(self.function_name(*address), *address)?
             }
             OpCode::RET => {
                 if let Some(frame) = self.stack_frames.pop() {
                     self.instruction_pointer = frame.return_address;
                     // skip jumping to the next instruction
-                    return;
+                    return Ok(());
                 } else {
                     panic!("Return with no active frame");
                 }
@@ -134,62 +807,297 @@ impl VirtualMachine {
             OpCode::JUMP(address) => {
                 self.instruction_pointer = *address;
                 // skip jumping to the next instruction
-                return;
+                return Ok(());
             }
             OpCode::JmpIfFalse(address) => {
-                if let Some(condition) = self.stack.pop() {
-                    if condition == 0 {
-                        self.instruction_pointer = *address;
-                        // skip jumping to the next instruction
-                        return;
-                    }
-                } else {
-                    panic!("Stack underflow on JmpIfFalse");
+                if self.pop_int("JmpIfFalse")? == 0 {
+                    self.instruction_pointer = *address;
+                    // skip jumping to the next instruction
+                    return Ok(());
+                }
+            }
+            OpCode::JmpIfTrue(address) => {
+                if self.pop_int("JmpIfTrue")? != 0 {
+                    self.instruction_pointer = *address;
+                    // skip jumping to the next instruction
+                    return Ok(());
+                }
+            }
+            // the operand is checked like EQUAL checks it, so a nil or a string is an error
+            OpCode::JZ(address) => {
+                if self.pop_int("JZ")? == 0 {
+                    self.instruction_pointer = *address;
+                    // skip jumping to the next instruction
+                    return Ok(());
+                }
+            }
+            OpCode::JNZ(address) => {
+                if self.pop_int("JNZ")? != 0 {
+                    self.instruction_pointer = *address;
+                    // skip jumping to the next instruction
+                    return Ok(());
                 }
             }
-            // OpCode::JmpIfTrue(address) => {
-            //     if let Some(condition) = self.stack.pop() {
-            //         if condition != 0 {
-            //             self.instruction_pointer = *address;
-            //             // skip jumping to the next instruction
-            //             return;
-            //         }
-            //     } else {
-            //         panic!("Stack underflow on JmpIfTrue");
-            //     }
-            // }
 
             // Comparison operations
-            OpCode::EQUAL => self.binary_operation(|a, b| (a == b) as i64),
-            OpCode::NotEqual => self.binary_operation(|a, b| (a != b) as i64),
+            OpCode::EQUAL => self.binary_operation("EQUAL", |a, b| Value::Bool(a == b))?,
+            OpCode::NotEqual => self.binary_operation("NotEqual", |a, b| Value::Bool(a != b))?,
+            OpCode::NOT => {
+                let value = self.pop_int("NOT")?;
+                self.stack.push(Value::Bool(value == 0));
+            }
+
+            OpCode::PANIC => {
+                return Err(match self.pop("PANIC")? {
+                    Value::String(message) => RuntimeError::UserPanic {
+                        message,
+                        ip: self.instruction_pointer,
+                    },
+                    Value::Nil => RuntimeError::NilValue {
+                        op: "PANIC",
+                        ip: self.instruction_pointer,
+                    },
+                    value => RuntimeError::TypeMismatch {
+                        op: "PANIC",
+                        expected: "string",
+                        found: value.type_name(),
+                        ip: self.instruction_pointer,
+                    },
+                });
+            }
+            OpCode::HALT => {
+                // the host's exit status is an i32, a wider code isn't cut down
+                let code = self.pop_int("HALT")?;
+                let code = i32::try_from(code).map_err(|_| RuntimeError::ExitCodeOutOfRange {
+                    code,
+                    ip: self.instruction_pointer,
+                })?;
+                self.exit_code = Some(code);
+                // moving past the last instruction ends the run
+                self.instruction_pointer = self.instructions.len();
+                return Ok(());
+            }
         }
 
         self.next_instruction();
+        Ok(())
+    }
+
+    fn arithmetic_operation(
+        &mut self,
+        op: &'static str,
+        checked: fn(i64, i64) -> Option<i64>,
+        wrapping: fn(i64, i64) -> i64,
+        saturating: fn(i64, i64) -> i64,
+    ) -> Result<(), RuntimeError> {
+        let b = self.pop_int(op)?;
+        let a = self.pop_int(op)?;
+        let value = match self.arithmetic_mode {
+            ArithmeticMode::Wrapping => wrapping(a, b),
+            ArithmeticMode::Saturating => saturating(a, b),
+            ArithmeticMode::Checked => checked(a, b).ok_or(RuntimeError::ArithmeticOverflow {
+                op,
+                ip: self.instruction_pointer,
+            })?,
+        };
+        self.stack.push(Value::Int(value));
+        Ok(())
+    }
+
+    // the value with `step` added, overflowing and type checked like ADD
+    fn increment(&mut self, value: Value, step: i64) -> Result<Value, RuntimeError> {
+        self.stack.push(value);
+        self.stack.push(Value::Int(step));
+        self.arithmetic_operation(
+            "INCR",
+            i64::checked_add,
+            i64::wrapping_add,
+            i64::saturating_add,
+        )?;
+        self.pop("INCR")
     }
 
-    fn binary_operation<F>(&mut self, op: F)
+    // two strings are concatenated, a string and anything else can't be added
+    fn add(&mut self) -> Result<(), RuntimeError> {
+        let b = self.pop("ADD")?;
+        let a = self.pop("ADD")?;
+        match (a, b) {
+            (Value::String(a), Value::String(b)) => {
+                self.stack.push(Value::String(a + &b));
+                Ok(())
+            }
+            (Value::String(_), other) | (other, Value::String(_)) => {
+                Err(RuntimeError::TypeMismatch {
+                    op: "ADD",
+                    expected: "string",
+                    found: other.type_name(),
+                    ip: self.instruction_pointer,
+                })
+            }
+            (a, b) => {
+                self.stack.push(a);
+                self.stack.push(b);
+                self.arithmetic_operation(
+                    "ADD",
+                    i64::checked_add,
+                    i64::wrapping_add,
+                    i64::saturating_add,
+                )
+            }
+        }
+    }
+
+    fn check_divisor(&self) -> Result<(), RuntimeError> {
+        match self.stack.last() {
+            Some(Value::Int(0)) => Err(RuntimeError::DivisionByZero {
+                ip: self.instruction_pointer,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn pop_array(&mut self, op: &'static str) -> Result<Vec<Value>, RuntimeError> {
+        match self.pop(op)? {
+            Value::Array(elements) => Ok(elements),
+            Value::Nil => Err(RuntimeError::NilValue {
+                op,
+                ip: self.instruction_pointer,
+            }),
+            value => Err(RuntimeError::TypeMismatch {
+                op,
+                expected: "array",
+                found: value.type_name(),
+                ip: self.instruction_pointer,
+            }),
+        }
+    }
+
+    // the index and the value of an element write, a nil can't be stored
+    fn pop_element(&mut self, op: &'static str) -> Result<(i64, Value), RuntimeError> {
+        let value = self.pop(op)?;
+        if value == Value::Nil {
+            return Err(RuntimeError::NilValue {
+                op,
+                ip: self.instruction_pointer,
+            });
+        }
+        Ok((self.pop_int(op)?, value))
+    }
+
+    fn check_writable(&self, name: Symbol) -> Result<(), RuntimeError> {
+        if self.read_only.contains(&name) {
+            return Err(RuntimeError::AssignToConstant {
+                name: self.symbols.resolve(name).to_string(),
+                ip: self.instruction_pointer,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_exponent(&self) -> Result<(), RuntimeError> {
+        match self.stack.last() {
+            Some(Value::Int(exponent)) if *exponent < 0 => Err(RuntimeError::NegativeExponent {
+                exponent: *exponent,
+                ip: self.instruction_pointer,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    fn binary_operation<F>(&mut self, op_name: &'static str, op: F) -> Result<(), RuntimeError>
     where
-        F: FnOnce(i64, i64) -> i64,
+        F: FnOnce(i64, i64) -> Value,
     {
-        if let (Some(b), Some(a)) = (self.stack.pop(), self.stack.pop()) {
-            self.stack.push(op(a, b));
-        } else {
-            panic!("Stack underflow on binary operation");
+        let b = self.pop_int(op_name)?;
+        let a = self.pop_int(op_name)?;
+        self.stack.push(op(a, b));
+        Ok(())
+    }
+
+    fn pop(&mut self, op: &'static str) -> Result<Value, RuntimeError> {
+        self.stack.pop().ok_or(RuntimeError::StackUnderflow {
+            op,
+            ip: self.instruction_pointer,
+        })
+    }
+
+    // operands have to be integers, a nil left by a void function is an error
+    fn pop_int(&mut self, op: &'static str) -> Result<i64, RuntimeError> {
+        match self.pop(op)? {
+            Value::Int(value) => Ok(value),
+            Value::Bool(value) => Ok(value as i64),
+            Value::Nil => Err(RuntimeError::NilValue {
+                op,
+                ip: self.instruction_pointer,
+            }),
+            value @ (Value::String(_) | Value::Array(_)) => Err(RuntimeError::TypeMismatch {
+                op,
+                expected: "int",
+                found: value.type_name(),
+                ip: self.instruction_pointer,
+            }),
         }
     }
 
-    fn find_function_start(&self, name: &String) -> usize {
-        *self
-            .functions
-            .get(name)
-            .unwrap_or_else(|| panic!("Undefined function name: {}", name))
+    fn call(&mut self, name: Symbol, address: usize) -> Result<(), RuntimeError> {
+        let next_instruction = self.instruction_pointer + 1;
+        // Locate function and set up a new frame
+        let frame = Frame {
+            locals: vec![],
+            return_address: next_instruction,
+        };
+        self.stack_frames.push(frame);
+        writeln!(
+            self.output,
+            "Allocate stack frame for function: {:?}",
+            self.symbols.resolve(name)
+        )
+        .map_err(|e| RuntimeError::OutputFailed(e.to_string()))?;
+        // Jump to the function's start
+        self.instruction_pointer = address;
+        Ok(())
     }
 
-    fn get_variable(&self, name: &str) -> Option<i64> {
-        self.variables
-            .get(name)
-            .or_else(|| self.stack_frames.last()?.local_variables.get(name))
+    fn tail_call(&mut self, name: Symbol, address: usize) -> Result<(), RuntimeError> {
+        // Tail call replaces the current frame, but keeps its return address:
+        // the callee, even a different function, returns straight to the
+        // original caller. The verifier has checked that it leaves as many
+        // values as that caller expects
+        let frame = self
+            .stack_frames
+            .last_mut()
+            .expect("No frame for tail call");
+        frame.locals.clear();
+        writeln!(
+            self.output,
+            "Tail call - reuse stack frame for function: {}",
+            self.symbols.resolve(name)
+        )
+        .map_err(|e| RuntimeError::OutputFailed(e.to_string()))?;
+        // Jump to the function's start
+        self.instruction_pointer = address;
+        Ok(())
+    }
+
+    // a resolved call address points at the ENTER right after the DECLARE
+    fn function_name(&self, address: usize) -> Symbol {
+        match address
+            .checked_sub(1)
+            .and_then(|position| self.instructions.get(position))
+        {
+            Some(OpCode::DECLARE(name, _)) => *name,
+            _ => panic!("No function at address: {}", address),
+        }
+    }
+
+    fn find_function_start(&self, name: Symbol) -> Result<usize, RuntimeError> {
+        self.functions
+            .get(&name)
             .copied()
+            .ok_or_else(|| RuntimeError::UndefinedFunction {
+                name: self.symbols.resolve(name).to_string(),
+                ip: self.instruction_pointer,
+            })
     }
 
     fn get_current_opcode(&self) -> &OpCode {
@@ -200,3 +1108,673 @@ impl VirtualMachine {
         self.instruction_pointer += 1;
     }
 }
+
+// a value in the trace, nil as null
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Int(value) => value.to_string(),
+        Value::Bool(value) => value.to_string(),
+        Value::String(value) => ast_json::string(value),
+        Value::Array(elements) => format!(
+            "[{}]",
+            elements
+                .iter()
+                .map(json_value)
+                .collect::<Vec<_>>()
+                .join(",")
+        ),
+        Value::Nil => "null".to_string(),
+    }
+}
+
+// `base` to a non negative `exponent` by squaring, `multiply` decides what an
+// overflow gives. i64::pow only takes a u32 exponent, 1 ** 5000000000 is still 1
+fn power(base: i64, exponent: i64, multiply: fn(i64, i64) -> Option<i64>) -> Option<i64> {
+    let (mut result, mut base, mut exponent) = (1i64, base, exponent);
+    while exponent > 0 {
+        if exponent & 1 == 1 {
+            result = multiply(result, base)?;
+        }
+        exponent >>= 1;
+        if exponent > 0 {
+            base = multiply(base, base)?;
+        }
+    }
+    Some(result)
+}
+
+// where `index` is in an array of `length` elements
+fn array_slot(index: i64, length: usize, ip: usize) -> Result<usize, RuntimeError> {
+    usize::try_from(index)
+        .ok()
+        .filter(|slot| *slot < length)
+        .ok_or(RuntimeError::IndexOutOfBounds { index, length, ip })
+}
+
+// writes the element of the array held by a variable, without copying the array
+fn store_element(
+    target: &mut Value,
+    index: i64,
+    value: Value,
+    op: &'static str,
+    ip: usize,
+) -> Result<(), RuntimeError> {
+    match target {
+        Value::Array(elements) => {
+            let slot = array_slot(index, elements.len(), ip)?;
+            elements[slot] = value;
+            Ok(())
+        }
+        Value::Nil => Err(RuntimeError::NilValue { op, ip }),
+        target => Err(RuntimeError::TypeMismatch {
+            op,
+            expected: "array",
+            found: target.type_name(),
+            ip,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::io::{Cursor, Read};
+    use std::rc::Rc;
+
+    // a writer whose bytes can still be read once the VM owns it
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    fn machine(source: &str, input: &str) -> VirtualMachine {
+        let bytecode = crate::compile(source).expect("the program compiles");
+        VirtualMachine::with_input(
+            bytecode.opcodes,
+            bytecode.constants,
+            bytecode.symbols,
+            Box::new(Cursor::new(input.to_string())),
+        )
+    }
+
+    // a reader that fails like a closed pipe would
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        }
+    }
+
+    #[test]
+    fn read_takes_lines_from_the_input() {
+        let mut vm = machine(
+            "this a = read(); this b = read(); this sum = a + b;",
+            "4\n 38 \n",
+        );
+        vm.run().unwrap();
+        assert_eq!(vm.global("sum"), Some(Value::Int(42)));
+    }
+
+    #[test]
+    fn read_rejects_a_line_which_is_not_an_integer() {
+        let mut vm = machine("this n = read();", "four\n");
+        assert!(matches!(vm.run(), Err(RuntimeError::InvalidInput(input)) if input == "four"));
+    }
+
+    #[test]
+    fn read_failing_is_a_runtime_error() {
+        let bytecode = crate::compile("this n = read();").unwrap();
+        let mut vm = VirtualMachine::with_input(
+            bytecode.opcodes,
+            bytecode.constants,
+            bytecode.symbols,
+            Box::new(BufReader::new(Broken)),
+        );
+        assert!(matches!(vm.run(), Err(RuntimeError::InputFailed(_))));
+    }
+
+    // taken is 2 when JmpIfTrue branches on the condition, 1 when it falls through
+    fn branch_on(condition: i64) -> Option<Value> {
+        let mut symbols = Interner::new();
+        let taken = symbols.intern("taken");
+        let mut vm = VirtualMachine::new(
+            vec![
+                OpCode::PUSHC(0),
+                OpCode::JmpIfTrue(4),
+                OpCode::STOREI(taken, 1),
+                OpCode::JUMP(5),
+                OpCode::STOREI(taken, 2),
+            ],
+            vec![condition],
+            symbols,
+        );
+        vm.run().unwrap();
+        vm.global("taken")
+    }
+
+    #[test]
+    fn jmp_if_true_branches_on_a_true_condition() {
+        assert_eq!(branch_on(1), Some(Value::Int(2)));
+        assert_eq!(branch_on(-3), Some(Value::Int(2)));
+    }
+
+    #[test]
+    fn jmp_if_true_falls_through_on_a_false_condition() {
+        assert_eq!(branch_on(0), Some(Value::Int(1)));
+    }
+
+    #[test]
+    fn dump_marks_the_instruction_about_to_run() {
+        let mut symbols = Interner::new();
+        let n = symbols.intern("n");
+        let mut vm = VirtualMachine::new(
+            vec![
+                OpCode::PUSHC(0),
+                OpCode::PUSHC(0),
+                OpCode::ADD,
+                OpCode::STORE(n),
+            ],
+            vec![21],
+            symbols,
+        );
+        assert!(vm.dump_instructions().starts_with("->    0 PUSHC 0 ; 21\n"));
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(
+            vm.dump_instructions(),
+            "      0 PUSHC 0 ; 21\n      1 PUSHC 0 ; 21\n->    2 ADD\n      3 STORE n\n"
+        );
+    }
+
+    type Seed = fn(Vec<OpCode>, Vec<i64>, Interner, HashMap<String, i64>) -> VirtualMachine;
+
+    #[test]
+    fn integer_builtins_run_on_the_stack() {
+        // through a function, so the optimizer doesn't fold the calls
+        let mut vm = machine(
+            "fn id(n: int) -> int { return n; }; \
+             this a = abs(id(-5)); this b = abs(id(5)); \
+             this low = min(id(3), id(-2)); this high = max(id(3), id(-2));",
+            "",
+        );
+        vm.run().unwrap();
+        assert_eq!(vm.global("a"), Some(Value::Int(5)));
+        assert_eq!(vm.global("b"), Some(Value::Int(5)));
+        assert_eq!(vm.global("low"), Some(Value::Int(-2)));
+        assert_eq!(vm.global("high"), Some(Value::Int(3)));
+    }
+
+    #[test]
+    fn the_remainder_takes_the_sign_of_the_dividend() {
+        let mut vm = machine(
+            "fn rem(a: int, b: int) -> int { return a % b; }; \
+             this a = rem(-7, 3); this b = rem(7, -3); this folded = -7 % 3;",
+            "",
+        );
+        vm.run().unwrap();
+        assert_eq!(vm.global("a"), Some(Value::Int(-1)));
+        assert_eq!(vm.global("b"), Some(Value::Int(1)));
+        assert_eq!(vm.global("folded"), Some(Value::Int(-1)));
+    }
+
+    #[test]
+    fn a_two_clause_loop_condition_exits_on_either_clause() {
+        let stop_at = |limit: i64| {
+            let mut vm = machine(
+                &format!(
+                    "this i = 0; this running = 1; \
+                     while i != 5 && running != 0 {{ i = i + 1; if i == {} {{ running = 0; }}; }};",
+                    limit
+                ),
+                "",
+            );
+            vm.run().unwrap();
+            (vm.global("i"), vm.global("running"))
+        };
+        assert_eq!(stop_at(9), (Some(Value::Int(5)), Some(Value::Int(1))));
+        assert_eq!(stop_at(2), (Some(Value::Int(2)), Some(Value::Int(0))));
+    }
+
+    #[test]
+    fn the_program_reads_its_arguments() {
+        let mut vm = machine(
+            "this count = args(); this first = arg(0); this last = arg(2);",
+            "",
+        );
+        vm.set_arguments(vec![7, -1, 42]);
+        vm.run().unwrap();
+        assert_eq!(vm.global("count"), Some(Value::Int(3)));
+        assert_eq!(vm.global("first"), Some(Value::Int(7)));
+        assert_eq!(vm.global("last"), Some(Value::Int(42)));
+
+        let mut vm = machine("print(arg(0));", "");
+        assert!(matches!(
+            vm.run(),
+            Err(RuntimeError::ArgumentOutOfRange {
+                index: 0,
+                count: 0,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn an_exit_code_outside_of_i32_is_an_error() {
+        let mut vm = machine("exit(-2147483648);", "");
+        vm.run().unwrap();
+        assert_eq!(vm.exit_code(), Some(i32::MIN));
+        let mut vm = machine("exit(4294967296);", "");
+        assert!(matches!(
+            vm.run(),
+            Err(RuntimeError::ExitCodeOutOfRange {
+                code: 4294967296,
+                ..
+            })
+        ));
+        assert_eq!(vm.exit_code(), None);
+    }
+
+    #[test]
+    fn the_profile_counts_each_opcode_run() {
+        let mut vm = machine("this i = 0; while i != 3 { i = i + 1; };", "");
+        assert!(vm.profile().is_empty());
+        vm.set_profiling(true);
+        vm.run().unwrap();
+        let mut counts = vm.profile().into_iter().collect::<Vec<_>>();
+        counts.sort();
+        // the condition is tested once more than the body runs
+        assert_eq!(
+            counts,
+            vec![
+                ("INCR", 3),
+                ("JUMP", 3),
+                ("JmpIfFalse", 4),
+                ("LOAD", 4),
+                ("NotEqual", 4),
+                ("PUSHC", 4),
+                ("STOREI", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn eprint_writes_apart_from_print() {
+        let mut vm = machine("eprint(\"oops\"); eprint(7);", "");
+        let output = Captured::default();
+        let error_output = Captured::default();
+        vm.set_output(Box::new(output.clone()));
+        vm.set_error_output(Box::new(error_output.clone()));
+        vm.run().unwrap();
+        assert_eq!(error_output.text(), "oops\n7\n");
+        assert_eq!(output.text(), "");
+    }
+
+    #[test]
+    fn the_trace_has_one_line_per_executed_instruction() {
+        let mut vm = machine("this a = 2; this b = a + 3;", "");
+        let trace = Captured::default();
+        vm.set_trace(Box::new(trace.clone()));
+        vm.run().unwrap();
+        let text = trace.text();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 5);
+        // the stack is the one left by the instruction
+        assert_eq!(
+            lines[2],
+            r#"{"ip":2,"opcode":"PUSHC","instruction":"PUSHC 0 ; 3","stack":[2,3]}"#
+        );
+        for (ip, line) in lines.iter().enumerate() {
+            let fields = ["ip", "opcode", "instruction", "stack"]
+                .iter()
+                .map(|field| line.find(&format!("\"{}\":", field)))
+                .collect::<Option<Vec<_>>>()
+                .expect("every field is on the line");
+            assert!(fields.is_sorted());
+            assert_eq!(line.matches("\":").count(), 4);
+            assert!(line.starts_with(&format!("{{\"ip\":{},", ip)));
+        }
+    }
+
+    fn seeded_by(seed: Seed, source: &str, globals: &[(&str, i64)]) -> VirtualMachine {
+        let globals: HashMap<String, i64> = globals
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect();
+        let names = globals.keys().cloned().collect();
+        let bytecode = crate::compile_with_globals(source, &names).unwrap();
+        seed(
+            bytecode.opcodes,
+            bytecode.constants,
+            bytecode.symbols,
+            globals,
+        )
+    }
+
+    fn seeded(source: &str, globals: &[(&str, i64)]) -> VirtualMachine {
+        seeded_by(VirtualMachine::with_globals, source, globals)
+    }
+
+    #[test]
+    fn seeded_globals_are_readable() {
+        let mut vm = seeded("this twice = k * 2;", &[("k", 21)]);
+        vm.run().unwrap();
+        assert_eq!(vm.global("twice"), Some(Value::Int(42)));
+    }
+
+    #[test]
+    fn assigning_a_seeded_global_is_visible_to_the_host() {
+        let mut vm = seeded("k = k + 3;", &[("k", 4), ("unused", 1)]);
+        vm.run().unwrap();
+        assert_eq!(vm.global("k"), Some(Value::Int(7)));
+        assert_eq!(vm.global("unused"), Some(Value::Int(1)));
+    }
+
+    #[test]
+    fn function_locals_shadow_seeded_globals() {
+        let mut vm = seeded(
+            "fn f(k: int) -> int { k = k + 1; return k; }; this r = f(10);",
+            &[("k", 4)],
+        );
+        vm.run().unwrap();
+        assert_eq!(vm.global("r"), Some(Value::Int(11)));
+        assert_eq!(vm.global("k"), Some(Value::Int(4)));
+    }
+
+    #[test]
+    fn host_constants_are_readable() {
+        let mut vm = seeded_by(
+            VirtualMachine::with_constants,
+            "this twice = limit * 2;",
+            &[("limit", 21)],
+        );
+        vm.run().unwrap();
+        assert_eq!(vm.global("twice"), Some(Value::Int(42)));
+    }
+
+    #[test]
+    fn assigning_a_host_constant_fails() {
+        let mut vm = seeded_by(
+            VirtualMachine::with_constants,
+            "print(limit); limit = 3;",
+            &[("limit", 21)],
+        );
+        let output = Captured::default();
+        vm.set_output(Box::new(output.clone()));
+        assert!(matches!(
+            vm.run(),
+            Err(RuntimeError::AssignToConstant { name, .. }) if name == "limit"
+        ));
+        assert_eq!(output.text(), "21\n");
+        assert_eq!(vm.global("limit"), Some(Value::Int(21)));
+    }
+
+    #[test]
+    fn compile_without_the_seeded_names_rejects_assigning_them() {
+        assert!(matches!(
+            crate::compile("k = 3;"),
+            Err(crate::CompileError::Semantic(
+                crate::semantic::SemanticError::UndeclaredAssignment { .. }
+            ))
+        ));
+    }
+
+    fn run_in(mode: ArithmeticMode, source: &str) -> (VirtualMachine, Result<(), RuntimeError>) {
+        let mut vm = machine(source, "");
+        vm.set_arithmetic_mode(mode);
+        let result = vm.run();
+        (vm, result)
+    }
+
+    #[test]
+    fn checked_mode_reports_overflow() {
+        let source = "fn inc(n: int) -> int { return n + 1; }; this n = inc(9223372036854775807);";
+        let (_, result) = run_in(ArithmeticMode::Checked, source);
+        assert!(matches!(
+            result,
+            Err(RuntimeError::ArithmeticOverflow { op: "ADD", .. })
+        ));
+        let (vm, result) = run_in(ArithmeticMode::Wrapping, source);
+        result.unwrap();
+        assert_eq!(vm.global("n"), Some(Value::Int(i64::MIN)));
+    }
+
+    #[test]
+    fn saturating_mode_clamps_at_the_bounds() {
+        let (vm, result) = run_in(
+            ArithmeticMode::Saturating,
+            "fn add(a: int, b: int) -> int { return a + b; }; \
+             fn mul(a: int, b: int) -> int { return a * b; }; \
+             fn narrow(x: i32) -> i32 { return x + 1; }; \
+             this max = add(9223372036854775807, 1); this min = add(0 - 9223372036854775807, 0 - 2); \
+             this big = mul(4611686018427387904, 4); this small = mul(4611686018427387904, 0 - 4); \
+             this narrowed = narrow(2147483647);",
+        );
+        result.unwrap();
+        assert_eq!(vm.global("max"), Some(Value::Int(i64::MAX)));
+        assert_eq!(vm.global("min"), Some(Value::Int(i64::MIN)));
+        assert_eq!(vm.global("big"), Some(Value::Int(i64::MAX)));
+        assert_eq!(vm.global("small"), Some(Value::Int(i64::MIN)));
+        assert_eq!(vm.global("narrowed"), Some(Value::Int(i32::MAX as i64)));
+    }
+
+    #[test]
+    fn wadd_wraps_whatever_the_mode() {
+        let source =
+            "fn inc(n: int) -> int { return wadd(n, 1); }; this n = inc(9223372036854775807);";
+        for mode in [ArithmeticMode::Checked, ArithmeticMode::Saturating] {
+            let (vm, result) = run_in(mode, source);
+            result.unwrap();
+            assert_eq!(vm.global("n"), Some(Value::Int(i64::MIN)));
+        }
+        // while i32 arithmetic follows the mode
+        let (_, result) = run_in(
+            ArithmeticMode::Checked,
+            "fn narrow(x: i32) -> i32 { return x + 1; }; this n = narrow(2147483647);",
+        );
+        assert!(matches!(
+            result,
+            Err(RuntimeError::ArithmeticOverflow { op: "WRAP32", .. })
+        ));
+    }
+
+    #[test]
+    fn checked_mode_leaves_results_in_range_alone() {
+        let (vm, result) = run_in(
+            ArithmeticMode::Checked,
+            "fn inc(n: int) -> int { return n + 1; }; this n = inc(9223372036854775806);",
+        );
+        result.unwrap();
+        assert_eq!(vm.global("n"), Some(Value::Int(i64::MAX)));
+    }
+
+    #[test]
+    fn the_globals_dump_is_the_same_on_every_run() {
+        let source = "this zeta = 1; this alpha = 2; this mid = zeta + alpha;";
+        let dump = |globals: &[(&str, i64)]| {
+            let mut vm = seeded(source, globals);
+            vm.run().unwrap();
+            vm.dump_globals()
+        };
+        // each map hashes with its own random seed, so their orders differ;
+        // the program's globals come first, then the seeded ones by name
+        let first = dump(&[("b", 1), ("a", 2), ("c", 3)]);
+        for _ in 0..10 {
+            assert_eq!(dump(&[("c", 3), ("a", 2), ("b", 1)]), first);
+        }
+        assert_eq!(first, "zeta = 1\nalpha = 2\nmid = 3\na = 2\nb = 1\nc = 3\n");
+    }
+
+    #[test]
+    fn machines_on_the_same_thread_share_stdin() {
+        let first = crate::compile("this n = 1;").unwrap();
+        let second = crate::compile("this n = 2;").unwrap();
+        let _first = VirtualMachine::new(first.opcodes, first.constants, first.symbols);
+        let mut second = VirtualMachine::new(second.opcodes, second.constants, second.symbols);
+        second.run().unwrap();
+        assert_eq!(second.global("n"), Some(Value::Int(2)));
+    }
+
+    fn array(elements: &[i64]) -> Option<Value> {
+        Some(Value::Array(
+            elements
+                .iter()
+                .map(|element| Value::Int(*element))
+                .collect(),
+        ))
+    }
+
+    #[test]
+    fn element_writes_change_only_the_variable_written() {
+        let mut vm = machine(
+            "this xs = [0; 3]; this copy = xs; this i = 0; \
+             while i != 3 { xs[i] = i * 10; i++; };",
+            "",
+        );
+        vm.run().unwrap();
+        assert_eq!(vm.global("xs"), array(&[0, 10, 20]));
+        assert_eq!(vm.global("copy"), array(&[0, 0, 0]));
+    }
+
+    #[test]
+    fn element_writes_in_a_function_change_its_own_copy() {
+        let mut vm = machine(
+            "this xs = [1, 2]; \
+             fn first(values: array) -> int { values[0] = 7; return values[0]; }; \
+             fn overwrite() -> int { xs[0] = 8; return xs[0]; }; \
+             this a = first(xs); this b = overwrite();",
+            "",
+        );
+        vm.run().unwrap();
+        assert_eq!(vm.global("a"), Some(Value::Int(7)));
+        assert_eq!(vm.global("b"), Some(Value::Int(8)));
+        assert_eq!(vm.global("xs"), array(&[1, 2]));
+    }
+
+    #[test]
+    fn writing_past_the_end_is_an_index_error() {
+        let mut vm = machine("this xs = [1, 2]; xs[2] = 3;", "");
+        assert!(matches!(
+            vm.run(),
+            Err(RuntimeError::IndexOutOfBounds {
+                index: 2,
+                length: 2,
+                ..
+            })
+        ));
+        assert_eq!(vm.global("xs"), array(&[1, 2]));
+    }
+
+    #[test]
+    fn writing_an_element_of_a_non_array_is_a_type_mismatch() {
+        let mut vm = machine("this n = 5; n[0] = 1;", "");
+        assert!(matches!(
+            vm.run(),
+            Err(RuntimeError::TypeMismatch {
+                op: "StoreIndex",
+                expected: "array",
+                found: "int",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn statements_after_an_early_return_do_not_run() {
+        let mut vm = machine(
+            "this x = 1; while x != 5 { if x == 2 { return; }; x = x + 1; }; x = 10;",
+            "",
+        );
+        vm.run().unwrap();
+        assert_eq!(vm.global("x"), Some(Value::Int(2)));
+        assert_eq!(vm.exit_code(), Some(0));
+
+        let mut vm = machine("this x = 1; return; x = 2;", "");
+        vm.run().unwrap();
+        assert_eq!(vm.global("x"), Some(Value::Int(1)));
+    }
+
+    // what a sample program prints, eprint included, then how it ended
+    fn golden_output(source: &str) -> String {
+        let bytecode = match crate::compile(source) {
+            Ok(bytecode) => bytecode,
+            Err(e) => return format!("error: {}\n", e),
+        };
+        let mut vm = VirtualMachine::with_input(
+            bytecode.opcodes,
+            bytecode.constants,
+            bytecode.symbols,
+            Box::new(Cursor::new("3\n")),
+        );
+        let output = Captured::default();
+        vm.set_output(Box::new(output.clone()));
+        vm.set_error_output(Box::new(output.clone()));
+        let result = vm.run();
+        let mut text = output.text();
+        if let Err(e) = result {
+            text.push_str(&format!("error: {}\n", crate::CompileError::Runtime(e)));
+        }
+        if let Some(code) = vm.exit_code() {
+            text.push_str(&format!("exit: {}\n", code));
+        }
+        text
+    }
+
+    // every sample against its output checked in under test_files/expected,
+    // UPDATE_EXPECTED=1 writes them again
+    #[test]
+    fn sample_programs_print_the_expected_output() {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/src/test_files");
+        for entry in fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if !path.is_file() {
+                continue;
+            }
+            let output = golden_output(&fs::read_to_string(&path).unwrap());
+            let expected = path
+                .parent()
+                .unwrap()
+                .join("expected")
+                .join(path.file_name().unwrap());
+            if std::env::var_os("UPDATE_EXPECTED").is_some() {
+                fs::write(&expected, &output).unwrap();
+                continue;
+            }
+            assert_eq!(
+                output,
+                fs::read_to_string(&expected).unwrap(),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn booleans_print_as_true_and_false() {
+        let mut vm = machine(
+            "this a = 1; print(1 == 1); print(a != 1); this same = (a == 1); print(same); print((a == 1) + 1);",
+            "",
+        );
+        let output = Captured::default();
+        vm.set_output(Box::new(output.clone()));
+        vm.run().unwrap();
+        assert_eq!(output.text(), "true\nfalse\ntrue\n2\n");
+    }
+}
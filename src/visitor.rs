@@ -0,0 +1,201 @@
+use crate::parser::{Block, Expression, Parameter, Program, Statement};
+
+// Read-only traversal of the AST. Every method walks into the children by default,
+// so an analysis only overrides the nodes it cares about and calls the matching
+// `walk_*` function to keep descending. The nodes are borrowed for `'ast`, as
+// long as the AST itself, so a visitor can keep references to what it finds.
+pub trait Visitor<'ast> {
+    fn visit_program(&mut self, program: &'ast Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_statement(&mut self, statement: &'ast Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_parameter(&mut self, _parameter: &'ast Parameter) {}
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_expression(&mut self, expression: &'ast Expression) {
+        walk_expression(self, expression);
+    }
+}
+
+pub fn walk_program<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, program: &'ast Program) {
+    match program {
+        Program::Statements(statements) => {
+            for statement in statements {
+                visitor.visit_statement(statement);
+            }
+        }
+    }
+}
+
+pub fn walk_statement<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    statement: &'ast Statement,
+) {
+    match statement {
+        Statement::VariableDeclaration { value, .. }
+        | Statement::Assignment { value, .. }
+        | Statement::TupleDeclaration { value, .. } => visitor.visit_expression(value),
+        Statement::IndexAssignment { index, value, .. } => {
+            visitor.visit_expression(index);
+            visitor.visit_expression(value);
+        }
+        Statement::FunctionDeclaration {
+            parameters, body, ..
+        } => {
+            for parameter in parameters {
+                visitor.visit_parameter(parameter);
+            }
+            visitor.visit_block(body);
+        }
+        Statement::FunctionCall(expression)
+        | Statement::Print(expression)
+        | Statement::Eprint(expression)
+        | Statement::Exit(expression)
+        | Statement::Panic {
+            message: expression,
+            ..
+        } => visitor.visit_expression(expression),
+        Statement::IfStatement {
+            condition,
+            then_block,
+            else_block,
+        } => {
+            visitor.visit_expression(condition);
+            visitor.visit_block(then_block);
+            if let Some(else_block) = else_block {
+                visitor.visit_block(else_block);
+            }
+        }
+        Statement::Assert { condition, .. } => visitor.visit_expression(condition),
+        Statement::Break { .. } => {}
+        Statement::Block(block) => visitor.visit_block(block),
+        Statement::WhileLoop {
+            condition, body, ..
+        } => {
+            visitor.visit_expression(condition);
+            visitor.visit_block(body);
+        }
+        Statement::DoWhileLoop {
+            body, condition, ..
+        } => {
+            visitor.visit_block(body);
+            visitor.visit_expression(condition);
+        }
+        Statement::Match {
+            value,
+            arms,
+            default,
+        } => {
+            visitor.visit_expression(value);
+            for arm in arms {
+                visitor.visit_block(&arm.body);
+            }
+            if let Some(default) = default {
+                visitor.visit_block(default);
+            }
+        }
+    }
+}
+
+pub fn walk_block<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, block: &'ast Block) {
+    for statement in &block.statements {
+        visitor.visit_statement(statement);
+    }
+    if let Some(return_expression) = &block.return_expression {
+        visitor.visit_expression(return_expression);
+    }
+}
+
+pub fn walk_expression<'ast, V: Visitor<'ast> + ?Sized>(
+    visitor: &mut V,
+    expression: &'ast Expression,
+) {
+    match expression {
+        Expression::Integer(_)
+        | Expression::String(_)
+        | Expression::Identifier { .. }
+        | Expression::Nil => {}
+        Expression::FunctionCall { arguments, .. }
+        | Expression::Tuple(arguments)
+        | Expression::Array(arguments) => {
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        }
+        Expression::ArithmeticExpression { left, right, .. }
+        | Expression::Comparison { left, right, .. }
+        | Expression::LogicalExpression { left, right, .. }
+        | Expression::Index {
+            array: left,
+            index: right,
+        }
+        | Expression::ArrayRepeat {
+            value: left,
+            count: right,
+        } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+        Expression::IfExpression {
+            condition,
+            then_block,
+            else_block,
+        } => {
+            visitor.visit_expression(condition);
+            visitor.visit_block(then_block);
+            visitor.visit_block(else_block);
+        }
+        Expression::Not(operand) => visitor.visit_expression(operand),
+        Expression::Sequence { steps, value } => {
+            for step in steps {
+                visitor.visit_statement(step);
+            }
+            visitor.visit_expression(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    // overrides only the expressions, the defaults reach them everywhere
+    #[derive(Default)]
+    struct CallCounter {
+        calls: usize,
+    }
+
+    impl Visitor<'_> for CallCounter {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let Expression::FunctionCall { .. } = expression {
+                self.calls += 1;
+            }
+            walk_expression(self, expression);
+        }
+    }
+
+    #[test]
+    fn counts_calls_wherever_they_are() {
+        let source = "fn f(n: int) -> int { return g(n) + 1; };
+            fn g(n: int) -> int { return n; };
+            f(1);
+            print(f(g(2)));
+            this x = if f(0) == 1 { g(1) } else { 0 };
+            while g(x) != 0 { x = (x = g(0), x); };";
+        let program = Parser::new(Lexer::tokenize(source).unwrap())
+            .parse()
+            .unwrap();
+        let mut counter = CallCounter::default();
+        counter.visit_program(&program);
+        assert_eq!(counter.calls, 8);
+    }
+}
@@ -0,0 +1,60 @@
+use crate::lexer::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WarningKind {
+    UnreachableCode,
+    Redeclaration,
+    UnusedFunction,
+    InfiniteLoop,
+}
+
+// Something suspicious that doesn't stop the program from compiling,
+// collected from every phase so the driver can report them together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+    pub span: Option<usize>, // token position the warning points at, when known
+    pub first_declaration: Option<usize>, // token position of the declaration a redeclaration repeats
+}
+
+impl Warning {
+    pub fn new(kind: WarningKind, message: String, span: Option<usize>) -> Self {
+        Self {
+            kind,
+            message,
+            span,
+            first_declaration: None,
+        }
+    }
+
+    pub fn with_first_declaration(mut self, position: usize) -> Self {
+        self.first_declaration = Some(position);
+        self
+    }
+
+    // the message with the token positions it refers to as lines and columns
+    pub fn located(&self, spans: &[Span]) -> String {
+        let mut located = self.message.clone();
+        if let Some(span) = self.span.and_then(|position| spans.get(position)) {
+            located.push_str(&format!(" (line {}, col {})", span.line, span.column));
+        }
+        if let Some(span) = self
+            .first_declaration
+            .and_then(|position| spans.get(position))
+        {
+            located.push_str(&format!(
+                ", first declared at line {}, col {}",
+                span.line, span.column
+            ));
+        }
+        located
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}